@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mach_ports::msg::{Buffer, MsgParser};
+
+fuzz_target!(|data: &[u8]| {
+    // Inputs shorter than a message header are rejected by `Buffer::from_bytes`.
+    if data.len() < 24 {
+        return;
+    }
+
+    // Port names in the input would refer to the fuzzer's own IPC space, so messages naming rights
+    // are rejected by `MsgParser::from_bytes` and never released.
+    let mut buffer = Buffer::from_bytes(data);
+    if let Ok(parser) = MsgParser::from_bytes(&mut buffer) {
+        drop(parser.parse_all());
+    }
+});
@@ -0,0 +1,266 @@
+//! Provides wrappers for looking up services registered with the bootstrap server (`launchd`).
+//!
+//! Services are looked up by name on the bootstrap port of the current task. [`PortRegistry`]
+//! additionally caches the looked up send rights for clients talking to multiple services. Servers
+//! retrieve the receive right of their service using [`check_in`].
+
+use crate::rights::{RecvRight, SendRight};
+use mach2::{
+    bootstrap::{bootstrap_check_in, bootstrap_look_up, bootstrap_port, BOOTSTRAP_MAX_NAME_LEN},
+    kern_return::{kern_return_t, KERN_INVALID_ARGUMENT, KERN_SUCCESS},
+    port::MACH_PORT_NULL,
+};
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fmt,
+    sync::{PoisonError, RwLock},
+};
+
+/// Converts a service name into a C string accepted by the bootstrap functions.
+fn service_name(name: &str) -> Result<CString, kern_return_t> {
+    // The name is copied into a `name_t` including the terminator.
+    if name.len() >= BOOTSTRAP_MAX_NAME_LEN as usize {
+        return Err(KERN_INVALID_ARGUMENT);
+    }
+
+    CString::new(name).map_err(|_| KERN_INVALID_ARGUMENT)
+}
+
+/// Looks up a service by name returning a send right to it.
+///
+/// # Errors
+/// Returns `KERN_INVALID_ARGUMENT` in case the name contains a NUL byte or is too long and the
+/// error code returned by `bootstrap_look_up` otherwise, e.g. `BOOTSTRAP_UNKNOWN_SERVICE` in case
+/// no service with the name is registered.
+///
+/// # Example
+/// ```
+/// # use mach_ports::bootstrap;
+/// # use mach2::bootstrap::BOOTSTRAP_UNKNOWN_SERVICE;
+/// let error = bootstrap::look_up("com.example.nonexistent").unwrap_err();
+/// assert_eq!(error, BOOTSTRAP_UNKNOWN_SERVICE as _);
+/// ```
+pub fn look_up(name: &str) -> Result<SendRight, kern_return_t> {
+    let name = service_name(name)?;
+    let mut port = MACH_PORT_NULL;
+    let result = unsafe { bootstrap_look_up(bootstrap_port, name.as_ptr(), &mut port) };
+
+    if result == KERN_SUCCESS {
+        Ok(SendRight::from_raw_name(port))
+    } else {
+        Err(result)
+    }
+}
+
+/// Checks in a service returning the receive right for it.
+///
+/// Services described by a `launchd` plist have their receive rights reserved by `launchd`, this
+/// retrieves the reserved right. Other names are registered with the bootstrap server creating a
+/// new port, clients may look the service up until the receive right is released.
+///
+/// # Errors
+/// Returns `KERN_INVALID_ARGUMENT` in case the name contains a NUL byte or is too long and the
+/// error code returned by `bootstrap_check_in` otherwise. `BOOTSTRAP_SERVICE_ACTIVE` is returned
+/// in case the service has already been checked in and `BOOTSTRAP_NOT_PRIVILEGED` in case the
+/// task isn't allowed to check in the service, e.g. because it belongs to another job.
+///
+/// # Example
+/// ```
+/// # use mach_ports::{bootstrap, msg::Buffer};
+/// let name = format!("com.example.check-in.{}", std::process::id());
+/// let service = bootstrap::check_in(&name).unwrap();
+///
+/// let mut buffer = Buffer::with_capacity(1024);
+/// bootstrap::look_up(&name).unwrap().ping(&mut buffer, 1337).unwrap();
+/// assert_eq!(service.recv(&mut buffer).unwrap().parse_all().header.id, 1337);
+/// ```
+pub fn check_in(name: &str) -> Result<RecvRight, kern_return_t> {
+    let name = service_name(name)?;
+    let mut port = MACH_PORT_NULL;
+    let result = unsafe { bootstrap_check_in(bootstrap_port, name.as_ptr(), &mut port) };
+
+    if result == KERN_SUCCESS {
+        Ok(RecvRight::from_raw_name(port))
+    } else {
+        Err(result)
+    }
+}
+
+/// The function used by a [`PortRegistry`] to resolve service names.
+type Resolver = dyn Fn(&str) -> Result<SendRight, kern_return_t> + Send + Sync;
+
+/// A thread-safe cache of send rights to services keyed by the service name.
+///
+/// Services are looked up using [`look_up`] on first use. A cached right is looked up again once
+/// its port dies, e.g. because the service was restarted.
+///
+/// # Example
+/// ```no_run
+/// # use mach_ports::bootstrap::PortRegistry;
+/// let registry = PortRegistry::new();
+/// let service = registry.get("com.example.service").unwrap();
+/// ```
+pub struct PortRegistry {
+    services: RwLock<HashMap<String, SendRight>>,
+    resolver: Box<Resolver>,
+}
+
+impl PortRegistry {
+    /// Creates an empty registry resolving names using [`look_up`].
+    pub fn new() -> Self {
+        Self::with_resolver(look_up)
+    }
+
+    /// Creates an empty registry resolving names using the specified function instead of
+    /// [`look_up`].
+    pub fn with_resolver<F>(resolver: F) -> Self
+    where
+        F: Fn(&str) -> Result<SendRight, kern_return_t> + Send + Sync + 'static,
+    {
+        Self {
+            services: RwLock::new(HashMap::new()),
+            resolver: Box::new(resolver),
+        }
+    }
+
+    /// Returns a send right to the service with the specified name.
+    ///
+    /// The cached right is cloned in case its port is still alive, otherwise the name is resolved
+    /// again and the new right replaces the cached one. Resolving is serialized, so concurrent
+    /// callers wait for a single lookup of a name.
+    ///
+    /// # Errors
+    /// Returns the error returned by the resolver, see [`look_up`]. The cached right isn't
+    /// removed in this case.
+    pub fn get(&self, name: &str) -> Result<SendRight, kern_return_t> {
+        // The map is never left in an inconsistent state, so poisoning is ignored.
+        let services = self.services.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(right) = services.get(name).filter(|right| right.is_alive()) {
+            return Ok(right.clone());
+        }
+        drop(services);
+
+        let mut services = self
+            .services
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Another thread may have resolved the name while the lock was released.
+        if let Some(right) = services.get(name).filter(|right| right.is_alive()) {
+            return Ok(right.clone());
+        }
+
+        let right = (self.resolver)(name)?;
+        services.insert(name.to_owned(), right.clone());
+
+        Ok(right)
+    }
+
+    /// Removes the cached right for the service with the specified name.
+    pub fn remove(&self, name: &str) -> Option<SendRight> {
+        self.services
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(name)
+    }
+}
+
+impl Default for PortRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for PortRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PortRegistry")
+            .field("services", &self.services)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::AsRawName;
+    use mach2::bootstrap::{BOOTSTRAP_SERVICE_ACTIVE, BOOTSTRAP_UNKNOWN_SERVICE};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    #[test]
+    fn test_look_up_errors() {
+        assert_eq!(
+            look_up("com.example.nonexistent").unwrap_err(),
+            BOOTSTRAP_UNKNOWN_SERVICE as kern_return_t
+        );
+        assert_eq!(
+            look_up("com.example\0svc").unwrap_err(),
+            KERN_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            look_up(&"a".repeat(128)).unwrap_err(),
+            KERN_INVALID_ARGUMENT
+        );
+    }
+
+    #[test]
+    fn test_check_in() {
+        let name = format!("com.example.test-check-in.{}", std::process::id());
+        let service = check_in(&name).unwrap();
+        assert_eq!(
+            look_up(&name).unwrap().as_raw_name(),
+            service.make_send().as_raw_name()
+        );
+        assert_eq!(
+            check_in(&name).unwrap_err(),
+            BOOTSTRAP_SERVICE_ACTIVE as kern_return_t
+        );
+
+        assert_eq!(
+            check_in("com.example\0svc").unwrap_err(),
+            KERN_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            check_in(&"a".repeat(128)).unwrap_err(),
+            KERN_INVALID_ARGUMENT
+        );
+    }
+
+    #[test]
+    fn test_registry_refresh() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let port = Arc::new(Mutex::new(RecvRight::alloc()));
+
+        let registry = {
+            let (lookups, port) = (lookups.clone(), port.clone());
+            PortRegistry::with_resolver(move |name| {
+                assert_eq!(name, "com.example.svc");
+                lookups.fetch_add(1, Ordering::Relaxed);
+                Ok(port.lock().unwrap().make_send())
+            })
+        };
+
+        let first = registry.get("com.example.svc").unwrap();
+        let cached = registry.get("com.example.svc").unwrap();
+        assert_eq!(first.as_raw_name(), cached.as_raw_name());
+        assert_eq!(lookups.load(Ordering::Relaxed), 1);
+
+        // Replacing the receive right kills the cached port.
+        drop(std::mem::replace(
+            &mut *port.lock().unwrap(),
+            RecvRight::alloc(),
+        ));
+        assert!(!first.is_alive());
+
+        let refreshed = registry.get("com.example.svc").unwrap();
+        assert!(refreshed.is_alive());
+        assert_eq!(refreshed.as_raw_name(), port.lock().unwrap().as_raw_name());
+        assert_eq!(lookups.load(Ordering::Relaxed), 2);
+
+        assert!(registry.remove("com.example.svc").is_some());
+        registry.get("com.example.svc").unwrap();
+        assert_eq!(lookups.load(Ordering::Relaxed), 3);
+    }
+}
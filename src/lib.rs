@@ -5,6 +5,11 @@
 #![warn(missing_debug_implementations)]
 #![warn(missing_copy_implementations)]
 
+pub mod bootstrap;
 pub mod msg;
+pub mod notify;
 pub mod rights;
+pub mod task;
+#[cfg(test)]
+mod test_util;
 pub mod traits;
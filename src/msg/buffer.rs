@@ -1,7 +1,9 @@
 //! Provides the [`Buffer`] structure used to represent a buffer for Mach messages.
 
-use crate::msg::MachMsgBits;
-use mach2::message::{mach_msg_header_t, mach_msg_size_t};
+use crate::msg::{parser, MachMsgBits};
+use mach2::message::{
+    mach_msg_header_t, mach_msg_size_t, mach_msg_trailer_t, mach_msg_type_descriptor_t,
+};
 use std::{
     alloc::{self, Layout},
     cmp, mem,
@@ -16,6 +18,44 @@ pub(crate) struct MsgData<T: ?Sized> {
     pub body: T,
 }
 
+/// Describes what kind of message a [`Buffer`] currently holds.
+///
+/// The state is only tracked in debug builds and is meant to help catching bugs like parsing a
+/// buffer that has never been received into. Release builds always report
+/// [`BufferState::Untracked`].
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BufferState {
+    /// The buffer doesn't contain a message.
+    Empty,
+    /// A message is being built in the buffer or has been built and sent from it.
+    Building,
+    /// The buffer contains a message received from the kernel.
+    Received,
+    /// The state isn't tracked since this is a release build.
+    Untracked,
+}
+
+/// Specifies how a [`Buffer`] grows when more capacity is required.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GrowthPolicy {
+    /// Grow exactly to the required capacity.
+    Exact,
+    /// Double the capacity or grow to the required capacity if that's not enough.
+    Double,
+    /// Multiply the capacity by a factor or grow to the required capacity if that's not enough.
+    ///
+    /// Factors not larger than `1.0` behave like [`GrowthPolicy::Exact`].
+    Factor(f32),
+}
+
+impl Default for GrowthPolicy {
+    /// Returns the default policy growing the capacity by a half.
+    fn default() -> Self {
+        GrowthPolicy::Factor(1.5)
+    }
+}
+
 /// A reusable buffer for Mach messages.
 ///
 /// This structure isn't designed to be aware of the Mach message format and exists to allow reusing
@@ -25,6 +65,9 @@ pub struct Buffer {
     ptr: NonNull<MsgData<[u8; 0]>>,
     len: mach_msg_size_t,
     capacity: mach_msg_size_t,
+    growth: GrowthPolicy,
+    #[cfg(debug_assertions)]
+    state: BufferState,
 }
 
 impl Buffer {
@@ -38,6 +81,23 @@ impl Buffer {
     /// Creates a new message buffer with the specified inline capacity. The capacity should not
     /// include the header's size as it is added automatically.
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_growth(capacity, GrowthPolicy::default())
+    }
+
+    /// Creates a new message buffer with the specified inline capacity and the policy used to grow
+    /// the buffer when more capacity is required.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::msg::{Buffer, Builder, GrowthPolicy};
+    /// let mut buffer = Buffer::with_capacity_and_growth(0, GrowthPolicy::Exact);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_inline_data(b"test");
+    /// builder.discard();
+    ///
+    /// assert_eq!(buffer.capacity(), 4);
+    /// ```
+    pub fn with_capacity_and_growth(capacity: usize, growth: GrowthPolicy) -> Self {
         let capacity = capacity.try_into().unwrap();
         let layout = Self::layout_for_capacity(capacity);
         let ptr = unsafe {
@@ -54,14 +114,91 @@ impl Buffer {
             ptr,
             len: 0,
             capacity,
+            growth,
+            #[cfg(debug_assertions)]
+            state: BufferState::Empty,
         }
     }
 
+    /// Creates a buffer containing a copy of a raw Mach message.
+    ///
+    /// The message isn't validated in any way. Use [`MsgParser::from_bytes`] to parse it.
+    ///
+    /// # Panics
+    /// This function will panic in case the slice is shorter than a message header.
+    ///
+    /// [`MsgParser::from_bytes`]: crate::msg::MsgParser::from_bytes
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        // Short slices are rejected by `set_contents`.
+        let capacity = bytes
+            .len()
+            .saturating_sub(mem::size_of::<mach_msg_header_t>());
+        let mut buffer = Self::with_capacity(capacity);
+        buffer.set_contents(bytes);
+
+        buffer
+    }
+
+    /// Replaces the contents of the buffer with a copy of a raw Mach message growing the buffer if
+    /// needed.
+    ///
+    /// # Panics
+    /// This function will panic in case the slice is shorter than a message header.
+    pub(crate) fn set_contents(&mut self, bytes: &[u8]) {
+        let (header, body) = bytes.split_at(mem::size_of::<mach_msg_header_t>());
+
+        self.clear();
+        self.reserve(body.len().try_into().unwrap());
+
+        // SAFETY: The slice is long enough and the header contains only integer fields.
+        *self.header_mut() =
+            unsafe { ptr::read_unaligned(header.as_ptr() as *const mach_msg_header_t) };
+        self.append(body);
+    }
+
+    /// Returns the kind of message the buffer currently holds.
+    ///
+    /// The state is only tracked in debug builds, release builds always return
+    /// [`BufferState::Untracked`].
+    pub fn debug_state(&self) -> BufferState {
+        #[cfg(debug_assertions)]
+        return self.state;
+
+        #[cfg(not(debug_assertions))]
+        BufferState::Untracked
+    }
+
+    #[cfg(debug_assertions)]
+    #[inline(always)]
+    pub(crate) fn set_debug_state(&mut self, state: BufferState) {
+        self.state = state;
+    }
+
+    /// Empties the buffer and resets the message header.
+    ///
+    /// Port rights and out-of-line memory referenced by the message aren't released, the buffer
+    /// should only be cleared after the message has been either sent or consumed by a parser.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        *self.header_mut() = Default::default();
+
+        #[cfg(debug_assertions)]
+        self.set_debug_state(BufferState::Empty);
+    }
+
     /// Returns the capacity of the inline data that fits into the buffer.
     pub fn capacity(&self) -> usize {
         self.capacity as usize
     }
 
+    /// Returns the number of bytes that may be added to the contents of the buffer before it has
+    /// to grow.
+    ///
+    /// After a message is received, this is the spare capacity the buffer had for the message body.
+    pub fn headroom(&self) -> usize {
+        (self.capacity - self.len) as usize
+    }
+
     fn data(&self) -> &MsgData<[u8]> {
         let len = self.len as usize;
         let data = self.ptr.as_ptr() as *const u8;
@@ -82,6 +219,18 @@ impl Buffer {
         &mut self.data_mut().header
     }
 
+    /// Returns a pointer to the message that is valid for writes of the header and the whole inline
+    /// capacity regardless of the current length, to be used as the receive buffer of `mach_msg`.
+    pub(crate) fn as_recv_ptr(&mut self) -> *mut mach_msg_header_t {
+        self.ptr.as_ptr() as *mut mach_msg_header_t
+    }
+
+    /// Returns the receive limit to pass to `mach_msg` along with [`Buffer::as_recv_ptr`].
+    pub(crate) fn recv_size(&self) -> mach_msg_size_t {
+        // Saturating keeps the limit within the allocation for capacities close to the maximum.
+        (mem::size_of::<mach_msg_header_t>() as mach_msg_size_t).saturating_add(self.capacity)
+    }
+
     pub(crate) fn body(&self) -> &[u8] {
         &self.data().body
     }
@@ -106,6 +255,63 @@ impl Buffer {
         }
     }
 
+    /// Returns the offset of the inline data from the end of the header.
+    ///
+    /// For complex messages this is the combined size of the descriptor count and the descriptors,
+    /// for simple messages it's zero.
+    ///
+    /// # Panics
+    /// This function will panic in case the descriptors don't fit into the buffer or one of them
+    /// has an unknown type.
+    pub fn inline_data_offset(&self) -> usize {
+        const SIZE_SIZE: usize = mem::size_of::<mach_msg_size_t>();
+        const TYPE_DESC_SIZE: usize = mem::size_of::<mach_msg_type_descriptor_t>();
+
+        if !self.header_bits().complex() {
+            return 0;
+        }
+
+        let body = self.body();
+        let mut offset = SIZE_SIZE;
+        for _ in 0..self.descriptors_count() {
+            let desc = &body[offset..offset + TYPE_DESC_SIZE];
+            let type_ = desc[mem::offset_of!(mach_msg_type_descriptor_t, type_)];
+
+            offset += parser::size_for_desc_type(type_.into());
+        }
+
+        assert!(offset <= body.len());
+
+        offset
+    }
+
+    /// Returns the bytes of the trailer following a received message.
+    ///
+    /// The returned slice is truncated in case the trailer doesn't fit into the buffer.
+    ///
+    /// # Safety
+    /// The buffer must contain a message received from the kernel since otherwise the memory after
+    /// the message may have never been initialized.
+    pub(crate) unsafe fn trailer_bytes(&self) -> &[u8] {
+        const TRAILER_HDR_SIZE: usize = mem::size_of::<mach_msg_trailer_t>();
+
+        let total_size = mem::size_of::<mach_msg_header_t>() + self.capacity as usize;
+        let offset = self.header().msgh_size as usize;
+
+        if offset > total_size || total_size - offset < TRAILER_HDR_SIZE {
+            return &[];
+        }
+
+        let ptr = (self.ptr.as_ptr() as *const u8).add(offset);
+        let trailer_header = ptr::read_unaligned(ptr as *const mach_msg_trailer_t);
+        let len = cmp::min(
+            trailer_header.msgh_trailer_size as usize,
+            total_size - offset,
+        );
+
+        slice::from_raw_parts(ptr, len)
+    }
+
     /// Returns the contents of the buffer as a byte slice.
     pub fn as_slice(&self) -> &[u8] {
         let len = self.body().len() + mem::size_of::<mach_msg_header_t>();
@@ -119,9 +325,15 @@ impl Buffer {
         let old_capacity = self.capacity;
 
         if requested_capacity > old_capacity {
-            let new_capacity = cmp::max(old_capacity / 2, additional)
-                .checked_add(old_capacity)
-                .unwrap();
+            let growth = match self.growth {
+                GrowthPolicy::Exact => 0,
+                GrowthPolicy::Double => old_capacity,
+                // The cast saturates, negative and NaN values are converted to zero.
+                GrowthPolicy::Factor(factor) => (old_capacity as f32 * (factor - 1.0)) as u32,
+            };
+
+            // Saturating is fine since the requested capacity is known not to overflow.
+            let new_capacity = cmp::max(growth, additional).saturating_add(old_capacity);
             let old_layout = Self::layout_for_capacity(old_capacity);
             let new_layout = Self::layout_for_capacity(new_capacity);
 
@@ -140,7 +352,7 @@ impl Buffer {
         let appended_len: mach_msg_size_t = bytes.len().try_into().unwrap();
         let space_left = self.capacity - self.len;
         if space_left < appended_len {
-            self.reserve(appended_len - space_left);
+            self.reserve(appended_len);
         }
 
         // SAFETY: The buffer must have been allocated by that point. Since before the call the
@@ -155,6 +367,16 @@ impl Buffer {
         self.len += appended_len;
     }
 
+    /// Removes `len` bytes at the specified offset from the inline part of the buffer shifting the
+    /// following contents.
+    pub(crate) fn remove(&mut self, at: mach_msg_size_t, len: mach_msg_size_t) {
+        let end = at.checked_add(len).unwrap();
+        assert!(end <= self.len);
+
+        self.body_mut().copy_within(end as usize.., at as usize);
+        self.len -= len;
+    }
+
     /// Inserts bytes at the specified offset into the inline part of the buffer (that means the
     /// offset is calculated from the end of the header).
     pub(crate) fn insert(&mut self, at: mach_msg_size_t, bytes: &[u8]) {
@@ -162,9 +384,8 @@ impl Buffer {
 
         let inserted_len: mach_msg_size_t = bytes.len().try_into().unwrap();
         let space_left = self.capacity - self.len;
-        let final_len = inserted_len.checked_add(at).unwrap();
-        if space_left < final_len {
-            self.reserve(final_len - space_left);
+        if space_left < inserted_len {
+            self.reserve(inserted_len);
         }
 
         let body_ptr = self.body_mut().as_mut_ptr();
@@ -188,6 +409,29 @@ impl Buffer {
         self.len += inserted_len;
     }
 
+    /// Inserts bytes at an offset from the end of the header shifting the following contents.
+    ///
+    /// This allows modifying a received message before forwarding it using
+    /// [`Builder::from_contents`](crate::msg::Builder::from_contents). The offset is calculated
+    /// from the start of the message body, which begins with the descriptors in complex messages.
+    /// Splicing data into the descriptors corrupts the message, so it's the caller's
+    /// responsibility to splice data at or after [`Buffer::inline_data_offset`]:
+    ///
+    /// ```
+    /// # use mach_ports::msg::Buffer;
+    /// # let mut buffer = Buffer::from_bytes(&[0; 24]);
+    /// // Prepend data to the inline data of the message.
+    /// let at = buffer.inline_data_offset();
+    /// buffer.splice(at, b"data");
+    /// ```
+    ///
+    /// # Panics
+    /// This function will panic in case the offset is past the end of the data or the size of the
+    /// buffer would exceed `mach_msg_size_t::MAX`.
+    pub fn splice(&mut self, at: usize, other: &[u8]) {
+        self.insert(at.try_into().unwrap(), other);
+    }
+
     /// Sets a new length for the buffer without performing any checks.
     pub(crate) unsafe fn set_len(&mut self, new_len: mach_msg_size_t) {
         assert!(new_len <= self.capacity);
@@ -196,6 +440,19 @@ impl Buffer {
     }
 }
 
+/// Buffers are compared by their contents, see [`Buffer::as_slice`].
+///
+/// Port names in the messages are compared as raw values, so equal buffers may carry different
+/// rights in case the names were reused, and messages carrying the same rights are only equal in
+/// case the dispositions match as well.
+impl PartialEq for Buffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for Buffer {}
+
 impl Drop for Buffer {
     fn drop(&mut self) {
         unsafe {
@@ -209,3 +466,36 @@ impl Drop for Buffer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_insert_grow() {
+        // The appended data doesn't fit into the spare capacity, but is smaller than the capacity.
+        let mut buffer = Buffer::with_capacity_and_growth(16, GrowthPolicy::Exact);
+        buffer.append(&[1; 12]);
+        buffer.append(&[2; 12]);
+        assert_eq!(buffer.capacity(), 28);
+        assert_eq!(buffer.body(), [[1; 12], [2; 12]].concat());
+
+        buffer.insert(4, &[3; 8]);
+        assert_eq!(buffer.capacity(), 36);
+        assert_eq!(
+            buffer.body(),
+            [&[1; 4][..], &[3; 8], &[1; 8], &[2; 12]].concat()
+        );
+
+        // The data is larger than the spare capacity of an empty buffer.
+        let mut buffer = Buffer::with_capacity_and_growth(16, GrowthPolicy::Exact);
+        buffer.append(&[1; 20]);
+        assert_eq!(buffer.capacity(), 36);
+        assert_eq!(buffer.body(), [1; 20]);
+
+        let mut buffer = Buffer::with_capacity_and_growth(16, GrowthPolicy::Exact);
+        buffer.insert(0, &[1; 20]);
+        assert_eq!(buffer.capacity(), 36);
+        assert_eq!(buffer.body(), [1; 20]);
+    }
+}
@@ -3,9 +3,10 @@
 use crate::{
     msg::{
         buffer::Buffer,
-        ool::OolBuf,
-        parser::{self, TransmutedMsgDesc},
-        MachMsgBits, MsgId,
+        ndr,
+        ool::{OolBuf, OolVec},
+        parser::{self, GuardedPortDescriptor, TransmutedMsgDesc},
+        BuildError, MachMsgBits, MsgId, SendError, MACH_MSG_SIZE_MAX,
     },
     rights::*,
     traits::{AsRawName, BaseRight, BaseSendRight, IntoRawName},
@@ -14,7 +15,12 @@ use mach2::{
     message::*,
     port::{mach_port_t, MACH_PORT_NULL},
 };
-use std::{marker::PhantomData, mem, ptr::NonNull, slice};
+use std::{
+    marker::PhantomData,
+    mem,
+    ptr::{self, NonNull},
+    slice,
+};
 
 /// Converts any sized type into a byte slice.
 ///
@@ -27,6 +33,34 @@ unsafe fn anything_as_bytes<T: Sized>(anything: &T) -> &[u8] {
     slice::from_raw_parts(data, len)
 }
 
+/// The `MACH_MSG_OVERWRITE` copy option of scatter list descriptors, which isn't exported by
+/// `mach2`.
+const MACH_MSG_OVERWRITE: mach_msg_copy_options_t = 3;
+
+/// Replaces the contents of the buffer with a scatter list of out-of-line overwrite descriptors,
+/// one for each region in order.
+pub(crate) fn set_scatter_list(buffer: &mut Buffer, regions: &[OolVec]) {
+    buffer.clear();
+    buffer.header_mut().msgh_bits = MACH_MSGH_BITS_COMPLEX;
+
+    let count: mach_msg_size_t = regions.len().try_into().unwrap();
+    buffer.append(&count.to_ne_bytes());
+    for region in regions {
+        let desc = mach_msg_ool_descriptor_t::new(
+            region.as_ptr().as_ptr().cast(),
+            false,
+            MACH_MSG_OVERWRITE,
+            region.capacity().try_into().unwrap_or(mach_msg_size_t::MAX),
+        );
+
+        // SAFETY: The descriptor consists of integer fields and contains no padding.
+        buffer.append(unsafe { anything_as_bytes(&desc) });
+    }
+
+    let size = mem::size_of::<mach_msg_header_t>() + buffer.body().len();
+    buffer.header_mut().msgh_size = size.try_into().unwrap();
+}
+
 fn drop_header(header: &mut mach_msg_header_t) {
     let bits = MachMsgBits::from_bits(header.msgh_bits);
 
@@ -44,17 +78,86 @@ fn drop_header(header: &mut mach_msg_header_t) {
     if header.msgh_voucher_port != MACH_PORT_NULL {
         let raw_name = mem::replace(&mut header.msgh_voucher_port, MACH_PORT_NULL);
 
-        match bits.local() {
+        match bits.voucher() {
             MACH_MSG_TYPE_MOVE_SEND => drop(SendRight::from_raw_name(raw_name)),
             MACH_MSG_TYPE_COPY_SEND => (),
             _ => unreachable!("unexpected voucher port bits"),
         }
     }
 
-    assert_eq!(header.msgh_remote_port, MACH_PORT_NULL);
-    assert_eq!(bits.remote(), 0);
+    // The destination may only be set using `Builder::rewrite_destination`, which doesn't move
+    // any references into the message.
+    if header.msgh_remote_port != MACH_PORT_NULL {
+        assert_eq!(bits.remote(), MACH_MSG_TYPE_COPY_SEND);
+        header.msgh_remote_port = MACH_PORT_NULL;
+    }
+
+    // Only the port bits are reset so that the importance bits of a forwarded message are
+    // preserved.
+    header.msgh_bits = bits.set_remote(0).set_local(0).set_voucher(0).0;
+}
+
+/// Releases the port right references and the out-of-line buffers moved into the descriptors of
+/// a message built by a [`Builder`].
+fn release_descriptors(buffer: &Buffer) {
+    let mut count = buffer.descriptors_count();
+    let mut offset = mem::size_of::<mach_msg_size_t>() as mach_msg_size_t;
+    while count > 0 {
+        use TransmutedMsgDesc::*;
+
+        match parser::next_desc_impl(buffer, &mut offset, false) {
+            Port(desc) => release_port(desc.name, desc.disposition as mach_msg_type_name_t),
+            Ool(desc) | OolVolatile(desc) => {
+                // Only deallocate the buffer in case it was meant to be deallocated.
+                if desc.deallocate != 0 {
+                    let ptr = NonNull::new(desc.address as *mut u8).unwrap();
+                    let length = desc.size.try_into().unwrap();
+
+                    // SAFETY: Since the message was produced by the builder, the address and
+                    // length should be correct.
+                    drop(unsafe { OolBuf::from_raw_parts(ptr, length) })
+                }
+            }
+            OolPorts(desc) => {
+                // SAFETY: The descriptor may be unaligned, see `parser::next_desc_impl`.
+                let address = unsafe { ptr::read_unaligned(ptr::addr_of!(desc.address)) };
+                let disposition = desc.disposition as mach_msg_type_name_t;
+                let length = desc.count as usize * mem::size_of::<mach_port_t>();
+
+                if let Some(ptr) = NonNull::new(address as *mut u8).filter(|_| length > 0) {
+                    // SAFETY: Since the message was produced by the builder, the address and
+                    // the count should be correct.
+                    let names = unsafe {
+                        slice::from_raw_parts(
+                            ptr.as_ptr() as *const mach_port_t,
+                            desc.count as usize,
+                        )
+                    };
+                    for &name in names {
+                        release_port(name, disposition);
+                    }
 
-    header.msgh_bits = MachMsgBits::new(bits.complex(), 0, 0, 0).0;
+                    if desc.deallocate != 0 {
+                        // SAFETY: See above.
+                        drop(unsafe { OolBuf::from_raw_parts(ptr, length) })
+                    }
+                }
+            }
+            GuardedPort(desc) => {
+                let (name, guard) = (desc.name, desc.context);
+
+                assert_eq!(
+                    desc.disposition as mach_msg_type_name_t,
+                    MACH_MSG_TYPE_MOVE_RECEIVE
+                );
+
+                // SAFETY: Only guarded receive rights are appended as guarded descriptors.
+                drop(unsafe { GuardedRecvRight::from_raw_parts(name, guard) })
+            }
+        }
+
+        count -= 1;
+    }
 }
 
 /// The type of memory copy operation requested from the kernel.
@@ -94,6 +197,40 @@ pub enum CopyKind {
 /// * `(append|set)_moved_*` functions consume any of the Mach port name wrappers. The reference
 /// count on the corresponding rights aren't changed, but dropping the [`Builder`] or sending the
 /// message will cause the sender to pass one reference on the right to the receiver.
+///
+/// # Disposition checks
+/// The bounds of the functions only accept the wrappers for which the corresponding disposition
+/// is valid, so passing an incompatible right is a compile-time error rather than a kernel error
+/// on send. For example, a receive right can't be set as a copied reply port:
+///
+/// ```compile_fail,E0271
+/// # use mach_ports::{msg::{Builder, Buffer}, rights::RecvRight};
+/// # let recv_right = RecvRight::alloc();
+/// # let mut buffer = Buffer::with_capacity(1024);
+/// let mut builder = Builder::new(&mut buffer);
+/// builder.set_copied_reply_port(&recv_right);
+/// ```
+///
+/// Nor can it be moved into the message as a reply port:
+///
+/// ```compile_fail,E0277
+/// # use mach_ports::{msg::{Builder, Buffer}, rights::RecvRight};
+/// # let recv_right = RecvRight::alloc();
+/// # let mut buffer = Buffer::with_capacity(1024);
+/// let mut builder = Builder::new(&mut buffer);
+/// builder.set_moved_reply_port(recv_right);
+/// ```
+///
+/// Similarly, a reply port right can only be made from a receive right:
+///
+/// ```compile_fail,E0271
+/// # use mach_ports::{msg::{Builder, Buffer}, rights::RecvRight};
+/// # let recv_right = RecvRight::alloc();
+/// # let send_right = recv_right.make_send();
+/// # let mut buffer = Buffer::with_capacity(1024);
+/// let mut builder = Builder::new(&mut buffer);
+/// builder.set_made_reply_port(&send_right, true);
+/// ```
 #[derive(Debug)]
 pub struct Builder<'a, 'buffer> {
     buffer: &'buffer mut Buffer,
@@ -103,10 +240,37 @@ pub struct Builder<'a, 'buffer> {
 
 impl<'a, 'buffer> Builder<'a, 'buffer> {
     /// Creates a new message builder.
+    ///
+    /// The buffer is cleared first, so a buffer still holding a previously received or built
+    /// message may be reused directly. Use [`Builder::from_contents`] to continue building the
+    /// message the buffer contains.
     pub fn new(buffer: &'buffer mut Buffer) -> Self {
+        buffer.clear();
+
+        // SAFETY: The buffer is empty.
+        unsafe { Self::from_contents(buffer) }
+    }
+
+    /// Creates a message builder that keeps the current contents of the buffer, e.g. a received
+    /// message modified using [`Buffer::splice`] to be forwarded.
+    ///
+    /// # Safety
+    /// The port right references and the out-of-line memory named in the message must be owned by
+    /// the message since the builder releases them in case it's dropped. This is not the case for
+    /// a received message once it has been parsed, unless it carries no rights or memory.
+    ///
+    /// # Panics
+    /// This function will panic in case the descriptors don't fit into the buffer or one of them
+    /// has an unknown type, see [`Buffer::inline_data_offset`].
+    pub unsafe fn from_contents(buffer: &'buffer mut Buffer) -> Self {
+        let inline_data_off = buffer.inline_data_offset().try_into().unwrap();
+
+        #[cfg(debug_assertions)]
+        buffer.set_debug_state(crate::msg::BufferState::Building);
+
         Self {
             buffer,
-            inline_data_off: 0,
+            inline_data_off,
             _marker: Default::default(),
         }
     }
@@ -165,6 +329,32 @@ impl<'a, 'buffer> Builder<'a, 'buffer> {
         header.msgh_bits = bits.set_local(local_bits).0;
     }
 
+    /// Sets up the message as a request expecting a reply: sets the reply port right to be made from
+    /// a receive right and appends a correlation ID to the inline data.
+    ///
+    /// The correlation ID is appended as a `u64` in the native byte order. Together with the reply
+    /// right this allows the client to match a reply with the request it was sent for.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Builder, Buffer}, rights::RecvRight};
+    /// # let reply_right = RecvRight::alloc();
+    /// # let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.set_request(&reply_right, true, 42);
+    ///
+    /// // This is equivalent to:
+    /// // builder.set_made_reply_port(&reply_right, true);
+    /// // builder.append_inline_data(&42u64.to_ne_bytes());
+    /// ```
+    pub fn set_request<T>(&mut self, reply: &'a T, once: bool, correlation_id: u64)
+    where
+        T: AsRawName<Base = RecvRight>,
+    {
+        self.set_made_reply_port(reply, once);
+        self.append_inline_data(&correlation_id.to_ne_bytes());
+    }
+
     /// Sets the reply port right to be copied from a send right when the message is sent. The
     /// sender's reference on the send right isn't dropped.
     pub fn set_copied_reply_port<T: AsRawName<Base = SendRight>>(&mut self, right: &'a T) {
@@ -192,16 +382,103 @@ impl<'a, 'buffer> Builder<'a, 'buffer> {
         let local_bits = T::Base::MSG_TYPE;
         header.msgh_local_port = reply_port.into_raw_name();
 
-        let new_bits = MachMsgBits::new(bits.complex(), 0, local_bits, bits.voucher());
-        header.msgh_bits = new_bits.0;
+        header.msgh_bits = bits.set_remote(0).set_local(local_bits).0;
+    }
+
+    fn release_voucher(&mut self) {
+        let header = self.buffer.header_mut();
+        let raw_old_name = header.msgh_voucher_port;
+        let bits = MachMsgBits::from_bits(header.msgh_bits);
+
+        if raw_old_name != MACH_PORT_NULL && bits.voucher() == MACH_MSG_TYPE_MOVE_SEND {
+            drop(SendRight::from_raw_name(raw_old_name));
+        }
+
+        header.msgh_voucher_port = MACH_PORT_NULL;
+        header.msgh_bits = bits.set_voucher(0).0;
+    }
+
+    /// Sets the voucher to be copied from a send right when the message is sent. The sender's
+    /// reference on the send right isn't dropped.
+    ///
+    /// The right must name a voucher port, otherwise sending the message fails with an error of
+    /// the [`InvalidVoucher`](crate::msg::SendErrorKind::InvalidVoucher) kind.
+    pub fn set_copied_voucher<T: AsRawName<Base = SendRight>>(&mut self, voucher: &'a T) {
+        self.release_voucher();
+
+        let header = self.buffer.header_mut();
+        let bits = MachMsgBits::from_bits(header.msgh_bits);
+
+        header.msgh_voucher_port = voucher.as_raw_name();
+        header.msgh_bits = bits.set_voucher(MACH_MSG_TYPE_COPY_SEND).0;
+    }
+
+    /// Consumes a send right to a voucher and sets it to be transferred to the receiver when the
+    /// message is sent.
+    ///
+    /// The right must name a voucher port, otherwise sending the message fails with an error of
+    /// the [`InvalidVoucher`](crate::msg::SendErrorKind::InvalidVoucher) kind.
+    pub fn set_moved_voucher<T: IntoRawName<Base = SendRight>>(&mut self, voucher: T) {
+        self.release_voucher();
+
+        let header = self.buffer.header_mut();
+        let bits = MachMsgBits::from_bits(header.msgh_bits);
+
+        header.msgh_voucher_port = voucher.into_raw_name();
+        header.msgh_bits = bits.set_voucher(MACH_MSG_TYPE_MOVE_SEND).0;
+    }
+
+    /// Sets the reply port right to be made from a receive right and the voucher to be copied
+    /// from a send right when the message is sent.
+    ///
+    /// This is equivalent to calling [`Builder::set_made_reply_port`] and
+    /// [`Builder::set_copied_voucher`], but updates the header bits once. Previously set reply
+    /// port and voucher rights are released.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Builder, Buffer}, rights::{RecvRight, SendRight}};
+    /// # fn set_up(voucher: &SendRight) {
+    /// # let reply_right = RecvRight::alloc();
+    /// # let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.set_reply_and_voucher(&reply_right, true, voucher);
+    /// # }
+    /// ```
+    pub fn set_reply_and_voucher<T, V>(&mut self, reply: &'a T, once: bool, voucher: &'a V)
+    where
+        T: AsRawName<Base = RecvRight>,
+        V: AsRawName<Base = SendRight>,
+    {
+        self.release_reply_port();
+        self.release_voucher();
+
+        let header = self.buffer.header_mut();
+        let bits = MachMsgBits::from_bits(header.msgh_bits);
+        let local_bits = if once {
+            MACH_MSG_TYPE_MAKE_SEND_ONCE
+        } else {
+            MACH_MSG_TYPE_MAKE_SEND
+        };
+
+        header.msgh_local_port = reply.as_raw_name();
+        header.msgh_voucher_port = voucher.as_raw_name();
+        header.msgh_bits = bits
+            .set_local(local_bits)
+            .set_voucher(MACH_MSG_TYPE_COPY_SEND)
+            .0;
     }
 
     /// Appends contents of a descriptor to the message.
     fn append_descriptor(&mut self, bytes: &[u8]) {
         debug_assert!(bytes.len() >= mem::size_of::<mach_msg_port_descriptor_t>());
-
         self.inc_desc_count(bytes.len());
 
+        // Descriptors are packed to the alignment of the descriptor count that precedes them.
+        debug_assert!(
+            (self.inline_data_off as usize).is_multiple_of(mem::size_of::<mach_msg_size_t>())
+        );
+
         let appended_len: mach_msg_size_t = bytes.len().try_into().unwrap();
         self.buffer.insert(self.inline_data_off, bytes);
         self.inline_data_off += appended_len;
@@ -273,20 +550,245 @@ impl<'a, 'buffer> Builder<'a, 'buffer> {
         self.append_port_descriptor(right.into_raw_name(), T::Base::MSG_TYPE);
     }
 
+    /// Appends a guarded port descriptor to the message that will contain a guarded receive right.
+    ///
+    /// The guard value is passed as the descriptor context, the flags are one or more of the
+    /// `MACH_MSG_GUARD_FLAGS_*` constants from [`mach2::message`]:
+    ///
+    /// * `MACH_MSG_GUARD_FLAGS_NONE` transfers the right with the guard kept on the port.
+    /// * `MACH_MSG_GUARD_FLAGS_IMMOVABLE_RECEIVE` additionally forbids the receiver from moving the
+    ///   receive right any further.
+    /// * `MACH_MSG_GUARD_FLAGS_UNGUARDED_ON_SEND` makes the kernel remove the guard when the message
+    ///   is sent, so the receiver gets an unguarded receive right.
+    ///
+    /// # Panics
+    /// This function will panic in case the flags don't fit into the descriptor.
+    pub fn append_moved_guarded_recv_right(
+        &mut self,
+        right: GuardedRecvRight,
+        flags: mach_msg_guard_flags_t,
+    ) {
+        let (name, guard) = right.into_raw_parts();
+        let desc = GuardedPortDescriptor::new(name, MACH_MSG_TYPE_MOVE_RECEIVE, guard, flags);
+
+        // SAFETY: The structure is packed and contains no padding.
+        self.append_descriptor(unsafe { anything_as_bytes(&desc) });
+    }
+
+    #[cfg(feature = "trace")]
+    #[inline(always)]
+    pub(crate) fn buffer(&self) -> &Buffer {
+        self.buffer
+    }
+
+    #[inline(always)]
+    pub(crate) fn buffer_mut(&mut self) -> &mut Buffer {
+        self.buffer
+    }
+
     /// Returns a slice with the message contents.
     pub fn as_slice(&self) -> &[u8] {
         self.buffer.as_slice()
     }
 
+    /// Compares the contents of two messages byte by byte.
+    ///
+    /// Port descriptors are compared by the raw names and dispositions, which checks the identity
+    /// of the names rather than the semantic equality of the rights.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::msg::{Buffer, Builder};
+    /// let mut first_buffer = Buffer::with_capacity(1024);
+    /// let mut first = Builder::new(&mut first_buffer);
+    /// first.append_inline_data(b"test");
+    ///
+    /// let mut second_buffer = Buffer::with_capacity(64);
+    /// let mut second = Builder::new(&mut second_buffer);
+    /// second.append_inline_data(b"test");
+    ///
+    /// assert!(first.content_eq(&second));
+    /// ```
+    pub fn content_eq(&self, other: &Builder) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+
+    /// Returns the number of bytes that may be added to the message before the buffer has to grow.
+    ///
+    /// Descriptors and inline data share the space after the header, so appending either of these
+    /// decreases the spare capacity. This is the [`Buffer::headroom`] of the underlying buffer.
+    pub fn spare_inline_capacity(&self) -> usize {
+        self.buffer.headroom()
+    }
+
+    /// Returns `true` in case the complex bit is set in the message header.
+    ///
+    /// The builder sets the bit and inserts the descriptor count after the header when the first
+    /// descriptor is appended.
+    #[inline]
+    pub fn is_complex(&self) -> bool {
+        self.buffer.header_bits().complex()
+    }
+
+    /// Returns the number of descriptors appended to the message.
+    #[inline]
+    pub fn descriptor_count(&self) -> mach_msg_size_t {
+        self.buffer.descriptors_count()
+    }
+
+    /// Removes all descriptors from the message turning it into a simple one.
+    ///
+    /// The port right references and the out-of-line buffers moved into the descriptors are
+    /// released as if the builder was dropped. The inline data and the header ports are kept.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder}, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_moved_right(recv_right.make_send());
+    /// builder.append_inline_data(b"test");
+    ///
+    /// builder.clear_descriptors();
+    ///
+    /// assert!(!builder.is_complex());
+    /// assert_eq!(&builder.as_slice()[24..], b"test");
+    /// ```
+    pub fn clear_descriptors(&mut self) {
+        if !self.is_complex() {
+            return;
+        }
+
+        release_descriptors(self.buffer);
+
+        let header = self.buffer.header_mut();
+        header.msgh_bits = MachMsgBits::from_bits(header.msgh_bits).into_simple().0;
+        self.buffer.remove(0, self.inline_data_off);
+        self.inline_data_off = 0;
+    }
+
+    /// Returns the maximum size of inline data that fits into a message with the specified number
+    /// of descriptors.
+    ///
+    /// The size of the largest descriptor type is assumed for each descriptor, so the result is a
+    /// lower bound for messages that contain port descriptors. The limit is computed from
+    /// `MACH_MSG_SIZE_MAX`, the kernel may refuse messages smaller than that with
+    /// `MACH_SEND_TOO_LARGE` or `MACH_SEND_NO_BUFFER` due to resource limits.
+    pub fn max_inline_for(descriptor_count: usize) -> usize {
+        const SIZE_SIZE: usize = mem::size_of::<mach_msg_size_t>();
+
+        let max_desc_size = [
+            MACH_MSG_PORT_DESCRIPTOR,
+            MACH_MSG_OOL_DESCRIPTOR,
+            MACH_MSG_OOL_VOLATILE_DESCRIPTOR,
+            MACH_MSG_OOL_PORTS_DESCRIPTOR,
+            MACH_MSG_GUARDED_PORT_DESCRIPTOR,
+        ]
+        .into_iter()
+        .map(parser::size_for_desc_type)
+        .max()
+        .unwrap();
+        let descriptors_size = if descriptor_count > 0 {
+            descriptor_count
+                .saturating_mul(max_desc_size)
+                .saturating_add(SIZE_SIZE)
+        } else {
+            0
+        };
+
+        // The message size must be a multiple of 4.
+        let max_size = MACH_MSG_SIZE_MAX as usize & !(SIZE_SIZE - 1);
+        max_size
+            .saturating_sub(mem::size_of::<mach_msg_header_t>())
+            .saturating_sub(descriptors_size)
+    }
+
+    /// Verifies that a message grown by `additional` bytes can still be sent.
+    fn check_size(&self, additional: usize) -> Result<(), BuildError> {
+        match self.as_slice().len().checked_add(additional) {
+            Some(size) if size <= mach_msg_size_t::MAX as usize => Ok(()),
+            _ => Err(BuildError::TooLarge),
+        }
+    }
+
     /// Appends inline data to the end of the message.
+    ///
+    /// # Panics
+    /// This function will panic in case the message size would exceed `mach_msg_size_t::MAX`. See
+    /// [`Builder::try_append_inline_data`] for a non-panicking version.
     pub fn append_inline_data(&mut self, data: &[u8]) {
+        self.try_append_inline_data(data).unwrap();
+    }
+
+    /// Appends inline data to the end of the message.
+    ///
+    /// # Errors
+    /// Returns [`BuildError::TooLarge`] in case the message size would exceed
+    /// `mach_msg_size_t::MAX`.
+    pub fn try_append_inline_data(&mut self, data: &[u8]) -> Result<(), BuildError> {
+        self.check_size(data.len())?;
         self.buffer.append(data);
+
+        Ok(())
+    }
+
+    /// Appends the NDR record describing the data representation of the current task to the end of
+    /// the message.
+    ///
+    /// MIG-style messages start the inline data with the record, see [`ndr`](crate::msg::ndr).
+    ///
+    /// # Panics
+    /// This function will panic in case the message size would exceed `mach_msg_size_t::MAX`.
+    pub fn append_ndr(&mut self) {
+        self.append_inline_data(&ndr::local_bytes());
+    }
+
+    /// Appends a NUL-terminated C string to the end of the inline data.
+    ///
+    /// No padding is added after the terminator, see
+    /// [`BodyParser::read_cstr`](crate::msg::BodyParser::read_cstr) for the reverse operation.
+    ///
+    /// # Errors
+    /// Returns [`BuildError::InteriorNul`] in case the string contains a NUL byte and
+    /// [`BuildError::TooLarge`] in case the message size would exceed `mach_msg_size_t::MAX`.
+    pub fn append_cstr(&mut self, s: &str) -> Result<(), BuildError> {
+        if s.as_bytes().contains(&0) {
+            return Err(BuildError::InteriorNul);
+        }
+
+        self.check_size(s.len().saturating_add(1))?;
+        self.buffer.append(s.as_bytes());
+        self.buffer.append(&[0]);
+
+        Ok(())
     }
 
     /// Inserts data at an offset from the start of the inline data.
+    ///
+    /// # Panics
+    /// This function will panic in case the offset is past the end of the inline data or the
+    /// message size would exceed `mach_msg_size_t::MAX`. See [`Builder::try_insert_inline_data`]
+    /// for a version that doesn't panic on the size overflow.
     pub fn insert_inline_data(&mut self, at: usize, data: &[u8]) {
+        self.try_insert_inline_data(at, data).unwrap();
+    }
+
+    /// Inserts data at an offset from the start of the inline data.
+    ///
+    /// # Errors
+    /// Returns [`BuildError::TooLarge`] in case the message size would exceed
+    /// `mach_msg_size_t::MAX`.
+    ///
+    /// # Panics
+    /// This function will panic in case the offset is past the end of the inline data.
+    pub fn try_insert_inline_data(&mut self, at: usize, data: &[u8]) -> Result<(), BuildError> {
+        self.check_size(data.len())?;
+
         let at: mach_msg_size_t = at.try_into().unwrap();
         self.buffer.insert(self.inline_data_off + at, data);
+
+        Ok(())
     }
 
     /// Appends an out-of-line data descriptor to the message.
@@ -294,33 +796,426 @@ impl<'a, 'buffer> Builder<'a, 'buffer> {
     /// The pages containing the data slice will be copied into the receiver task on message
     /// reception, the sender task's mapping's sharing mode may be changed to copy-on-write which
     /// may affect the performance (see [`CopyKind`] docs).
+    ///
+    /// # Panics
+    /// This function will panic in case the slice is longer than `mach_msg_size_t::MAX`. See
+    /// [`Builder::try_append_ool_data`] for a non-panicking version.
     pub fn append_ool_data(&mut self, data: &'a [u8], copy_kind: CopyKind) {
-        let desc = mach_msg_ool_descriptor_t::new(
-            data.as_ptr() as *mut _,
-            false,
+        self.try_append_ool_data(data, copy_kind).unwrap();
+    }
+
+    /// Appends an out-of-line data descriptor to the message.
+    ///
+    /// See [`Builder::append_ool_data`] for details.
+    ///
+    /// # Errors
+    /// Returns [`BuildError::TooLarge`] in case the slice is longer than `mach_msg_size_t::MAX`
+    /// or the message size would exceed it.
+    pub fn try_append_ool_data(
+        &mut self,
+        data: &'a [u8],
+        copy_kind: CopyKind,
+    ) -> Result<(), BuildError> {
+        self.try_append_ool_desc(
+            data,
             copy_kind as mach_msg_copy_options_t,
-            data.len().try_into().unwrap(),
-        );
+            MACH_MSG_OOL_DESCRIPTOR,
+        )
+    }
+
+    /// Appends a volatile out-of-line data descriptor to the message.
+    ///
+    /// Volatile descriptors are meant for data the sender may modify while the message is in
+    /// flight, so the data is physically copied instead of sharing the pages copy-on-write. The
+    /// memory is never deallocated by the kernel. The receiver gets the data as a regular
+    /// [`ParsedMsgDesc::OolData`](crate::msg::ParsedMsgDesc::OolData) descriptor.
+    ///
+    /// # Panics
+    /// This function will panic in case the slice is longer than `mach_msg_size_t::MAX` or the
+    /// message size would exceed it. See [`Builder::try_append_ool_volatile`] for a non-panicking
+    /// version.
+    pub fn append_ool_volatile(&mut self, data: &'a [u8]) {
+        self.try_append_ool_volatile(data).unwrap();
+    }
+
+    /// Appends a volatile out-of-line data descriptor to the message.
+    ///
+    /// See [`Builder::append_ool_volatile`] for details.
+    ///
+    /// # Errors
+    /// Returns [`BuildError::TooLarge`] in case the slice is longer than `mach_msg_size_t::MAX`
+    /// or the message size would exceed it.
+    pub fn try_append_ool_volatile(&mut self, data: &'a [u8]) -> Result<(), BuildError> {
+        self.try_append_ool_desc(
+            data,
+            MACH_MSG_PHYSICAL_COPY,
+            MACH_MSG_OOL_VOLATILE_DESCRIPTOR,
+        )
+    }
 
+    /// Appends an out-of-line data descriptor of the specified type for memory that isn't
+    /// deallocated by the kernel.
+    fn try_append_ool_desc(
+        &mut self,
+        data: &'a [u8],
+        copy_options: mach_msg_copy_options_t,
+        type_: mach_msg_descriptor_type_t,
+    ) -> Result<(), BuildError> {
+        let size = data.len().try_into().map_err(|_| BuildError::TooLarge)?;
+        self.check_size(mem::size_of::<mach_msg_ool_descriptor_t>())?;
+
+        let mut desc =
+            mach_msg_ool_descriptor_t::new(data.as_ptr() as *mut _, false, copy_options, size);
+        desc.type_ = type_ as _;
+
+        // SAFETY: The descriptor consists of integer fields and contains no padding.
         self.append_descriptor(unsafe { anything_as_bytes(&desc) });
+
+        Ok(())
+    }
+
+    /// Appends an out-of-line data descriptor for a buffer received in another message.
+    ///
+    /// The buffer owns the pages the kernel mapped into the task when the message was received, so
+    /// these are handed to the kernel with the `deallocate` flag set, as with
+    /// [`Builder::append_consumed_ool_data`], instead of copying the data into a new buffer. See
+    /// [`ParsedMsgDesc::into_forwardable`](crate::msg::ParsedMsgDesc::into_forwardable) for
+    /// forwarding descriptors of parsed messages.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{builder::CopyKind, Buffer, Builder}, ool_vec, rights::RecvRight};
+    /// # let proxy = RecvRight::alloc();
+    /// # let target = RecvRight::alloc();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.set_bulk_ool(ool_vec![1, 2, 3].into_buf(), CopyKind::Virtual);
+    /// proxy.make_send().send(builder).unwrap();
+    ///
+    /// let data = proxy.recv_bulk_ool(&mut buffer).unwrap().unwrap();
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_forwarded_ool(data, CopyKind::Virtual);
+    /// target.make_send().send(builder).unwrap();
+    ///
+    /// assert_eq!(target.recv_bulk_ool(&mut buffer).unwrap().unwrap(), [1, 2, 3]);
+    /// ```
+    #[inline]
+    pub fn append_forwarded_ool(&mut self, data: OolBuf, copy_kind: CopyKind) {
+        self.append_consumed_ool_data(data, copy_kind);
+    }
+
+    /// Turns an empty message into a bulk data message consisting of a single out-of-line data
+    /// descriptor for the buffer.
+    ///
+    /// The pages of the buffer are unmapped from the sender task's address space as with
+    /// [`Builder::append_consumed_ool_data`]. Use [`RecvRight::recv_bulk_ool`] to receive such
+    /// messages.
+    ///
+    /// # Panics
+    /// This function will panic in case the message already contains descriptors or inline data.
+    pub fn set_bulk_ool(&mut self, data: OolBuf, copy_kind: CopyKind) {
+        assert!(
+            self.buffer.body().is_empty(),
+            "a bulk message must contain only one descriptor"
+        );
+
+        self.append_consumed_ool_data(data, copy_kind);
     }
 
     /// Appends an out-of-line data descriptor to the message marking the backing virtual memory
     /// pages to be unmapped from the sender task's address space.
     ///
     /// The pages will also be unmapped when the builder is dropped without sending the message.
+    ///
+    /// # Panics
+    /// This function will panic in case the buffer is longer than `mach_msg_size_t::MAX`.
     pub fn append_consumed_ool_data(&mut self, data: OolBuf, copy_kind: CopyKind) {
-        let (address, size) = data.into_raw_parts();
+        let size: mach_msg_size_t = data.len().try_into().unwrap();
+        let (address, _) = data.into_raw_parts();
         let desc = mach_msg_ool_descriptor_t::new(
             address.as_ptr() as *mut _,
             true,
             copy_kind as mach_msg_copy_options_t,
-            size.try_into().unwrap(),
+            size,
         );
 
         self.append_descriptor(unsafe { anything_as_bytes(&desc) });
     }
 
+    fn append_ool_ports_descriptor<I>(&mut self, names: I, disposition: mach_msg_type_name_t)
+    where
+        I: ExactSizeIterator<Item = mach_port_t>,
+    {
+        const NAME_SIZE: usize = mem::size_of::<mach_port_t>();
+
+        let count: mach_msg_size_t = names.len().try_into().unwrap();
+        let address = if count > 0 {
+            let mut array = OolVec::with_capacity(names.len() * NAME_SIZE);
+            for name in names {
+                array.extend_from_slice(&name.to_ne_bytes());
+            }
+
+            array.into_buf().into_raw_parts().0.as_ptr()
+        } else {
+            ptr::null_mut()
+        };
+
+        // The kernel deallocates the array when the message is sent.
+        let desc = mach_msg_ool_ports_descriptor_t::new(
+            address as *mut _,
+            count > 0,
+            MACH_MSG_VIRTUAL_COPY,
+            disposition,
+            count,
+        );
+
+        // SAFETY: mach_msg_ool_ports_descriptor_t is repr(C) and contains no padding.
+        self.append_descriptor(unsafe { anything_as_bytes(&desc) });
+    }
+
+    /// Appends an out-of-line ports descriptor to the message that will contain the rights.
+    ///
+    /// All rights must be of the same kind. One reference for each right is consumed when the
+    /// message is sent. The rights are released in case the builder is dropped.
+    ///
+    /// # Panics
+    /// This function will panic in case there are more than `mach_msg_size_t::MAX` rights or
+    /// allocating the names array fails.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder, ParsedMsgDesc}, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_moved_ool_ports([RecvRight::alloc(), RecvRight::alloc()]);
+    /// send_right.send(builder).unwrap();
+    ///
+    /// let msg = recv_right.recv(&mut buffer).unwrap().parse_all();
+    /// let [ParsedMsgDesc::OolPortsRecv(rights)] = &msg.descriptors[..] else { unreachable!() };
+    ///
+    /// assert_eq!(rights.len(), 2);
+    /// ```
+    pub fn append_moved_ool_ports<I>(&mut self, rights: I)
+    where
+        I: IntoIterator,
+        I::Item: IntoRawName,
+    {
+        let names: Vec<_> = rights.into_iter().map(IntoRawName::into_raw_name).collect();
+
+        self.append_ool_ports_descriptor(names.into_iter(), <I::Item as AsRawName>::Base::MSG_TYPE);
+    }
+
+    /// Appends an out-of-line ports descriptor to the message that will contain send rights to the
+    /// ports represented by the send rights. The provided send rights' references are not consumed.
+    ///
+    /// # Panics
+    /// This function will panic in the same cases as [`Builder::append_moved_ool_ports`].
+    pub fn append_copied_ool_ports<T: AsRawName<Base = SendRight>>(&mut self, rights: &'a [T]) {
+        self.append_ool_ports_descriptor(
+            rights.iter().map(AsRawName::as_raw_name),
+            MACH_MSG_TYPE_COPY_SEND,
+        );
+    }
+
+    /// Checks the layout of the descriptors in the message.
+    ///
+    /// Every descriptor must start at an offset from the body start aligned to 4 bytes, have a
+    /// known type and the descriptor count must match the descriptors placed before the inline
+    /// data. Messages built using the builder methods always have a valid layout, so this is
+    /// mostly useful to catch `MACH_SEND_INVALID_DATA` errors early after modifying the message
+    /// contents.
+    ///
+    /// # Errors
+    /// Returns [`BuildError::InvalidLayout`] in case the layout is invalid.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder}, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_inline_data(&[1, 2, 3]);
+    /// builder.append_made_send_right(&recv_right, false);
+    ///
+    /// assert_eq!(builder.validate(), Ok(()));
+    /// ```
+    pub fn validate(&self) -> Result<(), BuildError> {
+        const SIZE_SIZE: usize = mem::size_of::<mach_msg_size_t>();
+        const TYPE_DESC_SIZE: usize = mem::size_of::<mach_msg_type_descriptor_t>();
+
+        let count = self.buffer.descriptors_count();
+        if count == 0 {
+            return Ok(());
+        }
+
+        let body = &self.buffer.body()[..self.inline_data_off as usize];
+        let mut offset = SIZE_SIZE;
+
+        for _ in 0..count {
+            if !offset.is_multiple_of(SIZE_SIZE) {
+                return Err(BuildError::InvalidLayout);
+            }
+
+            // Every descriptor starts with the fields of mach_msg_type_descriptor_t.
+            let Some(desc) = body[offset..].first_chunk::<TYPE_DESC_SIZE>() else {
+                return Err(BuildError::InvalidLayout);
+            };
+
+            let type_ = desc[mem::offset_of!(mach_msg_type_descriptor_t, type_)]
+                as mach_msg_descriptor_type_t;
+            let desc_size = match type_ {
+                MACH_MSG_PORT_DESCRIPTOR
+                | MACH_MSG_OOL_DESCRIPTOR
+                | MACH_MSG_OOL_VOLATILE_DESCRIPTOR
+                | MACH_MSG_OOL_PORTS_DESCRIPTOR
+                | MACH_MSG_GUARDED_PORT_DESCRIPTOR => parser::size_for_desc_type(type_),
+                _ => return Err(BuildError::InvalidLayout),
+            };
+
+            if body.len() - offset < desc_size {
+                return Err(BuildError::InvalidLayout);
+            }
+
+            offset += desc_size;
+        }
+
+        if offset == body.len() {
+            Ok(())
+        } else {
+            Err(BuildError::InvalidLayout)
+        }
+    }
+
+    /// Sets the `msgh_id` field in the message header, see [`Builder::set_id`].
+    ///
+    /// The `with_*` family of methods returns the builder to allow chaining calls.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder}, rights::RecvRight};
+    /// let right = RecvRight::alloc();
+    /// let send_right = right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder
+    ///     .with_id(1337)
+    ///     .with_ndr()
+    ///     .with_made_send_right(&right, false)
+    ///     .with_inline_data(b"x");
+    ///
+    /// send_right.send(builder).unwrap();
+    /// ```
+    #[inline]
+    pub fn with_id(&mut self, id: MsgId) -> &mut Self {
+        self.set_id(id);
+        self
+    }
+
+    /// Appends inline data to the message, see [`Builder::append_inline_data`].
+    #[inline]
+    pub fn with_inline_data(&mut self, data: &[u8]) -> &mut Self {
+        self.append_inline_data(data);
+        self
+    }
+
+    /// Appends the local NDR record to the message, see [`Builder::append_ndr`].
+    #[inline]
+    pub fn with_ndr(&mut self) -> &mut Self {
+        self.append_ndr();
+        self
+    }
+
+    /// Appends a made send right to the message, see [`Builder::append_made_send_right`].
+    #[inline]
+    pub fn with_made_send_right<T>(&mut self, recv_right: &'a T, once: bool) -> &mut Self
+    where
+        T: AsRawName<Base = RecvRight>,
+    {
+        self.append_made_send_right(recv_right, once);
+        self
+    }
+
+    /// Appends a copied send right to the message, see [`Builder::append_copied_send_right`].
+    #[inline]
+    pub fn with_copied_send_right<T>(&mut self, right: &'a T) -> &mut Self
+    where
+        T: AsRawName<Base = SendRight>,
+    {
+        self.append_copied_send_right(right);
+        self
+    }
+
+    /// Appends a moved right to the message, see [`Builder::append_moved_right`].
+    #[inline]
+    pub fn with_moved_right<T: IntoRawName>(&mut self, right: T) -> &mut Self {
+        self.append_moved_right(right);
+        self
+    }
+
+    /// Appends out-of-line data to the message, see [`Builder::append_ool_data`].
+    #[inline]
+    pub fn with_ool_data(&mut self, data: &'a [u8], copy_kind: CopyKind) -> &mut Self {
+        self.append_ool_data(data, copy_kind);
+        self
+    }
+
+    /// Finishes building the message.
+    ///
+    /// The returned [`FinishedMsg`] can no longer be modified, but it still owns the port right
+    /// references and out-of-line buffers held by the builder. This allows passing a built message
+    /// around before deciding where to send it.
+    pub fn finish(self) -> FinishedMsg<'a, 'buffer> {
+        FinishedMsg(self)
+    }
+
+    /// Discards the message releasing all port right references and out-of-line buffers moved into
+    /// it.
+    ///
+    /// This is equivalent to dropping the builder and exists to make the intent explicit.
+    #[inline]
+    pub fn discard(self) {
+        drop(self)
+    }
+
+    /// Sets the destination of the message to be copied from a send right preserving the rest of
+    /// the header including the complex bit.
+    ///
+    /// This is useful for proxies that decide where to forward a message after building it. The
+    /// message may then be sent to the destination using [`Builder::send`].
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Builder, Buffer}, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// # let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_made_send_right(&recv_right, false);
+    /// builder.rewrite_destination(&send_right);
+    /// builder.send().unwrap();
+    /// ```
+    pub fn rewrite_destination<T: AsRawName<Base = SendRight>>(&mut self, right: &'a T) {
+        self.set_raw_remote_port(right.as_raw_name(), MACH_MSG_TYPE_COPY_SEND);
+    }
+
+    /// Sends the message to the destination set using [`Builder::rewrite_destination`].
+    ///
+    /// See [`SendRight::send`] for details.
+    ///
+    /// # Errors
+    /// Returns an error of kind [`SendErrorKind::InvalidDest`] in case the destination wasn't set.
+    ///
+    /// [`SendErrorKind::InvalidDest`]: crate::msg::SendErrorKind::InvalidDest
+    pub fn send(self) -> Result<(), SendError> {
+        let header = self.buffer.header();
+        let name = header.msgh_remote_port;
+        let bits = MachMsgBits::from_bits(header.msgh_bits).remote();
+
+        send_impl(name, self, bits)
+    }
+
     pub(crate) fn set_raw_remote_port(&mut self, name: mach_port_t, bits: mach_msg_bits_t) {
         let header = self.buffer.header_mut();
         header.msgh_remote_port = name;
@@ -328,47 +1223,52 @@ impl<'a, 'buffer> Builder<'a, 'buffer> {
     }
 }
 
+/// Releases a port right reference moved into a message that wasn't sent.
+fn release_port(raw_name: mach_port_t, disposition: mach_msg_type_name_t) {
+    match disposition {
+        MACH_MSG_TYPE_MOVE_SEND => drop(SendRight::from_raw_name(raw_name)),
+        MACH_MSG_TYPE_MOVE_SEND_ONCE => drop(SendOnceRight::from_raw_name(raw_name)),
+        MACH_MSG_TYPE_MOVE_RECEIVE => drop(RecvRight::from_raw_name(raw_name)),
+        MACH_MSG_TYPE_COPY_SEND
+        | MACH_MSG_TYPE_COPY_RECEIVE
+        | MACH_MSG_TYPE_MAKE_SEND
+        | MACH_MSG_TYPE_MAKE_SEND_ONCE => (),
+        _ => unreachable!("invalid disposition value in a port descriptor"),
+    }
+}
+
 impl Drop for Builder<'_, '_> {
     fn drop(&mut self) {
         drop_header(self.buffer.header_mut());
+        release_descriptors(self.buffer);
+    }
+}
 
-        let mut count = self.buffer.descriptors_count();
-        let mut offset = mem::size_of::<mach_msg_size_t>() as mach_msg_size_t;
-        while count > 0 {
-            use TransmutedMsgDesc::*;
-
-            match parser::next_desc_impl(self.buffer, &mut offset, false) {
-                Port(desc) => {
-                    let raw_name = desc.name;
-                    match desc.disposition as mach_msg_type_name_t {
-                        MACH_MSG_TYPE_MOVE_SEND => drop(SendRight::from_raw_name(raw_name)),
-                        MACH_MSG_TYPE_MOVE_SEND_ONCE => {
-                            drop(SendOnceRight::from_raw_name(raw_name))
-                        }
-                        MACH_MSG_TYPE_MOVE_RECEIVE => drop(RecvRight::from_raw_name(raw_name)),
-                        MACH_MSG_TYPE_COPY_SEND
-                        | MACH_MSG_TYPE_COPY_RECEIVE
-                        | MACH_MSG_TYPE_MAKE_SEND
-                        | MACH_MSG_TYPE_MAKE_SEND_ONCE => (),
-                        _ => unreachable!("invalid disposition value in a port descriptor"),
-                    }
-                }
-                Ool(desc) | OolVolatile(desc) => {
-                    // Only deallocate the buffer in case it was meant to be deallocated.
-                    if desc.deallocate != 0 {
-                        let ptr = NonNull::new(desc.address as *mut u8).unwrap();
-                        let length = desc.size.try_into().unwrap();
+/// A Mach message that has been completely built and is ready to be sent.
+///
+/// Dropping a `FinishedMsg` without sending it releases the port right references and the
+/// out-of-line buffers moved into the message the same way dropping a [`Builder`] does.
+#[derive(Debug)]
+pub struct FinishedMsg<'a, 'buffer>(Builder<'a, 'buffer>);
 
-                        // SAFETY: Since the message was produced by the builder, the address and
-                        // length should be correct.
-                        drop(unsafe { OolBuf::from_raw_parts(ptr, length) })
-                    }
-                }
-                OolPorts(_) => unimplemented!("OOL ports descriptors are not yet implemented"),
-            }
+impl<'a, 'buffer> FinishedMsg<'a, 'buffer> {
+    /// Returns a slice with the message contents.
+    pub fn as_slice(&self) -> &[u8] {
+        self.0.as_slice()
+    }
 
-            count -= 1;
-        }
+    /// Sends the message to the port represented by a send right.
+    ///
+    /// See [`SendRight::send`] for details.
+    pub fn send(self, right: &SendRight) -> Result<(), SendError> {
+        right.send(self.0)
+    }
+
+    /// Sends the message to the port represented by a send once right consuming the right.
+    ///
+    /// See [`SendOnceRight::send`] for details.
+    pub fn send_once(self, right: SendOnceRight) -> Result<(), SendError> {
+        right.send(self.0)
     }
 }
 
@@ -376,8 +1276,9 @@ impl Drop for Builder<'_, '_> {
 mod tests {
     use super::*;
     use crate::{
-        msg::{ool::OolVec, DescOrBodyParser, MsgParser, ParsedMsgDesc},
+        msg::{ool::OolVec, DescOrBodyParser, GrowthPolicy, MsgParser, ParsedMsgDesc},
         rights::AnySendRight,
+        test_util::recv_refs,
     };
 
     #[test]
@@ -391,6 +1292,113 @@ mod tests {
         builder.insert_inline_data(4, b"1337");
     }
 
+    #[test]
+    fn test_drop_ool_ports() {
+        let names = [RecvRight::alloc(), RecvRight::alloc()].map(IntoRawName::into_raw_name);
+        assert!(names.iter().all(|&name| recv_refs(name) == Some(1)));
+
+        let mut array = OolVec::with_capacity(mem::size_of_val(&names));
+        for name in names {
+            array.extend_from_slice(&name.to_ne_bytes());
+        }
+        let (address, length) = array.into_buf().into_raw_parts();
+        assert_eq!(length, mem::size_of_val(&names));
+
+        let mut buffer = Buffer::with_capacity(1024);
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_inline_data(b"test");
+        let desc = mach_msg_ool_ports_descriptor_t::new(
+            address.as_ptr() as *mut _,
+            true,
+            MACH_MSG_VIRTUAL_COPY,
+            MACH_MSG_TYPE_MOVE_RECEIVE,
+            names.len() as mach_msg_size_t,
+        );
+        builder.append_descriptor(unsafe { anything_as_bytes(&desc) });
+        drop(builder);
+
+        // The moved receive rights are destroyed.
+        assert!(names.iter().all(|&name| recv_refs(name).is_none()));
+    }
+
+    #[test]
+    fn test_discard() {
+        use mach2::{kern_return::*, mach_port, port::MACH_PORT_RIGHT_RECEIVE, traps};
+
+        let mut buffer = Buffer::with_capacity(1024);
+        let right = RecvRight::alloc();
+        let raw_name = right.as_raw_name();
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_moved_right(right);
+        builder.discard();
+
+        // The receive right must be gone after the builder is discarded.
+        let result = unsafe {
+            mach_port::mach_port_mod_refs(
+                traps::mach_task_self(),
+                raw_name,
+                MACH_PORT_RIGHT_RECEIVE,
+                0,
+            )
+        };
+        assert_ne!(result, KERN_SUCCESS);
+    }
+
+    #[test]
+    fn test_discard_guarded_recv_right() {
+        use mach2::{kern_return::*, mach_port, port::MACH_PORT_RIGHT_RECEIVE, traps};
+
+        let mut buffer = Buffer::with_capacity(1024);
+        let right = GuardedRecvRight::alloc(0x1337);
+        let raw_name = right.as_raw_name();
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_inline_data(b"test");
+        builder.append_moved_guarded_recv_right(right, MACH_MSG_GUARD_FLAGS_NONE);
+        builder.discard();
+
+        // The guarded receive right must be destructed using the guard.
+        let result = unsafe {
+            mach_port::mach_port_mod_refs(
+                traps::mach_task_self(),
+                raw_name,
+                MACH_PORT_RIGHT_RECEIVE,
+                0,
+            )
+        };
+        assert_ne!(result, KERN_SUCCESS);
+    }
+
+    #[test]
+    fn test_finish() {
+        let mut buffer = Buffer::with_capacity(1024);
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.set_id(1337);
+        builder.append_inline_data(b"test");
+
+        let msg = builder.finish();
+        assert_eq!(
+            msg.as_slice().len(),
+            mem::size_of::<mach_msg_header_t>() + 4
+        );
+        msg.send(&send_right).unwrap();
+
+        let parser = recv_right.recv(&mut buffer).unwrap();
+        let (header, parser) = parser.parse_header();
+
+        assert_eq!(header.id, 1337);
+
+        let DescOrBodyParser::Body(parser) = parser else {
+            panic!("expected a body parser");
+        };
+
+        assert_eq!(parser.body(), b"test");
+    }
+
     #[test]
     fn test_reply_port_send() {
         let mut buffer = Buffer::with_capacity(1024);
@@ -408,6 +1416,32 @@ mod tests {
         assert!(matches!(header.reply_right, Some(AnySendRight::Send(_))));
     }
 
+    #[test]
+    fn test_set_request() {
+        let mut buffer = Buffer::with_capacity(1024);
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let reply_right = RecvRight::alloc();
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.set_request(&reply_right, true, 0xDEAD_BEEF_1337);
+        send_right.send(builder).unwrap();
+
+        let parser = recv_right.recv(&mut buffer).unwrap();
+        let (header, parser) = parser.parse_header();
+
+        assert!(matches!(
+            header.reply_right,
+            Some(AnySendRight::SendOnce(_))
+        ));
+
+        let DescOrBodyParser::Body(parser) = parser else {
+            panic!("expected a body parser");
+        };
+
+        assert_eq!(parser.body(), 0xDEAD_BEEF_1337u64.to_ne_bytes());
+    }
+
     #[test]
     fn test_reply_port_send_once() {
         let mut buffer = Buffer::with_capacity(1024);
@@ -443,6 +1477,202 @@ mod tests {
         assert!(matches!(parser, DescOrBodyParser::Body(_)));
     }
 
+    #[test]
+    fn test_ool_data_empty() {
+        let mut buffer = Buffer::with_capacity(1024);
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_ool_data(&[], CopyKind::Virtual);
+        builder.append_consumed_ool_data(OolVec::with_capacity(0).into_buf(), CopyKind::Physical);
+        send_right.send(builder).unwrap();
+
+        let parser = recv_right.recv(&mut buffer).unwrap();
+        let (_, mut parser) = parser.parse_header();
+
+        for _ in 0..2 {
+            let DescOrBodyParser::Descriptor(desc_parser) = parser else {
+                panic!("expected a descriptor");
+            };
+
+            let (ParsedMsgDesc::OolData(ool_data), next_parser) = desc_parser.next() else {
+                panic!("expected an OOL data descriptor");
+            };
+
+            assert!(ool_data.is_empty());
+            assert_eq!(ool_data.as_slice(), &[]);
+            parser = next_parser;
+        }
+
+        assert!(matches!(parser, DescOrBodyParser::Body(_)));
+    }
+
+    #[test]
+    fn test_validate() {
+        let right = RecvRight::alloc();
+        let mut buffer = Buffer::with_capacity(64);
+        let mut builder = Builder::new(&mut buffer);
+        assert_eq!(builder.validate(), Ok(()));
+
+        builder.append_inline_data(b"abc");
+        builder.append_made_send_right(&right, false);
+        builder.append_ool_data(b"data", CopyKind::Virtual);
+        assert_eq!(builder.validate(), Ok(()));
+
+        // Misalign the descriptors by placing a byte after the descriptor count.
+        builder.buffer.insert(4, &[0]);
+        builder.inline_data_off += 1;
+        assert_eq!(builder.validate(), Err(BuildError::InvalidLayout));
+
+        // Restore the layout so that the descriptors can be released on drop.
+        builder.buffer.body_mut()[4..].rotate_left(1);
+        let len = builder.buffer.body().len() - 1;
+        unsafe { builder.buffer.set_len(len as mach_msg_size_t) };
+        builder.inline_data_off -= 1;
+        assert_eq!(builder.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_content_eq() {
+        let build = |data: &[u8]| {
+            let mut buffer = Buffer::with_capacity(64);
+            let mut builder = Builder::new(&mut buffer);
+            builder.set_id(1337);
+            builder.append_inline_data(data);
+            builder.discard();
+            buffer
+        };
+
+        assert_eq!(build(b"test"), build(b"test"));
+        assert_ne!(build(b"test"), build(b"tset"));
+        assert_ne!(build(b"test"), build(b"test!"));
+
+        let right = RecvRight::alloc();
+        let mut first_buffer = Buffer::with_capacity(64);
+        let mut first = Builder::new(&mut first_buffer);
+        first.append_made_send_right(&right, false);
+
+        let mut second_buffer = Buffer::with_capacity(1024);
+        let mut second = Builder::new(&mut second_buffer);
+        assert!(!first.content_eq(&second));
+
+        second.append_made_send_right(&right, false);
+        assert!(first.content_eq(&second));
+
+        second.append_inline_data(b"test");
+        assert!(!first.content_eq(&second));
+    }
+
+    #[test]
+    fn test_complex_state() {
+        let right = RecvRight::alloc();
+        let mut buffer = Buffer::with_capacity(64);
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_inline_data(b"test");
+
+        assert!(!builder.is_complex());
+        assert_eq!(builder.descriptor_count(), 0);
+        assert_eq!(
+            builder.as_slice().len(),
+            mem::size_of::<mach_msg_header_t>() + 4
+        );
+
+        builder.append_made_send_right(&right, false);
+        builder.append_made_send_right(&right, true);
+
+        assert!(builder.is_complex());
+        assert_eq!(builder.descriptor_count(), 2);
+        assert_eq!(builder.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_max_inline_for() {
+        assert_eq!(Builder::max_inline_for(0), 0xffff_ffe4);
+        assert_eq!(Builder::max_inline_for(4), 0xffff_ffe4 - 4 - 4 * 16);
+        assert_eq!(Builder::max_inline_for(usize::MAX), 0);
+    }
+
+    #[test]
+    fn test_spare_inline_capacity() {
+        let right = RecvRight::alloc();
+        let mut buffer = Buffer::with_capacity(64);
+        let mut builder = Builder::new(&mut buffer);
+        assert_eq!(builder.spare_inline_capacity(), 64);
+
+        builder.append_inline_data(b"test");
+        assert_eq!(builder.spare_inline_capacity(), 60);
+
+        // the descriptor count and a port descriptor
+        builder.append_made_send_right(&right, false);
+        assert_eq!(builder.spare_inline_capacity(), 60 - 4 - 12);
+
+        builder.append_inline_data(&[0; 44]);
+        assert_eq!(builder.spare_inline_capacity(), 0);
+    }
+
+    #[test]
+    fn test_growth_policy() {
+        let mut buffer = Buffer::with_capacity_and_growth(0, GrowthPolicy::Exact);
+        let mut builder = Builder::new(&mut buffer);
+        for _ in 0..16 {
+            builder.append_inline_data(&[0; 12]);
+            assert_eq!(builder.spare_inline_capacity(), 0);
+        }
+        builder.discard();
+        assert_eq!(buffer.capacity(), 16 * 12);
+
+        let mut buffer = Buffer::with_capacity_and_growth(16, GrowthPolicy::Double);
+        let mut builder = Builder::new(&mut buffer);
+        // 16 + max(16, 20)
+        builder.append_inline_data(&[0; 20]);
+        assert_eq!(builder.spare_inline_capacity(), 16);
+        // 36 * 2
+        builder.append_inline_data(&[0; 20]);
+        assert_eq!(builder.spare_inline_capacity(), 32);
+        // 72 + max(72, 100)
+        builder.append_inline_data(&[0; 100]);
+        assert_eq!(builder.spare_inline_capacity(), 32);
+        builder.discard();
+    }
+
+    #[test]
+    fn test_too_large() {
+        // Only virtual memory is reserved, the pages are never touched.
+        let capacity = mach_msg_size_t::MAX as usize + 2;
+        let mut data = OolVec::with_capacity(capacity);
+        unsafe {
+            data.set_len(capacity - 1);
+        }
+
+        let mut buffer = Buffer::with_capacity(1024);
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_inline_data(b"test");
+
+        assert_eq!(
+            builder.try_append_ool_data(&data, CopyKind::Virtual),
+            Err(BuildError::TooLarge)
+        );
+        assert_eq!(
+            builder.try_append_ool_volatile(&data),
+            Err(BuildError::TooLarge)
+        );
+        assert_eq!(
+            builder.try_append_inline_data(&data),
+            Err(BuildError::TooLarge)
+        );
+        assert_eq!(
+            builder.try_insert_inline_data(0, &data[..mach_msg_size_t::MAX as usize]),
+            Err(BuildError::TooLarge)
+        );
+
+        // The message must be left intact.
+        assert_eq!(
+            builder.as_slice().len(),
+            mem::size_of::<mach_msg_header_t>() + 4
+        );
+    }
+
     #[test]
     fn test_ool_data_ref() {
         let mut data = vec![];
@@ -6,8 +6,15 @@
 //! provides two types that wrap these errors corresponding to the two groups: [`SendError`] and
 //! [`RecvError`]. Both have identical APIs and their own error kind enumerations: [`SendErrorKind`]
 //! and [`RecvErrorKind`].
+//!
+//! Additionally the module provides the [`ParseError`] type returned when a message fails
+//! validation before being parsed, the [`BuildError`] type returned when a message can't be
+//! built, the [`CallError`] type combining the two groups of `mach_msg` errors for request/reply
+//! exchanges and the [`RecvBatchError`] type returning the messages received before a failure.
 
+use crate::msg::MsgParser;
 use mach2::message::*;
+use std::fmt;
 
 /// A re-export of the `mach_msg_return_t` type, the result type returned by the `mach_msg`
 /// function.
@@ -36,7 +43,8 @@ macro_rules! def_error_kind {
 
         impl $name {
             #[doc = concat!(
-                "Creates a `", stringify!($name), " from a known error code or returns `None`."
+                "Creates a `", stringify!($name), "` from an error code or returns `",
+                stringify!($name), "::Other` in case the code is unknown."
             )]
             pub const fn from_error_code(code: RawErrorBits) -> Self {
                 match code {
@@ -124,8 +132,16 @@ def_error_kind! {
         /// compatibility: no longer a returned error
         InSet = MACH_RCV_IN_SET,
         /// Error receiving message header.  See special bits.
+        ///
+        /// The kernel destroys the message. The flag accessors of [`RecvError`] (e.g.
+        /// [`RecvError::ipc_space`]) report which resource shortage prevented the reception.
         HeaderError = MACH_RCV_HEADER_ERROR,
         /// Error receiving message body.  See special bits.
+        ///
+        /// The message is partially received. The receive functions of this crate destroy it
+        /// releasing the rights and the out-of-line memory already placed into the task. The flag
+        /// accessors of [`RecvError`] (e.g. [`RecvError::vm_space`]) report which resource
+        /// shortage prevented the reception.
         BodyError = MACH_RCV_BODY_ERROR,
         /// Invalid msg-type specification in scatter list.
         InvalidType = MACH_RCV_INVALID_TYPE,
@@ -157,6 +173,11 @@ macro_rules! def_error {
             }
 
             /// Returns the error kind of the error.
+            ///
+            #[doc = concat!(
+                "Error codes that are unknown to this crate are reported as `", stringify!($kind),
+                "::Other`, use [`raw`](Self::raw) to get the actual code."
+            )]
             pub const fn kind(self) -> $kind {
                 $kind::from_error_code(self.0 & !MACH_MSG_MASK)
             }
@@ -166,6 +187,15 @@ macro_rules! def_error {
                 self.0
             }
 
+            /// Returns the raw `mach_msg_return_t` value of the error including the flag bits.
+            ///
+            /// This is the same value as returned by [`bits`](Self::bits) and is useful for
+            /// matching against error codes without a corresponding error kind.
+            #[inline(always)]
+            pub const fn raw(self) -> mach_msg_return_t {
+                self.0
+            }
+
             /// Returns the VM space flag of the error.
             #[inline(always)]
             pub const fn vm_space(self) -> bool {
@@ -233,7 +263,11 @@ macro_rules! def_error {
 
         impl ::std::fmt::Display for $name {
             fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-                ::std::fmt::Display::fmt(&self.kind(), f)?;
+                match self.kind() {
+                    // Unknown codes are printed as is since the kind doesn't carry the code.
+                    $kind::Other => write!(f, "{:#x}", self.0 & !MACH_MSG_MASK)?,
+                    kind => ::std::fmt::Display::fmt(&kind, f)?,
+                }
 
                 if self.vm_space() {
                     f.write_str("|MACH_MSG_VM_SPACE")?;
@@ -269,3 +303,180 @@ def_error!(
     RecvErrorKind,
     "Represents an error returned on message reception failure."
 );
+
+impl SendError {
+    /// Returns `true` in case the error is of the [`SendErrorKind::InvalidReply`] kind.
+    ///
+    /// Since the reply port is always set using a port right wrapper, this usually means the
+    /// reply port was made from a [`RecvRight`] that was released before the message was sent.
+    /// The kernel rejects such messages before transferring any rights, the rights moved into the
+    /// message are released by the send functions in this case.
+    ///
+    /// [`RecvRight`]: crate::rights::RecvRight
+    #[inline]
+    pub const fn is_invalid_reply(self) -> bool {
+        matches!(self.kind(), SendErrorKind::InvalidReply)
+    }
+
+    /// Returns `true` in case the kernel rejected the message before copying in any rights, so the
+    /// rights and the memory referenced by the message are still owned by the sender.
+    pub(crate) const fn rights_not_consumed(self) -> bool {
+        matches!(
+            self.kind(),
+            SendErrorKind::InvalidDest
+                | SendErrorKind::InvalidReply
+                | SendErrorKind::InvalidVoucher
+                | SendErrorKind::InvalidHeader
+                | SendErrorKind::MsgTooSmall
+                | SendErrorKind::NoBuffer
+                | SendErrorKind::TooLarge
+        )
+    }
+}
+
+/// An error returned when a request/reply exchange fails, see [`SendRight::call`].
+///
+/// [`SendRight::call`]: crate::rights::SendRight::call
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[non_exhaustive]
+pub enum CallError {
+    /// Sending the request failed.
+    Send(SendError),
+    /// Receiving the reply failed.
+    Recv(RecvError),
+}
+
+impl From<SendError> for CallError {
+    fn from(error: SendError) -> Self {
+        CallError::Send(error)
+    }
+}
+
+impl From<RecvError> for CallError {
+    fn from(error: RecvError) -> Self {
+        CallError::Recv(error)
+    }
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Send(error) => write!(f, "failed to send the request: {error}"),
+            Self::Recv(error) => write!(f, "failed to receive the reply: {error}"),
+        }
+    }
+}
+
+impl ::std::error::Error for CallError {
+    fn source(&self) -> Option<&(dyn ::std::error::Error + 'static)> {
+        match self {
+            Self::Send(error) => Some(error),
+            Self::Recv(error) => Some(error),
+        }
+    }
+}
+
+/// An error returned when receiving a batch of messages fails, see [`RecvRight::recv_batch`].
+///
+/// The messages received before the failure are returned with the error, so they aren't lost.
+///
+/// [`RecvRight::recv_batch`]: crate::rights::RecvRight::recv_batch
+#[derive(Debug)]
+pub struct RecvBatchError<'buffer> {
+    /// The messages received before the failure.
+    pub batch: Vec<MsgParser<'buffer>>,
+    /// The error receiving the next message.
+    pub error: RecvError,
+}
+
+impl fmt::Display for RecvBatchError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "failed to receive a message after {} messages: {}",
+            self.batch.len(),
+            self.error
+        )
+    }
+}
+
+impl ::std::error::Error for RecvBatchError<'_> {
+    fn source(&self) -> Option<&(dyn ::std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// An error returned when a message fails validation before being parsed.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// The message size in the header is either smaller than the header, unaligned or exceeds the
+    /// size of the data in the buffer.
+    InvalidSize,
+    /// The header contains unexpected bits or port dispositions.
+    InvalidHeaderBits,
+    /// The descriptor count doesn't match the size of the message.
+    InvalidDescriptorCount,
+    /// A descriptor has an unknown type or an unexpected port disposition.
+    InvalidDescriptor,
+    /// The message contains an out-of-line descriptor. These may only be trusted in messages
+    /// received from the kernel since they point to memory in the task's address space.
+    UntrustedOolDescriptor,
+    /// The message names port rights, either in the header or in descriptors. These may only be
+    /// trusted in messages received from the kernel since the names refer to the IPC space of the
+    /// task, see [`MsgParser::from_bytes_with_rights`].
+    ///
+    /// [`MsgParser::from_bytes_with_rights`]: crate::msg::MsgParser::from_bytes_with_rights
+    UntrustedRight,
+    /// The message body is too short to contain the expected data.
+    Truncated,
+    /// The NDR record in the message doesn't match the data representation of the current task.
+    InvalidNdr,
+    /// A string in the message body isn't valid UTF-8.
+    InvalidUtf8,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Self::InvalidSize => "invalid message size",
+            Self::InvalidHeaderBits => "invalid message header bits",
+            Self::InvalidDescriptorCount => "descriptor count doesn't match the message size",
+            Self::InvalidDescriptor => "invalid message descriptor",
+            Self::UntrustedOolDescriptor => "untrusted out-of-line descriptor",
+            Self::UntrustedRight => "untrusted port right",
+            Self::Truncated => "message body is truncated",
+            Self::InvalidNdr => "unsupported NDR record",
+            Self::InvalidUtf8 => "string is not valid UTF-8",
+        };
+        f.write_str(s)
+    }
+}
+
+impl ::std::error::Error for ParseError {}
+
+/// An error returned when data can't be added to a message being built.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// The size of the message or of an out-of-line buffer would exceed `mach_msg_size_t::MAX`.
+    TooLarge,
+    /// The descriptors of the message are misaligned, have unknown types or don't match the
+    /// descriptor count.
+    InvalidLayout,
+    /// A string appended as a C string contains a NUL byte.
+    InteriorNul,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Self::TooLarge => "message size exceeds mach_msg_size_t::MAX",
+            Self::InvalidLayout => "invalid message descriptors layout",
+            Self::InteriorNul => "string contains an interior NUL byte",
+        };
+        f.write_str(s)
+    }
+}
+
+impl ::std::error::Error for BuildError {}
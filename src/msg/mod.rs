@@ -3,48 +3,50 @@
 pub mod buffer;
 pub mod builder;
 pub mod error;
+pub mod ndr;
 pub mod ool;
+pub mod options;
 pub mod parser;
 #[cfg(test)]
 mod tests;
-
-pub use buffer::Buffer;
-pub use builder::Builder;
-pub use error::{RecvError, RecvErrorKind, SendError, SendErrorKind};
+pub mod trailer;
+
+pub use buffer::{Buffer, BufferState, GrowthPolicy};
+pub use builder::{Builder, FinishedMsg};
+pub use error::{
+    BuildError, CallError, ParseError, RecvBatchError, RecvError, RecvErrorKind, SendError,
+    SendErrorKind,
+};
 use mach2::{message::*, port::mach_port_right_t};
+pub use options::RecvOptions;
 pub use parser::*;
+pub use trailer::{MsgTrailer, TrailerRequest};
 
 /// A type for Mach message IDs.
 pub type MsgId = mach_msg_id_t;
 
+/// Set by the kernel in case the message raised the importance of the receiving task.
+const MACH_MSGH_BITS_RAISEIMP: mach_msg_bits_t = 0x2000_0000;
+/// Used by the importance assertion machinery in userland, also used as the circular send
+/// detection flag by the kernel.
+const MACH_MSGH_BITS_IMPHOLDASRT: mach_msg_bits_t = 0x1000_0000;
+/// All the bits that may be present in a message header.
+const MACH_MSGH_BITS_USED: mach_msg_bits_t =
+    MACH_MSGH_BITS_USER | MACH_MSGH_BITS_RAISEIMP | MACH_MSGH_BITS_IMPHOLDASRT;
+
+/// The maximum size of a Mach message as defined in `mach/message.h`.
+const MACH_MSG_SIZE_MAX: mach_msg_size_t = !0;
+
 /// A wrapper for a mach_msg_bits_t value. Provides useful helper methods.
 #[repr(transparent)]
 #[derive(Default, Copy, Clone)]
 struct MachMsgBits(mach_msg_bits_t);
 
 impl MachMsgBits {
-    pub const fn new(
-        complex: bool,
-        remote: mach_port_right_t,
-        local: mach_port_right_t,
-        voucher: mach_port_right_t,
-    ) -> Self {
-        assert!(remote == remote & MACH_MSGH_BITS_REMOTE_MASK);
-        assert!(local == local & MACH_MSGH_BITS_REMOTE_MASK);
-        assert!(voucher == voucher & MACH_MSGH_BITS_REMOTE_MASK);
-
-        let port_bits = remote | (local << 8) | (voucher << 16);
-        if complex {
-            Self(port_bits | MACH_MSGH_BITS_COMPLEX)
-        } else {
-            Self(port_bits)
-        }
-    }
-
     #[inline(always)]
     pub const fn from_bits(value: mach_msg_bits_t) -> Self {
-        assert!(value == (value & MACH_MSGH_BITS_USER));
-
+        // Messages received from the kernel may carry bits other than the user ones (for example
+        // the importance bits) so no checks are performed here.
         MachMsgBits(value)
     }
 
@@ -77,6 +79,13 @@ impl MachMsgBits {
         (self.0 & MACH_MSGH_BITS_VOUCHER_MASK) >> 16
     }
 
+    #[inline(always)]
+    pub const fn set_voucher(self, bits: mach_msg_bits_t) -> Self {
+        assert!(bits == bits & MACH_MSGH_BITS_REMOTE_MASK);
+
+        Self((self.0 & !MACH_MSGH_BITS_VOUCHER_MASK) | (bits << 16))
+    }
+
     #[inline(always)]
     pub const fn complex(self) -> bool {
         (self.0 & MACH_MSGH_BITS_COMPLEX) == MACH_MSGH_BITS_COMPLEX
@@ -86,4 +95,19 @@ impl MachMsgBits {
     pub const fn into_complex(self) -> Self {
         Self(self.0 | MACH_MSGH_BITS_COMPLEX)
     }
+
+    #[inline(always)]
+    pub const fn into_simple(self) -> Self {
+        Self(self.0 & !MACH_MSGH_BITS_COMPLEX)
+    }
+
+    #[inline(always)]
+    pub const fn raise_imp(self) -> bool {
+        (self.0 & MACH_MSGH_BITS_RAISEIMP) == MACH_MSGH_BITS_RAISEIMP
+    }
+
+    #[inline(always)]
+    pub const fn imp_hold_assert(self) -> bool {
+        (self.0 & MACH_MSGH_BITS_IMPHOLDASRT) == MACH_MSGH_BITS_IMPHOLDASRT
+    }
 }
@@ -0,0 +1,28 @@
+//! Contains helpers for NDR records used by MIG-style messages.
+//!
+//! MIG messages carry an `NDR_record_t` describing the byte order and the representation of
+//! integers, characters and floating point values used by the sender. The record is placed at the
+//! start of the inline data, see [`Builder::append_ndr`](crate::msg::Builder::append_ndr) and
+//! [`BodyParser::read_ndr`](crate::msg::BodyParser::read_ndr).
+
+use mach2::ndr::NDR_record;
+pub use mach2::ndr::NDR_record_t;
+use std::mem;
+
+/// The size of an NDR record in bytes.
+pub const NDR_SIZE: usize = mem::size_of::<NDR_record_t>();
+
+/// Returns the NDR record describing the data representation used by the current task.
+#[inline]
+pub fn local_record() -> NDR_record_t {
+    // SAFETY: NDR_record is an immutable static.
+    unsafe { NDR_record }
+}
+
+/// Returns the bytes of the NDR record describing the data representation used by the current
+/// task.
+#[inline]
+pub fn local_bytes() -> [u8; NDR_SIZE] {
+    // SAFETY: NDR_record_t consists of bytes only.
+    unsafe { mem::transmute(local_record()) }
+}
@@ -13,33 +13,317 @@ use std::{
     slice,
 };
 
-mod vm_buf {
-    use mach2::{
-        kern_return::*,
-        traps, vm,
-        vm_prot::{VM_PROT_READ, VM_PROT_WRITE},
-        vm_statistics::VM_FLAGS_ANYWHERE,
-    };
+/// Size and offset computations used by [`VmBuf`] and [`OolVec`].
+///
+/// These are kept free of any VM calls, so that the tests may be run under Miri using
+/// `cargo miri test layout`.
+mod layout {
     use std::{
+        cmp,
         ops::{Add, Rem, Sub},
-        ptr::NonNull,
     };
 
     #[inline(always)]
-    fn align_up<T: Copy + Add<Output = T> + Sub<Output = T> + Rem<Output = T>>(
+    pub fn align_up<T: Copy + Add<Output = T> + Sub<Output = T> + Rem<Output = T>>(
         value: T,
         alignment: T,
     ) -> T {
         value + (alignment - value % alignment) % alignment
     }
 
+    /// Returns the capacity of a buffer rounded up to the end of the last page it occupies.
+    #[inline]
+    pub fn actual_capacity(address: usize, capacity: usize, page_size: usize) -> usize {
+        if capacity > 0 {
+            let offset_in_page = address % page_size;
+            align_up(capacity + offset_in_page, page_size) - offset_in_page
+        } else {
+            0
+        }
+    }
+
+    /// Returns the offset from the start of a buffer and the size of the region that has to be
+    /// deallocated to shrink the buffer to the target capacity.
+    ///
+    /// `None` is returned in case the buffer occupies the same pages after shrinking.
+    ///
+    /// # Panics
+    /// This function will panic in case the target capacity is larger than the current one or the
+    /// page size isn't a power of two.
+    pub fn shrink_range(
+        address: usize,
+        capacity: usize,
+        target_capacity: usize,
+        page_size: usize,
+    ) -> Option<(usize, usize)> {
+        assert!(target_capacity <= capacity);
+        assert!(page_size.is_power_of_two());
+
+        if capacity == 0 {
+            return None;
+        }
+
+        let offset_in_page = address % page_size;
+        let aligned_capacity = actual_capacity(address, capacity, page_size);
+        // Shrinking to zero releases the first page even if the buffer doesn't start at a page
+        // boundary, nothing would deallocate it otherwise.
+        let aligned_target_capacity = if target_capacity > 0 {
+            align_up(target_capacity + offset_in_page, page_size) - offset_in_page
+        } else {
+            0
+        };
+
+        if aligned_target_capacity < aligned_capacity {
+            Some((aligned_target_capacity, capacity - aligned_target_capacity))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the available capacity of a vector.
+    ///
+    /// # Panics
+    /// This function will panic in case the length exceeds the capacity.
+    #[inline(always)]
+    fn available_capacity(len: usize, capacity: usize) -> usize {
+        capacity
+            .checked_sub(len)
+            .expect("vector length exceeds its capacity")
+    }
+
+    /// Returns the length after appending `additional` bytes to a vector or the available capacity
+    /// in case these don't fit.
+    ///
+    /// # Panics
+    /// This function will panic in case the length exceeds the capacity.
+    #[inline]
+    pub fn extended_len(len: usize, capacity: usize, additional: usize) -> Result<usize, usize> {
+        let available_capacity = available_capacity(len, capacity);
+
+        if additional <= available_capacity {
+            // Can't overflow since the result doesn't exceed the capacity.
+            Ok(len + additional)
+        } else {
+            Err(available_capacity)
+        }
+    }
+
+    /// Returns the capacity a vector has to grow to in order to fit `additional` more bytes or
+    /// `None` in case there is enough capacity already.
+    ///
+    /// The vector grows by a half of its capacity, but not past `isize::MAX` unless `additional`
+    /// bytes don't fit otherwise.
+    ///
+    /// # Panics
+    /// This function will panic in case the required capacity overflows `usize` or the length
+    /// exceeds the capacity.
+    #[inline]
+    pub fn grown_capacity(len: usize, capacity: usize, additional: usize) -> Option<usize> {
+        if available_capacity(len, capacity) >= additional {
+            return None;
+        }
+
+        let required_capacity = len.checked_add(additional).unwrap();
+        let grown_capacity = cmp::min(capacity.saturating_add(capacity / 2), isize::MAX as usize);
+
+        Some(cmp::max(grown_capacity, required_capacity))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_align_up() {
+            assert_eq!(align_up(0usize, 0x4000), 0);
+            assert_eq!(align_up(1usize, 0x4000), 0x4000);
+            assert_eq!(align_up(0x4000usize, 0x4000), 0x4000);
+            assert_eq!(align_up(0x4001usize, 0x4000), 0x8000);
+        }
+
+        #[test]
+        fn test_actual_capacity() {
+            const PAGE: usize = 0x4000;
+
+            assert_eq!(actual_capacity(PAGE, 0, PAGE), 0);
+            assert_eq!(actual_capacity(PAGE, 1, PAGE), PAGE);
+            assert_eq!(actual_capacity(PAGE, PAGE, PAGE), PAGE);
+            assert_eq!(actual_capacity(PAGE, PAGE + 1, PAGE), PAGE * 2);
+            assert_eq!(actual_capacity(PAGE + 0x100, 1, PAGE), PAGE - 0x100);
+            assert_eq!(actual_capacity(PAGE + 0x100, PAGE, PAGE), PAGE * 2 - 0x100);
+        }
+
+        #[test]
+        fn test_shrink_range() {
+            const PAGE: usize = 0x4000;
+
+            // Shrinking within the last page doesn't release anything.
+            assert_eq!(shrink_range(PAGE, PAGE * 2, PAGE + 1, PAGE), None);
+            assert_eq!(shrink_range(PAGE, PAGE * 2, PAGE * 2, PAGE), None);
+
+            assert_eq!(shrink_range(PAGE, PAGE * 2, PAGE, PAGE), Some((PAGE, PAGE)));
+            assert_eq!(shrink_range(PAGE, PAGE * 2, 0, PAGE), Some((0, PAGE * 2)));
+            assert_eq!(
+                shrink_range(PAGE, PAGE * 2 - 3, 1, PAGE),
+                Some((PAGE, PAGE - 3))
+            );
+
+            // A buffer starting in the middle of a page keeps the partial first page.
+            let address = PAGE + 0x100;
+            assert_eq!(
+                shrink_range(address, PAGE * 2, 1, PAGE),
+                Some((PAGE - 0x100, PAGE + 0x100))
+            );
+            assert_eq!(
+                shrink_range(address, PAGE * 2, PAGE - 0x100, PAGE),
+                Some((PAGE - 0x100, PAGE + 0x100))
+            );
+            assert_eq!(shrink_range(address, PAGE - 0x100, 1, PAGE), None);
+            assert_eq!(shrink_range(address, 1, 0, PAGE), Some((0, 1)));
+
+            // Empty buffers don't own any memory.
+            assert_eq!(shrink_range(1, 0, 0, PAGE), None);
+        }
+
+        #[test]
+        #[should_panic]
+        fn test_shrink_range_grow() {
+            shrink_range(0, 1, 2, 0x4000);
+        }
+
+        #[test]
+        fn test_extended_len() {
+            assert_eq!(extended_len(0, 0, 0), Ok(0));
+            assert_eq!(extended_len(3, 8, 5), Ok(8));
+            assert_eq!(extended_len(3, 8, 6), Err(5));
+            assert_eq!(extended_len(8, 8, usize::MAX), Err(0));
+
+            // Lengths near the boundary of the address space.
+            let max = usize::MAX;
+            assert_eq!(extended_len(max - 1, max, 1), Ok(max));
+            assert_eq!(extended_len(max - 1, max, 2), Err(1));
+            assert_eq!(extended_len(max, max, 0), Ok(max));
+            assert_eq!(extended_len(max, max, max), Err(0));
+        }
+
+        #[test]
+        #[should_panic]
+        fn test_extended_len_invalid() {
+            let _ = extended_len(2, 1, 0);
+        }
+
+        #[test]
+        fn test_grown_capacity() {
+            assert_eq!(grown_capacity(0, 0, 0), None);
+            assert_eq!(grown_capacity(2, 8, 6), None);
+            assert_eq!(grown_capacity(0, 0, 3), Some(3));
+            assert_eq!(grown_capacity(8, 8, 1), Some(12));
+            assert_eq!(grown_capacity(8, 8, 16), Some(24));
+            assert_eq!(
+                grown_capacity(usize::MAX - 1, usize::MAX - 1, 1),
+                Some(usize::MAX)
+            );
+
+            // The growth stops at isize::MAX.
+            let max = isize::MAX as usize;
+            assert_eq!(grown_capacity(max / 4 * 3, max / 4 * 3, 1), Some(max));
+            assert_eq!(grown_capacity(max - 1, max - 1, 1), Some(max));
+            assert_eq!(grown_capacity(max, max, 1), Some(max + 1));
+            assert_eq!(grown_capacity(max, max, 0), None);
+        }
+
+        #[test]
+        #[should_panic]
+        fn test_grown_capacity_invalid() {
+            grown_capacity(2, 1, 0);
+        }
+
+        #[test]
+        #[should_panic]
+        fn test_grown_capacity_overflow() {
+            grown_capacity(usize::MAX, usize::MAX, 1);
+        }
+    }
+}
+
+mod vm_buf {
+    use super::layout;
+    use mach2::{
+        kern_return::*,
+        traps, vm,
+        vm_prot::{VM_PROT_READ, VM_PROT_WRITE},
+        vm_statistics::VM_FLAGS_ANYWHERE,
+    };
+    use std::{ffi::c_int, marker::PhantomData, ptr::NonNull};
+
+    /// Virtual memory functions used by a [`VmBuf`] to manage its memory.
+    ///
+    /// This allows substituting a mock allocator in tests.
+    pub trait VmAllocator {
+        /// Returns the virtual memory page size.
+        fn page_size() -> usize;
+
+        /// Allocates a zero filled region of the specified non-zero size attributed to a VM tag.
+        ///
+        /// # Panics
+        /// Implementations panic in case the memory can't be allocated.
+        fn allocate(size: usize, tag: u8) -> NonNull<u8>;
+
+        /// Deallocates a memory region.
+        ///
+        /// # Safety
+        /// The region must have been allocated by the allocator and mustn't be accessed anymore.
+        unsafe fn deallocate(address: usize, size: usize) -> kern_return_t;
+    }
+
+    /// The allocator implemented using the Mach VM API.
     #[derive(Debug)]
-    pub struct VmBuf {
+    pub struct MachVm;
+
+    impl VmAllocator for MachVm {
+        #[inline(always)]
+        fn page_size() -> usize {
+            page_size::get_granularity()
+        }
+
+        #[inline(always)]
+        fn allocate(size: usize, tag: u8) -> NonNull<u8> {
+            let mut address = 0;
+            let size = size.try_into().unwrap();
+            // VM_MAKE_TAG(tag)
+            let tag_bits = (tag as c_int) << 24;
+
+            let result = unsafe {
+                vm::mach_vm_allocate(
+                    traps::mach_task_self(),
+                    &mut address,
+                    size,
+                    VM_PROT_READ | VM_PROT_WRITE | VM_FLAGS_ANYWHERE | tag_bits,
+                )
+            };
+
+            assert_eq!(result, KERN_SUCCESS);
+
+            NonNull::new(address as *mut u8).unwrap()
+        }
+
+        #[inline(always)]
+        unsafe fn deallocate(address: usize, size: usize) -> kern_return_t {
+            let address = address.try_into().unwrap();
+            let size = size.try_into().unwrap();
+
+            unsafe { vm::mach_vm_deallocate(traps::mach_task_self(), address, size) }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct VmBuf<A: VmAllocator = MachVm> {
         ptr: NonNull<u8>,
         capacity: usize,
+        _allocator: PhantomData<A>,
     }
 
-    impl VmBuf {
+    impl<A: VmAllocator> VmBuf<A> {
         /// Allocates a new `VmBuf` of the specified size.
         ///
         /// # Panics
@@ -48,35 +332,35 @@ mod vm_buf {
         /// 2. A call to `mach_vm_allocate` returns an error.
         #[inline(always)]
         pub fn alloc(capacity: usize) -> Self {
+            Self::alloc_tagged(capacity, 0)
+        }
+
+        /// Allocates a new `VmBuf` of the specified size attributed to a VM tag.
+        ///
+        /// # Panics
+        /// This function will panic in the same cases as [`VmBuf::alloc`].
+        #[inline(always)]
+        pub fn alloc_tagged(capacity: usize, tag: u8) -> Self {
             let ptr = if capacity > 0 {
                 assert!(capacity <= (isize::MAX as usize));
 
-                let mut address = 0;
-                let size = capacity.try_into().unwrap();
-
-                let result = unsafe {
-                    vm::mach_vm_allocate(
-                        traps::mach_task_self(),
-                        &mut address,
-                        size,
-                        VM_PROT_READ | VM_PROT_WRITE | VM_FLAGS_ANYWHERE,
-                    )
-                };
-
-                assert_eq!(result, KERN_SUCCESS);
-
-                NonNull::new(address as *mut u8).unwrap()
+                A::allocate(capacity, tag)
             } else {
                 NonNull::dangling()
             };
 
-            Self { ptr, capacity }
+            // SAFETY: The memory has just been allocated by the allocator.
+            unsafe { Self::from_raw_parts(ptr, capacity) }
         }
 
         /// Creates a new `VmBuf` from a pointer and a capacity value.
         #[inline]
         pub unsafe fn from_raw_parts(ptr: NonNull<u8>, capacity: usize) -> Self {
-            Self { ptr, capacity }
+            Self {
+                ptr,
+                capacity,
+                _allocator: PhantomData,
+            }
         }
 
         /// Returns the address of the buffer represented by the `VmBuf`.
@@ -93,39 +377,31 @@ mod vm_buf {
             self.capacity
         }
 
+        /// Returns the capacity of the buffer rounded up to the end of the last page it occupies.
+        #[inline]
+        pub fn actual_capacity(&self) -> usize {
+            layout::actual_capacity(self.ptr.addr().get(), self.capacity, A::page_size())
+        }
+
+        /// Sets the capacity of the buffer to the actual one.
+        #[inline]
+        pub fn expand_to_actual(&mut self) {
+            self.capacity = self.actual_capacity();
+        }
+
         pub fn shrink_to(&mut self, target_capacity: usize) {
-            let cur_capacity = self.capacity;
-            let page_size = page_size::get_granularity();
-            let offset_in_page = self.ptr.addr().get() % page_size;
-
-            assert!(target_capacity <= cur_capacity);
-            assert!(page_size.is_power_of_two());
-
-            let aligned_capacity =
-                align_up(cur_capacity + offset_in_page, page_size) - offset_in_page;
-            let aligned_target_capacity =
-                align_up(target_capacity + offset_in_page, page_size) - offset_in_page;
-
-            if aligned_target_capacity < aligned_capacity {
-                let size = (cur_capacity - aligned_target_capacity).try_into().unwrap();
-
-                // SAFETY: This is safe since aligned_target_capacity is less than  aligned_capacity
-                // and aligned_capacity is the actual capacity of the buffer.
-                let result = unsafe {
-                    let address = self
-                        .ptr
-                        .as_ptr()
-                        .addr()
-                        .add(aligned_target_capacity)
-                        .try_into()
-                        .unwrap();
-
-                    vm::mach_vm_deallocate(traps::mach_task_self(), address, size)
-                };
+            let address = self.ptr.addr().get();
+            let range =
+                layout::shrink_range(address, self.capacity, target_capacity, A::page_size());
+
+            if let Some((offset, size)) = range {
+                // SAFETY: This is safe since the range is within the pages occupied by the buffer
+                // and after the target capacity, so it won't be accessed anymore.
+                let result = unsafe { A::deallocate(address + offset, size) };
 
                 assert_eq!(result, KERN_SUCCESS);
 
-                if aligned_target_capacity == 0 {
+                if offset == 0 {
                     self.ptr = NonNull::dangling();
                 }
             }
@@ -140,10 +416,7 @@ mod vm_buf {
         /// implementation.
         unsafe fn dealloc_impl(&mut self) -> kern_return_t {
             if self.capacity > 0 {
-                let address = self.ptr.as_ptr().addr().try_into().unwrap();
-                let size = self.capacity.try_into().unwrap();
-
-                unsafe { vm::mach_vm_deallocate(traps::mach_task_self(), address, size) }
+                unsafe { A::deallocate(self.ptr.as_ptr().addr(), self.capacity) }
             } else {
                 KERN_SUCCESS
             }
@@ -160,16 +433,14 @@ mod vm_buf {
         }
     }
 
-    impl Default for VmBuf {
+    impl<A: VmAllocator> Default for VmBuf<A> {
         fn default() -> Self {
-            Self {
-                ptr: NonNull::dangling(),
-                capacity: 0,
-            }
+            // SAFETY: Empty buffers own no memory.
+            unsafe { Self::from_raw_parts(NonNull::dangling(), 0) }
         }
     }
 
-    impl Drop for VmBuf {
+    impl<A: VmAllocator> Drop for VmBuf<A> {
         #[inline]
         fn drop(&mut self) {
             // SAFETY: this is safe since after drop no one can access the fields of the VmBuf.
@@ -182,18 +453,179 @@ mod vm_buf {
     #[cfg(test)]
     mod tests {
         use super::*;
+        use std::{alloc::Layout, cell::RefCell};
+
+        const HEAP_PAGE_SIZE: usize = 0x4000;
+
+        /// A heap allocation handed out by [`HeapVm`].
+        struct HeapRegion {
+            ptr: NonNull<u8>,
+            layout: Layout,
+            live_pages: Vec<bool>,
+        }
+
+        thread_local! {
+            static REGIONS: RefCell<Vec<HeapRegion>> = const { RefCell::new(Vec::new()) };
+            static DEALLOCATED: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+        }
+
+        /// A heap-backed allocator emulating the page granularity of the VM allocator.
+        ///
+        /// Deallocated regions are recorded and checked to only cover live pages of a previous
+        /// allocation. The heap memory is released once all pages of an allocation are
+        /// deallocated.
+        #[derive(Debug)]
+        struct HeapVm;
+
+        impl VmAllocator for HeapVm {
+            fn page_size() -> usize {
+                HEAP_PAGE_SIZE
+            }
+
+            fn allocate(size: usize, _tag: u8) -> NonNull<u8> {
+                let size = layout::align_up(size, HEAP_PAGE_SIZE);
+                let layout = Layout::from_size_align(size, HEAP_PAGE_SIZE).unwrap();
+                let ptr = NonNull::new(unsafe { std::alloc::alloc_zeroed(layout) }).unwrap();
+
+                REGIONS.with_borrow_mut(|regions| {
+                    regions.push(HeapRegion {
+                        ptr,
+                        layout,
+                        live_pages: vec![true; size / HEAP_PAGE_SIZE],
+                    })
+                });
+
+                ptr
+            }
+
+            unsafe fn deallocate(address: usize, size: usize) -> kern_return_t {
+                DEALLOCATED.with_borrow_mut(|deallocated| deallocated.push((address, size)));
+
+                // The VM allocator deallocates all pages touched by the region.
+                let start = address - address % HEAP_PAGE_SIZE;
+                let end = layout::align_up(address + size, HEAP_PAGE_SIZE);
+
+                REGIONS.with_borrow_mut(|regions| {
+                    let index = regions
+                        .iter()
+                        .position(|region| {
+                            let base = region.ptr.addr().get();
+                            start >= base && end <= base + region.layout.size()
+                        })
+                        .expect("deallocating memory that wasn't allocated");
+                    let region = &mut regions[index];
+                    let first_page = (start - region.ptr.addr().get()) / HEAP_PAGE_SIZE;
+
+                    for live in
+                        &mut region.live_pages[first_page..][..(end - start) / HEAP_PAGE_SIZE]
+                    {
+                        assert!(*live, "deallocating a page twice");
+                        *live = false;
+                    }
+
+                    if region.live_pages.iter().all(|live| !live) {
+                        let region = regions.swap_remove(index);
+                        unsafe { std::alloc::dealloc(region.ptr.as_ptr(), region.layout) };
+                    }
+                });
+
+                KERN_SUCCESS
+            }
+        }
+
+        fn take_deallocated() -> Vec<(usize, usize)> {
+            DEALLOCATED.take()
+        }
+
+        fn live_regions() -> usize {
+            REGIONS.with_borrow(|regions| regions.len())
+        }
 
         #[test]
         #[should_panic]
         fn test_bad_dealloc() {
-            let buf = VmBuf::alloc(page_size::get_granularity());
+            let buf = VmBuf::<MachVm>::alloc(page_size::get_granularity());
             let ptr = buf.as_ptr();
 
             buf.dealloc();
 
-            let bad_buf = unsafe { VmBuf::from_raw_parts(ptr, usize::MAX) };
+            let bad_buf = unsafe { VmBuf::<MachVm>::from_raw_parts(ptr, usize::MAX) };
             bad_buf.dealloc();
         }
+
+        #[test]
+        fn test_heap_shrink_to() {
+            let mut buf = VmBuf::<HeapVm>::alloc(HEAP_PAGE_SIZE * 3);
+            let address = buf.as_ptr().addr().get();
+
+            // The memory is zero filled and writable.
+            let bytes = unsafe { std::slice::from_raw_parts_mut(buf.as_ptr().as_ptr(), 3) };
+            assert_eq!(bytes, [0; 3]);
+            bytes.copy_from_slice(b"abc");
+
+            // The last page is still partially used.
+            buf.shrink_to(HEAP_PAGE_SIZE * 2 + 1);
+            assert_eq!(take_deallocated(), []);
+            assert_eq!(buf.capacity(), HEAP_PAGE_SIZE * 2 + 1);
+
+            buf.shrink_to(HEAP_PAGE_SIZE);
+            assert_eq!(
+                take_deallocated(),
+                [(address + HEAP_PAGE_SIZE, HEAP_PAGE_SIZE + 1)]
+            );
+            assert_eq!(buf.capacity(), HEAP_PAGE_SIZE);
+
+            // The remaining memory is untouched.
+            let bytes = unsafe { std::slice::from_raw_parts(buf.as_ptr().as_ptr(), 3) };
+            assert_eq!(bytes, b"abc");
+
+            // Shrinking to zero releases everything and leaves nothing for drop.
+            buf.shrink_to(0);
+            assert_eq!(take_deallocated(), [(address, HEAP_PAGE_SIZE)]);
+            assert_eq!(buf.as_ptr(), NonNull::dangling());
+            assert_eq!(live_regions(), 0);
+
+            drop(buf);
+            assert_eq!(take_deallocated(), []);
+        }
+
+        #[test]
+        fn test_heap_shrink_to_page_offset() {
+            const OFFSET: usize = 0x100;
+
+            // A buffer starting in the middle of a page, as if it was received in a message.
+            let region = HeapVm::allocate(HEAP_PAGE_SIZE * 3, 0);
+            let ptr = unsafe { region.add(OFFSET) };
+            let address = ptr.addr().get();
+            let mut buf =
+                unsafe { VmBuf::<HeapVm>::from_raw_parts(ptr, HEAP_PAGE_SIZE * 3 - OFFSET) };
+
+            // The first page can't be released while the buffer isn't empty.
+            buf.shrink_to(1);
+            assert_eq!(
+                take_deallocated(),
+                [(address + HEAP_PAGE_SIZE - OFFSET, HEAP_PAGE_SIZE * 2)]
+            );
+            assert_eq!(buf.as_ptr(), ptr);
+
+            buf.shrink_to(1);
+            assert_eq!(take_deallocated(), []);
+
+            buf.shrink_to(0);
+            assert_eq!(take_deallocated(), [(address, 1)]);
+            assert_eq!(buf.as_ptr(), NonNull::dangling());
+            assert_eq!(live_regions(), 0);
+        }
+
+        #[test]
+        fn test_heap_drop() {
+            drop(VmBuf::<HeapVm>::alloc(HEAP_PAGE_SIZE + 1));
+            assert_eq!(take_deallocated().len(), 1);
+            assert_eq!(live_regions(), 0);
+
+            drop(VmBuf::<HeapVm>::alloc(0));
+            assert_eq!(take_deallocated(), []);
+        }
     }
 }
 
@@ -202,6 +634,18 @@ use vm_buf::VmBuf;
 /// A byte buffer backed by the Mach VM allocator.
 ///
 /// It's intended to be used to represent out-of-line data buffers received in Mach messages.
+///
+/// # Comparisons
+/// The contents of the buffer may be compared with byte slices, arrays and vectors directly:
+///
+/// ```
+/// # use mach_ports::{msg::ool::OolBuf, ool_vec};
+/// let buf = OolBuf::from(ool_vec![1, 2, 3]);
+///
+/// assert_eq!(buf, [1, 2, 3]);
+/// assert_eq!(buf, vec![1, 2, 3]);
+/// assert_eq!(&[1u8, 2, 3][..], buf);
+/// ```
 #[derive(Default, Debug)]
 pub struct OolBuf(VmBuf);
 
@@ -215,11 +659,37 @@ impl OolBuf {
         Self(VmBuf::from_raw_parts(ptr, length))
     }
 
-    pub(crate) fn into_raw_parts(self) -> (NonNull<u8>, usize) {
+    /// Decomposes an [`OolBuf`] into a raw pointer and a length without deallocating the memory.
+    ///
+    /// The caller becomes responsible for the memory, which must eventually be deallocated using
+    /// `mach_vm_deallocate` or passed back to [`OolBuf::from_raw_parts`]. Nothing needs to be
+    /// deallocated in case the length is zero since the pointer is dangling then.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::ool::OolBuf, ool_vec};
+    /// let buf = OolBuf::from(ool_vec![1, 2, 3]);
+    /// let (ptr, len) = buf.into_raw_parts();
+    ///
+    /// let buf = unsafe { OolBuf::from_raw_parts(ptr, len) };
+    /// assert_eq!(buf, [1, 2, 3]);
+    /// ```
+    pub fn into_raw_parts(self) -> (NonNull<u8>, usize) {
         let buf = ManuallyDrop::new(self);
         (buf.0.as_ptr(), buf.0.capacity())
     }
 
+    /// Consumes the buffer and leaks the memory returning a mutable reference to the contents.
+    ///
+    /// The memory stays mapped until the task exits unless it's deallocated manually using
+    /// `mach_vm_deallocate`.
+    pub fn leak(self) -> &'static mut [u8] {
+        let (ptr, len) = self.into_raw_parts();
+
+        // SAFETY: The memory is never deallocated by the crate after this point.
+        unsafe { slice::from_raw_parts_mut(ptr.as_ptr(), len) }
+    }
+
     /// Returns a raw pointer to the buffer, or a dangling raw pointer valid for zero sized reads if
     /// the buffer's capacity is zero.
     pub fn as_ptr(&self) -> NonNull<u8> {
@@ -245,6 +715,9 @@ impl OolBuf {
     }
 
     /// Returns `true` if the buffer is empty.
+    ///
+    /// Empty buffers don't own any memory and use a dangling pointer, so dropping them never calls
+    /// `mach_vm_deallocate`.
     #[inline]
     pub fn is_empty(&self) -> bool {
         self.len() == 0
@@ -264,6 +737,15 @@ impl From<OolVec> for OolBuf {
     }
 }
 
+/// Copies the contents into a heap allocated vector deallocating the VM buffer.
+///
+/// The memory can't be handed over since the buffers use different allocators.
+impl From<OolBuf> for Vec<u8> {
+    fn from(value: OolBuf) -> Self {
+        value.as_slice().to_vec()
+    }
+}
+
 impl PartialEq for OolBuf {
     fn eq(&self, other: &Self) -> bool {
         self.as_slice().eq(other.as_slice())
@@ -320,10 +802,32 @@ impl DerefMut for OolBuf {
     }
 }
 
+/// The operation that failed with a [`NotEnoughCapacity`] error.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[non_exhaustive]
+pub enum CapacityErrorKind {
+    /// Pushing a single byte to the vector.
+    Push,
+    /// Extending the vector with a byte slice.
+    Extend,
+    /// Resizing the vector to a new length.
+    Resize,
+}
+
 /// An error returned when an [`OolVec`] doesn't have enough capacity.
+///
+/// The meaning of the capacity fields depends on the failed operation:
+///
+/// * For [`Push`](CapacityErrorKind::Push) and [`Extend`](CapacityErrorKind::Extend),
+///   `required_capacity` is the number of bytes being appended and `available_capacity` is the
+///   capacity left after the current contents of the vector.
+/// * For [`Resize`](CapacityErrorKind::Resize), `required_capacity` is the requested length and
+///   `available_capacity` is the total capacity of the vector.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 #[non_exhaustive]
 pub struct NotEnoughCapacity {
+    /// The operation that failed.
+    pub kind: CapacityErrorKind,
     /// The capacity that was expected to be available by an extension operation.
     pub required_capacity: usize,
     /// The actual capacity currently available in the OOL buffer.
@@ -332,11 +836,23 @@ pub struct NotEnoughCapacity {
 
 impl fmt::Display for NotEnoughCapacity {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "could not extend OOL buffer with {} bytes as the available capacity is {}",
-            self.required_capacity, self.available_capacity
-        )
+        match self.kind {
+            CapacityErrorKind::Push => write!(
+                f,
+                "could not push a byte to OOL buffer as the available capacity is {}",
+                self.available_capacity
+            ),
+            CapacityErrorKind::Extend => write!(
+                f,
+                "could not extend OOL buffer with {} bytes as the available capacity is {}",
+                self.required_capacity, self.available_capacity
+            ),
+            CapacityErrorKind::Resize => write!(
+                f,
+                "could not resize OOL buffer to {} bytes as its capacity is {}",
+                self.required_capacity, self.available_capacity
+            ),
+        }
     }
 }
 
@@ -348,12 +864,24 @@ impl Error for NotEnoughCapacity {}
 /// is a buffer that can be constructed in a more or less zero-cost
 ///
 /// # Resizing the vector
-/// Currently the capacity of the vector can only be decreased. Technically implementing growing
-/// isn't very complicated, but the only use case for the capacity change I came up with is
-/// truncating an overly large vector that is passed as an out-of-line buffer in a Mach message
-/// with the deallocate flag set to `true`. This isn't even the best way to handle such a case since
-/// freeing the buffer will require a call to `mach_vm_deallocate` and freeing a part using
-/// `mach_msg` doesn't make a lot of sense.
+/// The vector never grows implicitly, functions that append data panic or return an error in case
+/// there is not enough capacity. The capacity may be increased explicitly using
+/// [`OolVec::reserve`], which allocates a new buffer and copies the contents since Mach VM
+/// allocations can't be extended in place. Decreasing the capacity is useful for truncating an
+/// overly large vector that is passed as an out-of-line buffer in a Mach message with the
+/// deallocate flag set to `true`.
+///
+/// # Comparisons
+/// The contents of the vector may be compared with byte slices, arrays and vectors directly:
+///
+/// ```
+/// # use mach_ports::ool_vec;
+/// let v = ool_vec![1, 2, 3];
+///
+/// assert_eq!(v, [1, 2, 3]);
+/// assert_eq!(v, vec![1, 2, 3]);
+/// assert_eq!(&[1u8, 2, 3][..], v);
+/// ```
 #[derive(Default, Debug)]
 pub struct OolVec {
     buf: VmBuf,
@@ -375,6 +903,63 @@ impl OolVec {
         }
     }
 
+    /// Allocates a new vector with the specified capacity attributing the memory to a VM tag.
+    ///
+    /// The tag is one of the `VM_MEMORY_*` values from `mach/vm_statistics.h` or a value in the
+    /// `VM_MEMORY_APPLICATION_SPECIFIC_1..=VM_MEMORY_APPLICATION_SPECIFIC_16` range (240 to 255),
+    /// it's reported by memory tools like `vmmap`. Zero leaves the memory untagged. The tag only
+    /// applies to this allocation, memory allocated when the vector grows is untagged.
+    ///
+    /// # Panics
+    /// This function will panic in the same cases as [`OolVec::with_capacity`] or in case the tag
+    /// is larger than 255.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::msg::ool::OolVec;
+    /// // VM_MEMORY_APPLICATION_SPECIFIC_1
+    /// let mut v = OolVec::with_capacity_tagged(1024, 240);
+    /// v.extend_from_slice(b"test");
+    ///
+    /// assert_eq!(v, *b"test");
+    /// ```
+    pub fn with_capacity_tagged(capacity: usize, tag: u32) -> Self {
+        Self {
+            buf: VmBuf::alloc_tagged(capacity, tag.try_into().unwrap()),
+            len: 0,
+        }
+    }
+
+    /// Allocates a new vector with the specified capacity and fills it with a value, so that its
+    /// length equals the capacity.
+    ///
+    /// Memory returned by `mach_vm_allocate` is zero filled, so filling the vector with zeroes
+    /// doesn't touch the pages.
+    ///
+    /// # Panics
+    /// This function will panic in the same cases as [`OolVec::with_capacity`].
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::msg::ool::OolVec;
+    /// let v = OolVec::with_capacity_filled(4, 0xAA);
+    ///
+    /// assert_eq!(v.as_slice(), &[0xAA; 4]);
+    /// ```
+    pub fn with_capacity_filled(capacity: usize, value: u8) -> Self {
+        let mut v = Self::with_capacity(capacity);
+
+        if value != 0 {
+            // SAFETY: The allocated buffer is exactly capacity bytes long.
+            unsafe {
+                slice::from_raw_parts_mut(v.as_ptr().as_ptr(), capacity).fill(value);
+            }
+        }
+
+        v.len = capacity;
+        v
+    }
+
     /// Creates an [`OolVec`] from a pointer, a length and a capacity.
     ///
     /// # Safety
@@ -389,6 +974,40 @@ impl OolVec {
         }
     }
 
+    /// Decomposes an [`OolVec`] into a raw pointer, a length and a capacity without deallocating
+    /// the memory.
+    ///
+    /// The caller becomes responsible for the memory, which must eventually be deallocated using
+    /// `mach_vm_deallocate` with the returned capacity or passed back to
+    /// [`OolVec::from_raw_parts`]. Nothing needs to be deallocated in case the capacity is zero
+    /// since the pointer is dangling then.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::ool::OolVec, ool_vec};
+    /// let v = ool_vec![1, 2, 3; 1024];
+    /// let (ptr, len, capacity) = v.into_raw_parts();
+    ///
+    /// let v = unsafe { OolVec::from_raw_parts(ptr, len, capacity) };
+    /// assert_eq!(v, [1, 2, 3]);
+    /// assert_eq!(v.capacity(), 1024);
+    /// ```
+    pub fn into_raw_parts(self) -> (NonNull<u8>, usize, usize) {
+        let v = ManuallyDrop::new(self);
+        (v.buf.as_ptr(), v.len, v.buf.capacity())
+    }
+
+    /// Consumes the vector and leaks the memory returning a mutable reference to the contents.
+    ///
+    /// The spare capacity isn't released. The memory stays mapped until the task exits unless
+    /// it's deallocated manually using `mach_vm_deallocate`.
+    pub fn leak(self) -> &'static mut [u8] {
+        let (ptr, len, _) = self.into_raw_parts();
+
+        // SAFETY: The memory is never deallocated by the crate after this point.
+        unsafe { slice::from_raw_parts_mut(ptr.as_ptr(), len) }
+    }
+
     /// Returns a raw pointer to the buffer, or a dangling raw pointer valid for zero sized reads if
     /// the buffer's capacity is zero.
     pub fn as_ptr(&self) -> NonNull<u8> {
@@ -437,6 +1056,40 @@ impl OolVec {
         self.buf.capacity()
     }
 
+    /// Returns the capacity of the buffer rounded up to the virtual memory page size.
+    ///
+    /// The memory is allocated in whole pages, so the bytes past the capacity up to the actual
+    /// capacity are accessible as well. These may be used after calling
+    /// [`OolVec::expand_to_actual_capacity`].
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::msg::ool::OolVec;
+    /// let v = OolVec::with_capacity(1);
+    ///
+    /// assert_eq!(v.actual_capacity(), page_size::get_granularity());
+    /// ```
+    #[inline]
+    pub fn actual_capacity(&self) -> usize {
+        self.buf.actual_capacity()
+    }
+
+    /// Sets the capacity of the vector to [`OolVec::actual_capacity`] without reallocating.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::msg::ool::OolVec;
+    /// let mut v = OolVec::with_capacity(1);
+    /// v.expand_to_actual_capacity();
+    /// v.resize(v.capacity(), 0x55).unwrap();
+    ///
+    /// assert_eq!(v.len(), page_size::get_granularity());
+    /// ```
+    #[inline]
+    pub fn expand_to_actual_capacity(&mut self) {
+        self.buf.expand_to_actual();
+    }
+
     /// Returns `true` if the buffer is empty.
     #[inline]
     pub fn is_empty(&self) -> bool {
@@ -446,21 +1099,19 @@ impl OolVec {
     /// Sets the new length of the buffer in bytes.
     ///
     /// # Safety
-    /// The caller must ensure that the length is less than the buffer's capacity and that the
+    /// The caller must ensure that the length doesn't exceed the buffer's capacity and that the
     /// contents of the buffer are not read until being initialized.
     #[inline]
     pub unsafe fn set_len(&mut self, new_len: usize) {
-        assert!(new_len < self.capacity());
+        assert!(new_len <= self.capacity());
 
         self.len = new_len;
     }
 
     /// Tries to extend the vector with bytes from a byte slice.
     fn try_extend_from_slice(&mut self, slice: &[u8]) -> Result<(), NotEnoughCapacity> {
-        let available_capacity = self.capacity() - self.len;
-
-        if slice.len() <= available_capacity {
-            unsafe {
+        match layout::extended_len(self.len, self.capacity(), slice.len()) {
+            Ok(new_len) => unsafe {
                 // SAFETY: This is safe since the length is always less than th capacity of the buffer,
                 // the capacity is less than isize::MAX and since the buffer has been allocated the
                 // address will not overflow.
@@ -472,23 +1123,26 @@ impl OolVec {
                 // because everything after the length can't be accessed without calling set_len.
                 ptr::copy_nonoverlapping(slice.as_ptr(), dst, slice.len());
 
-                // SAFETY: This is safe since we've just initialized slice.len() bytes. The addition
-                // can not overflow since the result is verified to be less than the current
-                // capacity.
-                self.len += slice.len();
-            };
+                // SAFETY: This is safe since we've just initialized slice.len() bytes. The new
+                // length is verified to be less than the current capacity.
+                self.len = new_len;
 
-            Ok(())
-        } else {
-            Err(NotEnoughCapacity {
+                Ok(())
+            },
+            Err(available_capacity) => Err(NotEnoughCapacity {
+                kind: CapacityErrorKind::Extend,
                 required_capacity: slice.len(),
                 available_capacity,
-            })
+            }),
         }
     }
 
     fn try_push(&mut self, value: u8) -> Result<(), NotEnoughCapacity> {
         self.try_extend_from_slice(&[value])
+            .map_err(|error| NotEnoughCapacity {
+                kind: CapacityErrorKind::Push,
+                ..error
+            })
     }
 
     /// Extends a vector with contents of a byte slice.
@@ -509,6 +1163,44 @@ impl OolVec {
         self.try_extend_from_slice(slice).unwrap();
     }
 
+    /// Extends a vector with contents of an out-of-line buffer growing the vector if required.
+    ///
+    /// The contents are copied since the memory of two VM allocations can't be merged.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::ool::OolBuf, ool_vec};
+    /// let mut v = ool_vec![1, 2, 3];
+    /// let buf = OolBuf::from(ool_vec![4, 5, 6]);
+    ///
+    /// v.extend_from_ool_buf(&buf);
+    ///
+    /// assert_eq!(v.as_slice(), &[1, 2, 3, 4, 5, 6]);
+    /// ```
+    pub fn extend_from_ool_buf(&mut self, other: &OolBuf) {
+        self.reserve(other.len());
+        self.extend_from_slice(other.as_slice());
+    }
+
+    /// Reserves capacity for at least `additional` more bytes.
+    ///
+    /// In case the available capacity is insufficient a new buffer is allocated and the contents of
+    /// the vector are copied into it.
+    ///
+    /// # Panics
+    /// This function will panic in case the new capacity is larger than [`isize::MAX`] or a call to
+    /// `mach_vm_allocate` returns an error.
+    pub fn reserve(&mut self, additional: usize) {
+        let Some(new_capacity) = layout::grown_capacity(self.len, self.capacity(), additional)
+        else {
+            return;
+        };
+        let mut new_vec = Self::with_capacity(new_capacity);
+
+        new_vec.extend_from_slice(self.as_slice());
+        *self = new_vec;
+    }
+
     /// Pushes a byte to the end of the vector.
     ///
     /// # Panics
@@ -561,6 +1253,7 @@ impl OolVec {
             Ok(())
         } else {
             Err(NotEnoughCapacity {
+                kind: CapacityErrorKind::Resize,
                 required_capacity: new_len,
                 available_capacity,
             })
@@ -589,6 +1282,144 @@ impl OolVec {
         Some(value)
     }
 
+    /// Inserts a byte at the specified position shifting all the bytes after it to the right.
+    ///
+    /// The vector grows in case there is no available capacity, see [`OolVec::reserve`].
+    ///
+    /// # Panics
+    /// This function will panic in case the index is greater than the length of the vector.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::ool_vec;
+    /// let mut v = ool_vec![1, 2, 4];
+    ///
+    /// v.insert(2, 3);
+    /// v.insert(4, 5);
+    /// assert_eq!(v.as_slice(), &[1, 2, 3, 4, 5]);
+    /// ```
+    pub fn insert(&mut self, index: usize, value: u8) {
+        let len = self.len;
+        assert!(index <= len, "insert index {} is out of bounds", index);
+
+        if len == self.capacity() {
+            self.reserve(1);
+        }
+
+        // SAFETY: The index is in bounds and there is capacity for one more byte, so both the
+        // source and the destination ranges are within the allocation.
+        unsafe {
+            let ptr = self.as_ptr().as_ptr().add(index);
+
+            ptr::copy(ptr, ptr.add(1), len - index);
+            ptr.write(value);
+        }
+
+        self.len += 1;
+    }
+
+    /// Removes a byte at the specified position shifting all the bytes after it to the left and
+    /// returns the byte.
+    ///
+    /// # Panics
+    /// This function will panic in case the index is out of bounds.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::ool_vec;
+    /// let mut v = ool_vec![1, 2, 3];
+    ///
+    /// assert_eq!(v.remove(1), 2);
+    /// assert_eq!(v.as_slice(), &[1, 3]);
+    /// ```
+    pub fn remove(&mut self, index: usize) -> u8 {
+        let len = self.len;
+        assert!(index < len, "removal index {} is out of bounds", index);
+
+        // SAFETY: The index is in bounds, so both ranges are within the initialized bytes.
+        let value = unsafe {
+            let ptr = self.as_ptr().as_ptr().add(index);
+            let value = ptr.read();
+
+            ptr::copy(ptr.add(1), ptr, len - index - 1);
+            value
+        };
+
+        self.len -= 1;
+
+        value
+    }
+
+    /// Removes a byte from the vector and returns it replacing it with the last byte.
+    ///
+    /// # Panics
+    /// This function will panic in case the index is out of bounds.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::ool_vec;
+    /// let mut v = ool_vec![1, 2, 3, 4];
+    ///
+    /// assert_eq!(v.swap_remove(1), 2);
+    /// assert_eq!(v.as_slice(), &[1, 4, 3]);
+    /// ```
+    pub fn swap_remove(&mut self, index: usize) -> u8 {
+        let len = self.len;
+        assert!(index < len, "swap_remove index {} is out of bounds", index);
+
+        let slice = self.as_slice_mut();
+        let value = slice[index];
+        slice[index] = slice[len - 1];
+        self.len -= 1;
+
+        value
+    }
+
+    /// Retains only the bytes for which the predicate returns `true`.
+    ///
+    /// The bytes are compacted in place preserving their order, the capacity of the vector isn't
+    /// affected.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::ool_vec;
+    /// let mut v = ool_vec![1, 2, 3, 4, 5];
+    ///
+    /// v.retain(|byte| byte % 2 == 1);
+    /// assert_eq!(v.as_slice(), &[1, 3, 5]);
+    /// ```
+    pub fn retain<F: FnMut(u8) -> bool>(&mut self, mut f: F) {
+        let slice = self.as_slice_mut();
+        let mut retained = 0;
+
+        for index in 0..slice.len() {
+            let value = slice[index];
+
+            if f(value) {
+                slice[retained] = value;
+                retained += 1;
+            }
+        }
+
+        self.len = retained;
+    }
+
+    /// Removes consecutive repeated bytes from the vector.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::ool_vec;
+    /// let mut v = ool_vec![1, 1, 2, 2, 2, 1, 3];
+    ///
+    /// v.dedup();
+    /// assert_eq!(v.as_slice(), &[1, 2, 1, 3]);
+    /// ```
+    pub fn dedup(&mut self) {
+        let mut previous = None;
+
+        self.retain(|value| previous.replace(value) != Some(value));
+    }
+
     /// Shrinks the vector to the smallest capacity that can hold the stored data.
     pub fn shrink_to_fit(&mut self) {
         self.buf.shrink_to(self.len);
@@ -618,6 +1449,31 @@ impl From<&'_ [u8]> for OolVec {
     }
 }
 
+/// Allocates an [`OolVec`] with the capacity of `N` bytes and copies the array into it.
+///
+/// # Example
+/// ```
+/// # use mach_ports::msg::ool::OolVec;
+/// let v = OolVec::from([1u8, 2, 3]);
+///
+/// assert_eq!(v.as_slice(), &[1, 2, 3]);
+/// assert_eq!(v.capacity(), 3);
+/// ```
+impl<const N: usize> From<[u8; N]> for OolVec {
+    fn from(value: [u8; N]) -> Self {
+        OolVec::from(value.as_slice())
+    }
+}
+
+/// Copies the contents into a heap allocated vector deallocating the VM buffer.
+///
+/// The memory can't be handed over since the buffers use different allocators.
+impl From<OolVec> for Vec<u8> {
+    fn from(value: OolVec) -> Self {
+        value.as_slice().to_vec()
+    }
+}
+
 impl PartialEq for OolVec {
     fn eq(&self, other: &Self) -> bool {
         self.as_slice().eq(other.as_slice())
@@ -682,6 +1538,34 @@ impl Extend<u8> for OolVec {
     }
 }
 
+/// Implements comparisons of an OOL data type against byte slices, arrays and vectors in both
+/// directions.
+macro_rules! impl_slice_eq {
+    ($ty:ty) => {
+        impl_slice_eq!(@impl [] $ty, [u8]);
+        impl_slice_eq!(@impl [] $ty, &[u8]);
+        impl_slice_eq!(@impl [] $ty, Vec<u8>);
+        impl_slice_eq!(@impl [const N: usize] $ty, [u8; N]);
+        impl_slice_eq!(@impl [const N: usize] $ty, &[u8; N]);
+    };
+    (@impl [$($gen:tt)*] $ty:ty, $other:ty) => {
+        impl<$($gen)*> PartialEq<$other> for $ty {
+            fn eq(&self, other: &$other) -> bool {
+                self.as_slice() == &other[..]
+            }
+        }
+
+        impl<$($gen)*> PartialEq<$ty> for $other {
+            fn eq(&self, other: &$ty) -> bool {
+                &self[..] == other.as_slice()
+            }
+        }
+    };
+}
+
+impl_slice_eq!(OolBuf);
+impl_slice_eq!(OolVec);
+
 /// Creates an [`OolVec`] from a list of elements and optionally a capacity value.
 ///
 /// # Examples
@@ -722,3 +1606,205 @@ macro_rules! ool_vec {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_raw_parts() {
+        let page_size = page_size::get_granularity();
+        let mut v = OolVec::with_capacity(page_size * 2);
+        v.extend_from_slice(&[0x55; 16]);
+
+        let (ptr, len, capacity) = v.into_raw_parts();
+        assert_eq!((len, capacity), (16, page_size * 2));
+
+        let v = unsafe { OolVec::from_raw_parts(ptr, len, capacity) };
+        assert_eq!(v, [0x55; 16]);
+        assert_eq!(v.as_ptr(), ptr);
+
+        let buf = OolBuf::from(v);
+        let (ptr, len) = buf.into_raw_parts();
+        assert_eq!(len, 16);
+
+        let buf = unsafe { OolBuf::from_raw_parts(ptr, len) };
+        assert_eq!(buf, [0x55; 16]);
+
+        let (ptr, len, capacity) = OolVec::default().into_raw_parts();
+        assert_eq!((ptr, len, capacity), (NonNull::dangling(), 0, 0));
+    }
+
+    #[test]
+    fn test_extend_from_ool_buf() {
+        let page_size = page_size::get_granularity();
+        let first = OolBuf::from(OolVec::from(vec![0x55u8; page_size + 3].as_slice()));
+        let second = OolBuf::from(OolVec::from(vec![0xAAu8; page_size * 2].as_slice()));
+
+        let mut v = OolVec::with_capacity(first.len());
+        v.extend_from_ool_buf(&first);
+        assert_eq!(v.capacity(), first.len());
+
+        // The second buffer doesn't fit into the initial capacity.
+        v.extend_from_ool_buf(&second);
+
+        assert_eq!(v.len(), first.len() + second.len());
+        assert_eq!(&v[..first.len()], first.as_slice());
+        assert_eq!(&v[first.len()..], second.as_slice());
+    }
+
+    #[test]
+    fn test_not_enough_capacity() {
+        let mut v = OolVec::from([1, 2, 3]);
+        let capacity = v.capacity();
+
+        let error = v.try_push(4).unwrap_err();
+        assert_eq!(error.kind, CapacityErrorKind::Push);
+        assert_eq!(error.required_capacity, 1);
+        assert_eq!(error.available_capacity, capacity - 3);
+
+        let error = v.try_extend_from_slice(&[4, 5]).unwrap_err();
+        assert_eq!(error.kind, CapacityErrorKind::Extend);
+        assert_eq!(error.required_capacity, 2);
+        assert_eq!(error.available_capacity, capacity - 3);
+
+        v.pop();
+        let error = v.resize(capacity + 2, 0).unwrap_err();
+        assert_eq!(error.kind, CapacityErrorKind::Resize);
+        assert_eq!(error.required_capacity, capacity + 2);
+        assert_eq!(error.available_capacity, capacity);
+
+        // Failed operations must not change the contents.
+        assert_eq!(v.as_slice(), &[1, 2]);
+
+        // Lengths which overflow when added to the current length.
+        let error = v.resize(usize::MAX, 0).unwrap_err();
+        assert_eq!(error.available_capacity, capacity);
+
+        v.extend_from_slice(&[3]);
+        assert_eq!(v.len(), capacity);
+        v.extend_from_slice(&[]);
+        let error = v.try_push(4).unwrap_err();
+        assert_eq!(error.available_capacity, 0);
+    }
+
+    #[test]
+    fn test_retain() {
+        let page_size = page_size::get_granularity();
+        let mut v = OolVec::with_capacity(page_size * 2);
+        v.extend((0..=255).cycle().take(page_size + 3));
+        let ptr = v.as_ptr();
+
+        v.retain(|byte| byte % 2 == 0);
+
+        assert_eq!(v.len(), (page_size + 3).div_ceil(2));
+        assert!(v
+            .iter()
+            .copied()
+            .eq((0..=255).step_by(2).cycle().take(v.len())));
+        // The bytes are compacted without reallocating.
+        assert_eq!(v.as_ptr(), ptr);
+        assert_eq!(v.capacity(), page_size * 2);
+
+        v.retain(|_| false);
+        assert!(v.is_empty());
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let page_size = page_size::get_granularity();
+        let mut v = OolVec::with_capacity_filled(page_size, 0x55);
+        v.resize(page_size - 1, 0).unwrap();
+        let middle = v.len() / 2;
+
+        v.insert(middle, 0xAA);
+        assert_eq!(v.len(), page_size);
+        assert_eq!(v[middle], 0xAA);
+        assert!(v[..middle]
+            .iter()
+            .chain(&v[middle + 1..])
+            .all(|&byte| byte == 0x55));
+
+        // The vector is full, so inserting requires growing.
+        v.insert(middle, 0xBB);
+        assert_eq!(v.len(), page_size + 1);
+        assert!(v.capacity() > page_size);
+        assert_eq!(v[middle..middle + 2], [0xBB, 0xAA]);
+
+        assert_eq!(v.remove(middle + 1), 0xAA);
+        assert_eq!(v.remove(middle), 0xBB);
+        assert_eq!(v.len(), page_size - 1);
+        assert!(v.iter().all(|&byte| byte == 0x55));
+    }
+
+    #[test]
+    fn test_into_heap_vec() {
+        let page_size = page_size::get_granularity();
+        let data = vec![0x55u8; page_size + 3];
+
+        assert_eq!(Vec::from(OolVec::from(data.as_slice())), data);
+        assert_eq!(Vec::from(OolBuf::from(OolVec::from(data.as_slice()))), data);
+        assert_eq!(Vec::from(OolVec::default()), Vec::<u8>::new());
+        assert_eq!(Vec::from(OolBuf::default()), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_actual_capacity() {
+        let page_size = page_size::get_granularity();
+        let mut v = OolVec::with_capacity(1);
+        v.push(0x55);
+        let ptr = v.as_ptr();
+
+        assert_eq!(v.capacity(), 1);
+        assert_eq!(v.actual_capacity(), page_size);
+
+        v.expand_to_actual_capacity();
+        assert_eq!(v.capacity(), page_size);
+        assert_eq!(v.as_ptr(), ptr);
+
+        v.resize(page_size, 0xAA).unwrap();
+        assert_eq!(v[0], 0x55);
+        assert!(v[1..].iter().all(|&byte| byte == 0xAA));
+
+        assert_eq!(OolVec::default().actual_capacity(), 0);
+    }
+
+    #[test]
+    fn test_with_capacity_tagged() {
+        const VM_MEMORY_APPLICATION_SPECIFIC_16: u32 = 255;
+
+        let page_size = page_size::get_granularity();
+        let mut v = OolVec::with_capacity_tagged(page_size * 2, VM_MEMORY_APPLICATION_SPECIFIC_16);
+        assert_eq!(v.capacity(), page_size * 2);
+
+        v.resize(page_size + 1, 0x55).unwrap();
+        assert!(v.iter().all(|&byte| byte == 0x55));
+
+        // Growing the vector moves the contents into an untagged allocation.
+        v.reserve(page_size * 2);
+        assert_eq!(v.len(), page_size + 1);
+
+        assert!(OolVec::with_capacity_tagged(0, VM_MEMORY_APPLICATION_SPECIFIC_16).is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_with_capacity_tagged_invalid() {
+        OolVec::with_capacity_tagged(1, 256);
+    }
+
+    #[test]
+    fn test_with_capacity_filled() {
+        let capacity = page_size::get_granularity() * 2 + 5;
+
+        for value in [0, 0x55] {
+            let v = OolVec::with_capacity_filled(capacity, value);
+
+            assert_eq!(v.len(), capacity);
+            assert_eq!(v.capacity(), capacity);
+            assert!(v.iter().all(|&byte| byte == value));
+        }
+
+        assert!(OolVec::with_capacity_filled(0, 0xAA).is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+//! Provides the [`RecvOptions`] structure used to alter the way Mach messages are received.
+
+use crate::msg::trailer::TrailerRequest;
+use mach2::message::*;
+
+/// Options that may be specified when receiving a Mach message.
+///
+/// # Example
+/// ```
+/// # use mach_ports::msg::RecvOptions;
+/// # use mach2::message::MACH_RCV_TRAILER_AUDIT;
+/// // Request the kernel to append a trailer containing the sender's audit token.
+/// let options = RecvOptions::new().trailer(MACH_RCV_TRAILER_AUDIT);
+/// ```
+#[derive(Copy, Clone, Default, Debug)]
+pub struct RecvOptions {
+    trailer_elements: mach_msg_trailer_type_t,
+    voucher: bool,
+    ool_vec: bool,
+    guarded_desc: bool,
+}
+
+impl RecvOptions {
+    /// Creates the default receive options requesting only the minimal trailer.
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            trailer_elements: MACH_RCV_TRAILER_NULL,
+            voucher: false,
+            ool_vec: false,
+            guarded_desc: false,
+        }
+    }
+
+    /// Requests the kernel to append a trailer containing all elements up to the specified one.
+    ///
+    /// The elements are specified using one of the `MACH_RCV_TRAILER_*` constants from
+    /// [`mach2::message`]. See [`MsgTrailer`](super::MsgTrailer) for accessors for the received
+    /// elements.
+    ///
+    /// # Panics
+    /// This function will panic in case the value is larger than `MACH_RCV_TRAILER_LABELS`.
+    #[inline]
+    pub const fn trailer(mut self, elements: mach_msg_trailer_type_t) -> Self {
+        assert!(elements <= MACH_RCV_TRAILER_LABELS);
+
+        self.trailer_elements = elements;
+        self
+    }
+
+    /// Requests the kernel to append a trailer containing the specified elements.
+    ///
+    /// This is equivalent to calling [`RecvOptions::trailer`] with [`TrailerRequest::elements`].
+    /// The raw bytes of the received elements may be retrieved using
+    /// [`MsgTrailer::bytes`](super::MsgTrailer::bytes).
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::msg::{RecvOptions, TrailerRequest};
+    /// let options = RecvOptions::new().request_trailer(TrailerRequest::Context);
+    /// ```
+    #[inline]
+    pub const fn request_trailer(self, request: TrailerRequest) -> Self {
+        self.trailer(request.elements())
+    }
+
+    /// Requests the kernel to pass the voucher sent with the message to the receiver.
+    ///
+    /// By default the kernel drops the voucher and [`ParsedMsgHdr::voucher`] is always `None`.
+    ///
+    /// [`ParsedMsgHdr::voucher`]: super::ParsedMsgHdr::voucher
+    #[inline]
+    pub const fn voucher(mut self, receive: bool) -> Self {
+        self.voucher = receive;
+        self
+    }
+
+    /// Makes the parser return out-of-line data as [`ParsedMsgDesc::OolDataVec`] instead of
+    /// [`ParsedMsgDesc::OolData`].
+    ///
+    /// This saves a conversion for receivers that modify the received data in place. The option
+    /// only affects parsing, the kernel isn't aware of it.
+    ///
+    /// [`ParsedMsgDesc::OolDataVec`]: super::ParsedMsgDesc::OolDataVec
+    /// [`ParsedMsgDesc::OolData`]: super::ParsedMsgDesc::OolData
+    #[inline]
+    pub const fn ool_vec(mut self, enabled: bool) -> Self {
+        self.ool_vec = enabled;
+        self
+    }
+
+    /// Requests the kernel to pass guarded receive rights as guarded port descriptors.
+    ///
+    /// These are parsed as [`ParsedMsgDesc::PortGuardedRecv`] keeping the guard set by the sender.
+    /// By default the kernel passes such rights in regular port descriptors.
+    ///
+    /// [`ParsedMsgDesc::PortGuardedRecv`]: super::ParsedMsgDesc::PortGuardedRecv
+    #[inline]
+    pub const fn guarded_desc(mut self, enabled: bool) -> Self {
+        self.guarded_desc = enabled;
+        self
+    }
+
+    #[inline]
+    pub(crate) const fn ool_vec_enabled(self) -> bool {
+        self.ool_vec
+    }
+
+    /// Returns the `mach_msg` option bits representing the options.
+    pub(crate) const fn bits(self) -> mach_msg_option_t {
+        // MACH_RCV_TRAILER_TYPE(MACH_MSG_TRAILER_FORMAT_0) | MACH_RCV_TRAILER_ELEMENTS(elements)
+        let trailer_bits =
+            ((MACH_MSG_TRAILER_FORMAT_0 & 0xf) << 28) | ((self.trailer_elements & 0xf) << 24);
+
+        let voucher_bits = if self.voucher { MACH_RCV_VOUCHER } else { 0 };
+        let guarded_bits = if self.guarded_desc {
+            MACH_RCV_GUARDED_DESC
+        } else {
+            0
+        };
+
+        MACH_RCV_MSG | voucher_bits | guarded_bits | trailer_bits as mach_msg_option_t
+    }
+}
@@ -1,19 +1,33 @@
 //! Contains the implementation of the Mach message parser.
 
 use crate::{
-    msg::{buffer::Buffer, ool::OolBuf, MachMsgBits, MsgId},
-    rights::{AnySendRight, RecvRight, SendOnceRight, SendRight},
+    msg::{
+        buffer::Buffer,
+        ndr::{self, NDR_SIZE},
+        ool::{OolBuf, OolVec},
+        trailer::MsgTrailer,
+        Builder, MachMsgBits, MsgId, ParseError, RecvOptions, SendError, SendErrorKind,
+        MACH_MSGH_BITS_USED,
+    },
+    rights::{AnySendRight, GuardedRecvRight, RecvRight, SendOnceRight, SendRight},
+    traits::AsRawName,
 };
-use mach2::{message::*, port::MACH_PORT_NULL};
-use std::{mem, ptr, ptr::NonNull};
+use mach2::{
+    kern_return::{kern_return_t, KERN_SUCCESS},
+    message::*,
+    port::{mach_port_name_t, MACH_PORT_DEAD, MACH_PORT_NULL},
+    vm_types::mach_port_context_t,
+};
+use std::{mem, ptr, ptr::NonNull, str};
 
-fn size_for_desc_type(type_: mach_msg_descriptor_type_t) -> usize {
+pub(crate) fn size_for_desc_type(type_: mach_msg_descriptor_type_t) -> usize {
     match type_ {
         MACH_MSG_PORT_DESCRIPTOR => mem::size_of::<mach_msg_port_descriptor_t>(),
         MACH_MSG_OOL_DESCRIPTOR | MACH_MSG_OOL_VOLATILE_DESCRIPTOR => {
             mem::size_of::<mach_msg_ool_descriptor_t>()
         }
         MACH_MSG_OOL_PORTS_DESCRIPTOR => mem::size_of::<mach_msg_ool_ports_descriptor_t>(),
+        MACH_MSG_GUARDED_PORT_DESCRIPTOR => mem::size_of::<GuardedPortDescriptor>(),
         _ => unreachable!("unexpected Mach message descriptor type {:#x}", type_),
     }
 }
@@ -34,6 +48,37 @@ unsafe fn anything_from_bytes<T: Sized>(bytes: &[u8]) -> &T {
     &*(bytes.as_ptr() as *const T)
 }
 
+/// The `mach_msg_guarded_port_descriptor_t` structure as defined for 64-bit tasks.
+///
+/// This isn't provided by mach2. Descriptors are packed to 4 bytes in the system headers, so the
+/// context field is unaligned.
+#[repr(C, packed(4))]
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct GuardedPortDescriptor {
+    pub context: mach_port_context_t,
+    pub flags: u16,      // mach_msg_guard_flags_t bitfield
+    pub disposition: u8, // mach_msg_type_name_t bitfield
+    pub type_: u8,       // mach_msg_descriptor_type_t bitfield
+    pub name: mach_port_name_t,
+}
+
+impl GuardedPortDescriptor {
+    pub fn new(
+        name: mach_port_name_t,
+        disposition: mach_msg_type_name_t,
+        context: mach_port_context_t,
+        flags: mach_msg_guard_flags_t,
+    ) -> Self {
+        Self {
+            context,
+            flags: flags.try_into().unwrap(),
+            disposition: disposition as u8,
+            type_: MACH_MSG_GUARDED_PORT_DESCRIPTOR as u8,
+            name,
+        }
+    }
+}
+
 /// Represents a parsed message header.
 #[derive(Debug)]
 pub struct ParsedMsgHdr {
@@ -43,10 +88,115 @@ pub struct ParsedMsgHdr {
     pub reply_right: Option<AnySendRight>,
     /// The voucher port right passed with the message if any.
     pub voucher: Option<SendRight>,
+    /// Whether the message raised the importance of the receiving task.
+    pub importance_raised: bool,
+    /// Whether the importance assertion carried by the message is held by the receiver.
+    pub importance_held: bool,
+    /// The trailer appended to the message by the kernel.
+    pub trailer: MsgTrailer,
+}
+
+impl ParsedMsgHdr {
+    /// Returns the PID of the sender, see [`MsgTrailer::sender_pid`].
+    ///
+    /// This is `None` unless the message was received with the audit trailer requested, e.g.
+    /// using [`TrailerRequest::Audit`](crate::msg::TrailerRequest::Audit).
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder, RecvOptions, TrailerRequest}, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// send_right.send(Builder::new(&mut buffer)).unwrap();
+    ///
+    /// let options = RecvOptions::new().request_trailer(TrailerRequest::Audit);
+    /// let parser = recv_right.recv_with_options(&mut buffer, options).unwrap();
+    /// let (header, _) = parser.parse_header();
+    ///
+    /// assert_eq!(header.sender_pid(), Some(std::process::id() as i32));
+    /// ```
+    #[inline]
+    pub fn sender_pid(&self) -> Option<libc::pid_t> {
+        self.trailer.sender_pid()
+    }
+
+    /// Sends a reply message using the reply right consuming it.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder}, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let reply_recv_right = RecvRight::alloc();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.set_made_reply_port(&reply_recv_right, true);
+    /// recv_right.make_send().send(builder).unwrap();
+    ///
+    /// let (mut header, _) = recv_right.recv(&mut buffer).unwrap().parse_header();
+    /// header.reply_with(Builder::new(&mut buffer)).unwrap();
+    ///
+    /// assert!(header.reply_right.is_none());
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error of the [`InvalidDest`](crate::msg::SendErrorKind::InvalidDest) kind in
+    /// case the message carried no reply right or the right was already used.
+    ///
+    /// # Port right references
+    /// This method consumes all moved port right references that the message holds no matter if the
+    /// message transfer is successful or not.
+    pub fn reply_with(&mut self, msg: Builder) -> Result<(), SendError> {
+        match self.reply_right.take() {
+            Some(reply_right) => reply_right.reply(msg),
+            None => Err(SendError::from_kind(SendErrorKind::InvalidDest)),
+        }
+    }
+
+    /// Clones the reply right in case it's a send right keeping the original in the header.
+    ///
+    /// This allows sending multiple replies to the same port. `None` is returned in case the
+    /// message carried no reply right or a send once right, which can't be cloned.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder}, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let reply_recv_right = RecvRight::alloc();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.set_made_reply_port(&reply_recv_right, false);
+    /// recv_right.make_send().send(builder).unwrap();
+    ///
+    /// let (header, _) = recv_right.recv(&mut buffer).unwrap().parse_header();
+    /// let reply_right = header.clone_reply_send().unwrap();
+    /// reply_right.ping(&mut buffer, 1).unwrap();
+    /// reply_right.ping(&mut buffer, 2).unwrap();
+    /// ```
+    pub fn clone_reply_send(&self) -> Option<SendRight> {
+        match &self.reply_right {
+            Some(AnySendRight::Send(right)) => Some(right.clone()),
+            Some(AnySendRight::SendOnce(_)) | None => None,
+        }
+    }
+}
+
+/// Represents a fully parsed message owning all of its resources.
+///
+/// Returned by [`MsgParser::parse_all`].
+#[derive(Debug)]
+pub struct ParsedMsg {
+    /// The parsed message header.
+    pub header: ParsedMsgHdr,
+    /// The descriptors of the message in the order they appear in it.
+    pub descriptors: Vec<ParsedMsgDesc>,
+    /// A copy of the inline data of the message.
+    pub body: Vec<u8>,
 }
 
 /// Represents a parsed message descriptor.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ParsedMsgDesc {
     /// A receive right from a port descriptor.
     PortRecv(RecvRight),
@@ -54,8 +204,261 @@ pub enum ParsedMsgDesc {
     PortSend(SendRight),
     /// A send once right from a port descriptor.
     PortSendOnce(SendOnceRight),
+    /// A guarded receive right from a guarded port descriptor, see [`RecvOptions::guarded_desc`].
+    ///
+    /// Rights the sender asked to be unguarded on send are reported as [`ParsedMsgDesc::PortRecv`].
+    PortGuardedRecv(GuardedRecvRight),
     /// An out-of-line data descriptor.
+    ///
+    /// Empty buffers are reported with [`OolBuf::is_empty`] returning `true`, these don't own any
+    /// memory and are never deallocated.
     OolData(OolBuf),
+    /// An out-of-line data descriptor parsed as a vector, see [`RecvOptions::ool_vec`].
+    ///
+    /// The length of the vector matches its capacity.
+    OolDataVec(OolVec),
+    /// Receive rights from an out-of-line ports descriptor.
+    ///
+    /// Rights the sender passed as `MACH_PORT_NULL` or which died in transit are `None`.
+    OolPortsRecv(Vec<Option<RecvRight>>),
+    /// Send rights from an out-of-line ports descriptor, see [`ParsedMsgDesc::OolPortsRecv`].
+    OolPortsSend(Vec<Option<SendRight>>),
+    /// Send once rights from an out-of-line ports descriptor, see
+    /// [`ParsedMsgDesc::OolPortsRecv`].
+    OolPortsSendOnce(Vec<Option<SendOnceRight>>),
+}
+
+impl ParsedMsgDesc {
+    /// Converts an out-of-line data descriptor into a buffer that may be forwarded in another
+    /// message without copying the data, returning other descriptors unchanged as the error.
+    ///
+    /// The buffer owns the pages mapped by the kernel, so passing it to
+    /// [`Builder::append_forwarded_ool`] moves the pages into the new message and unmaps them
+    /// from the task once the message is sent. Vectors parsed using [`RecvOptions::ool_vec`] are
+    /// converted into their buffers.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{builder::CopyKind, Buffer, Builder}, rights::RecvRight};
+    /// # let proxy = RecvRight::alloc();
+    /// # let target = RecvRight::alloc();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_ool_data(b"data", CopyKind::Virtual);
+    /// proxy.make_send().send(builder).unwrap();
+    ///
+    /// let mut msg = proxy.recv(&mut buffer).unwrap().parse_all();
+    /// let data = msg.descriptors.pop().unwrap().into_forwardable().unwrap();
+    ///
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_forwarded_ool(data, CopyKind::Virtual);
+    /// target.make_send().send(builder).unwrap();
+    /// ```
+    pub fn into_forwardable(self) -> Result<OolBuf, Self> {
+        match self {
+            ParsedMsgDesc::OolData(buf) => Ok(buf),
+            ParsedMsgDesc::OolDataVec(vec) => Ok(vec.into_buf()),
+            desc => Err(desc),
+        }
+    }
+
+    /// Creates a parsed descriptor from a raw port descriptor taking ownership of the right.
+    ///
+    /// This is useful for code mixing the parsed descriptors with raw `mach2` descriptors, e.g.
+    /// ones parsed manually from a received message.
+    ///
+    /// # Safety
+    /// The name must denote a right of the type matching the disposition in the IPC space of the
+    /// task and the reference must not be owned by anything else, since it's released once the
+    /// returned value is dropped.
+    ///
+    /// # Panics
+    /// This function will panic in case the disposition isn't one of `MACH_MSG_TYPE_MOVE_SEND`,
+    /// `MACH_MSG_TYPE_MOVE_SEND_ONCE` or `MACH_MSG_TYPE_MOVE_RECEIVE` as set by the kernel in
+    /// received messages.
+    pub unsafe fn from_raw_port_desc(desc: &mach_msg_port_descriptor_t) -> Self {
+        port_desc_right(desc)
+    }
+
+    /// Creates a parsed descriptor from a raw out-of-line data descriptor taking ownership of the
+    /// memory region.
+    ///
+    /// # Safety
+    /// The descriptor must describe a memory region that may be deallocated using
+    /// `mach_vm_deallocate`, see [`OolBuf::from_raw_parts`].
+    pub unsafe fn from_raw_ool_desc(desc: &mach_msg_ool_descriptor_t) -> Self {
+        // SAFETY: Guaranteed by the caller.
+        ParsedMsgDesc::OolData(unsafe { ool_desc_buf(desc) })
+    }
+
+    /// Creates a parsed descriptor from a raw out-of-line ports descriptor taking ownership of
+    /// the rights and deallocating the names array.
+    ///
+    /// # Safety
+    /// The descriptor must describe an array of names that may be deallocated using
+    /// `mach_vm_deallocate`, and the names must denote rights of the type matching the
+    /// disposition like for [`ParsedMsgDesc::from_raw_port_desc`].
+    ///
+    /// # Panics
+    /// This function will panic in the same cases as [`ParsedMsgDesc::from_raw_port_desc`].
+    pub unsafe fn from_raw_ool_ports_desc(desc: &mach_msg_ool_ports_descriptor_t) -> Self {
+        ool_ports_rights(desc)
+    }
+}
+
+/// Takes the right from a received port descriptor.
+fn port_desc_right(desc: &mach_msg_port_descriptor_t) -> ParsedMsgDesc {
+    match desc.disposition as mach_msg_type_name_t {
+        MACH_MSG_TYPE_MOVE_SEND => ParsedMsgDesc::PortSend(SendRight::from_raw_name(desc.name)),
+        MACH_MSG_TYPE_MOVE_SEND_ONCE => {
+            ParsedMsgDesc::PortSendOnce(SendOnceRight::from_raw_name(desc.name))
+        }
+        MACH_MSG_TYPE_MOVE_RECEIVE => ParsedMsgDesc::PortRecv(RecvRight::from_raw_name(desc.name)),
+        _ => unreachable!("invalid disposition value in a port descriptor"),
+    }
+}
+
+/// Takes the memory region described by a received out-of-line data descriptor.
+///
+/// # Safety
+/// The descriptor must describe a memory region mapped for the task, see
+/// [`OolBuf::from_raw_parts`].
+unsafe fn ool_desc_buf(desc: &mach_msg_ool_descriptor_t) -> OolBuf {
+    let length: usize = desc.size.try_into().unwrap();
+    if length == 0 {
+        // An empty buffer owns no memory, so the address isn't even read.
+        return OolBuf::default();
+    }
+
+    // SAFETY: This is obviously safe, but required since the alignment may be invalid here.
+    let address = unsafe { ptr::read_unaligned(ptr::addr_of!(desc.address)) };
+    let ptr = NonNull::new(address as *mut u8).unwrap();
+
+    // SAFETY: Guaranteed by the caller.
+    unsafe { OolBuf::from_raw_parts(ptr, length) }
+}
+
+/// Takes the rights from a received out-of-line ports descriptor deallocating the names array.
+fn ool_ports_rights(desc: &mach_msg_ool_ports_descriptor_t) -> ParsedMsgDesc {
+    fn wrap<T>(names: &[mach_port_name_t], f: fn(mach_port_name_t) -> T) -> Vec<Option<T>> {
+        names
+            .iter()
+            .map(|&name| (name != MACH_PORT_NULL && name != MACH_PORT_DEAD).then(|| f(name)))
+            .collect()
+    }
+
+    const NAME_SIZE: usize = mem::size_of::<mach_port_name_t>();
+
+    // SAFETY: This is obviously safe, but required since the alignment may be invalid here.
+    let address = unsafe { ptr::read_unaligned(ptr::addr_of!(desc.address)) };
+    let count = desc.count as usize;
+    let array = match NonNull::new(address as *mut u8).filter(|_| count > 0) {
+        // SAFETY: The kernel is trusted to provide a valid array of names.
+        Some(ptr) => unsafe { OolBuf::from_raw_parts(ptr, count * NAME_SIZE) },
+        None => OolBuf::default(),
+    };
+    let names: Vec<_> = array
+        .chunks_exact(NAME_SIZE)
+        .map(|bytes| mach_port_name_t::from_ne_bytes(bytes.try_into().unwrap()))
+        .collect();
+
+    match desc.disposition as mach_msg_type_name_t {
+        MACH_MSG_TYPE_MOVE_RECEIVE => {
+            ParsedMsgDesc::OolPortsRecv(wrap(&names, RecvRight::from_raw_name))
+        }
+        MACH_MSG_TYPE_MOVE_SEND => {
+            ParsedMsgDesc::OolPortsSend(wrap(&names, SendRight::from_raw_name))
+        }
+        MACH_MSG_TYPE_MOVE_SEND_ONCE => {
+            ParsedMsgDesc::OolPortsSendOnce(wrap(&names, SendOnceRight::from_raw_name))
+        }
+        _ => unreachable!("invalid disposition value in an out-of-line ports descriptor"),
+    }
+}
+
+/// Takes the receive right from a received guarded port descriptor.
+fn guarded_desc_right(desc: &GuardedPortDescriptor) -> ParsedMsgDesc {
+    let (name, guard, flags) = (desc.name, desc.context, desc.flags);
+
+    assert_eq!(
+        desc.disposition as mach_msg_type_name_t, MACH_MSG_TYPE_MOVE_RECEIVE,
+        "invalid disposition value in a guarded port descriptor"
+    );
+
+    if flags as mach_msg_guard_flags_t & MACH_MSG_GUARD_FLAGS_UNGUARDED_ON_SEND != 0 {
+        ParsedMsgDesc::PortRecv(RecvRight::from_raw_name(name))
+    } else {
+        // SAFETY: The kernel reports the guard the right is guarded by in the context field.
+        ParsedMsgDesc::PortGuardedRecv(unsafe { GuardedRecvRight::from_raw_parts(name, guard) })
+    }
+}
+
+/// The kind of a descriptor in a message, see [`MsgParser::descriptor_kinds`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum DescKind {
+    /// A port descriptor carrying a receive right.
+    PortRecv,
+    /// A port descriptor carrying a send right.
+    PortSend,
+    /// A port descriptor carrying a send once right.
+    PortSendOnce,
+    /// A guarded port descriptor carrying a receive right that stays guarded.
+    PortGuardedRecv,
+    /// An out-of-line data descriptor, including volatile ones.
+    Ool,
+    /// An out-of-line ports descriptor.
+    OolPorts,
+}
+
+impl From<&mach_msg_port_descriptor_t> for DescKind {
+    /// Returns the kind of the right the receiver of the descriptor gets.
+    ///
+    /// # Panics
+    /// This function will panic in case the disposition isn't a valid port disposition.
+    fn from(desc: &mach_msg_port_descriptor_t) -> Self {
+        match desc.disposition as mach_msg_type_name_t {
+            MACH_MSG_TYPE_MOVE_SEND | MACH_MSG_TYPE_COPY_SEND | MACH_MSG_TYPE_MAKE_SEND => {
+                DescKind::PortSend
+            }
+            MACH_MSG_TYPE_MOVE_SEND_ONCE | MACH_MSG_TYPE_MAKE_SEND_ONCE => DescKind::PortSendOnce,
+            MACH_MSG_TYPE_MOVE_RECEIVE => DescKind::PortRecv,
+            _ => panic!("invalid disposition value in a port descriptor"),
+        }
+    }
+}
+
+impl From<&mach_msg_ool_descriptor_t> for DescKind {
+    fn from(_: &mach_msg_ool_descriptor_t) -> Self {
+        DescKind::Ool
+    }
+}
+
+impl From<&mach_msg_ool_ports_descriptor_t> for DescKind {
+    fn from(_: &mach_msg_ool_ports_descriptor_t) -> Self {
+        DescKind::OolPorts
+    }
+}
+
+impl DescKind {
+    /// Returns the kind of a transmuted descriptor.
+    fn of(desc: &TransmutedMsgDesc) -> Self {
+        match desc {
+            TransmutedMsgDesc::Port(port_desc) => DescKind::from(*port_desc),
+            TransmutedMsgDesc::Ool(_) | TransmutedMsgDesc::OolVolatile(_) => DescKind::Ool,
+            TransmutedMsgDesc::OolPorts(_) => DescKind::OolPorts,
+            TransmutedMsgDesc::GuardedPort(guarded_desc) => {
+                let flags = guarded_desc.flags as mach_msg_guard_flags_t;
+
+                // See `guarded_desc_right`.
+                if flags & MACH_MSG_GUARD_FLAGS_UNGUARDED_ON_SEND != 0 {
+                    DescKind::PortRecv
+                } else {
+                    DescKind::PortGuardedRecv
+                }
+            }
+        }
+    }
 }
 
 pub(crate) enum TransmutedMsgDesc<'a> {
@@ -63,6 +466,7 @@ pub(crate) enum TransmutedMsgDesc<'a> {
     Ool(&'a mach_msg_ool_descriptor_t),
     OolVolatile(&'a mach_msg_ool_descriptor_t),
     OolPorts(&'a mach_msg_ool_ports_descriptor_t),
+    GuardedPort(&'a GuardedPortDescriptor),
 }
 
 /// Message body parser.
@@ -73,6 +477,19 @@ pub struct BodyParser<'buffer> {
 }
 
 impl BodyParser<'_> {
+    /// Returns the ID of the parsed message.
+    #[inline]
+    pub fn id(&self) -> MsgId {
+        self.buffer.header().msgh_id
+    }
+
+    /// Returns the size of the whole message as reported by `msgh_size`, see
+    /// [`MsgParser::wire_size`].
+    #[inline]
+    pub fn total_wire_size(&self) -> usize {
+        self.buffer.header().msgh_size as usize
+    }
+
     /// Returns the message body as a byte slice.
     pub fn body(&self) -> &[u8] {
         let offset = self.offset as usize;
@@ -83,6 +500,81 @@ impl BodyParser<'_> {
 
         &self.buffer.body()[offset..size]
     }
+
+    /// Parses the start of a MIG reply body returning the `RetCode` field of the reply.
+    ///
+    /// MIG replies start with an `NDR_record_t` followed by a `kern_return_t`. The NDR record is
+    /// validated to match the data representation of the current task. On success the parser is
+    /// advanced past the parsed fields, so [`BodyParser::body`] returns the data following the
+    /// return code.
+    ///
+    /// # Errors
+    /// Returns [`ParseError::Truncated`] in case the body is too short and
+    /// [`ParseError::InvalidNdr`] in case the NDR record doesn't match. The parser isn't advanced
+    /// in these cases.
+    pub fn parse_mig_reply(&mut self) -> Result<kern_return_t, ParseError> {
+        const RET_CODE_SIZE: usize = mem::size_of::<kern_return_t>();
+
+        let Some((_, tail)) = self.body().split_first_chunk::<NDR_SIZE>() else {
+            return Err(ParseError::Truncated);
+        };
+        let Some((&ret_code, _)) = tail.split_first_chunk::<RET_CODE_SIZE>() else {
+            return Err(ParseError::Truncated);
+        };
+
+        self.read_ndr()?;
+        self.offset += RET_CODE_SIZE as mach_msg_size_t;
+
+        Ok(kern_return_t::from_ne_bytes(ret_code))
+    }
+
+    /// Parses an NDR record at the start of the body.
+    ///
+    /// The record is validated to match the data representation of the current task. On success
+    /// the parser is advanced past the record.
+    ///
+    /// # Errors
+    /// Returns [`ParseError::Truncated`] in case the body is too short and
+    /// [`ParseError::InvalidNdr`] in case the NDR record doesn't match, e.g. if the sender uses a
+    /// different byte order. The parser isn't advanced in these cases.
+    pub fn read_ndr(&mut self) -> Result<(), ParseError> {
+        let Some((record, _)) = self.body().split_first_chunk::<NDR_SIZE>() else {
+            return Err(ParseError::Truncated);
+        };
+
+        if *record != ndr::local_bytes() {
+            return Err(ParseError::InvalidNdr);
+        }
+
+        self.offset += NDR_SIZE as mach_msg_size_t;
+
+        Ok(())
+    }
+
+    /// Reads a NUL-terminated UTF-8 string at the start of the body.
+    ///
+    /// On success the parser is advanced past the terminator and the string is returned without
+    /// it, see [`Builder::append_cstr`].
+    ///
+    /// # Errors
+    /// Returns [`ParseError::Truncated`] in case the body doesn't contain a NUL byte and
+    /// [`ParseError::InvalidUtf8`] in case the string isn't valid UTF-8. The parser isn't
+    /// advanced in these cases.
+    pub fn read_cstr(&mut self) -> Result<&str, ParseError> {
+        let start = self.offset as usize;
+        let size = self.buffer.header().msgh_size as usize - mem::size_of::<mach_msg_header_t>();
+        // The string borrows the buffer, so the offset may still be advanced.
+        let body = &self.buffer.body()[start..size];
+        let len = body
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(ParseError::Truncated)?;
+        let s = str::from_utf8(&body[..len]).map_err(|_| ParseError::InvalidUtf8)?;
+
+        self.offset += len as mach_msg_size_t + 1;
+
+        Ok(s)
+    }
 }
 
 /// Either a descriptor or a body parser.
@@ -95,7 +587,7 @@ pub enum DescOrBodyParser<'buffer> {
 }
 
 pub(crate) fn next_desc_impl<'buffer>(
-    buffer: &'buffer mut Buffer,
+    buffer: &'buffer Buffer,
     offset: &mut mach_msg_size_t,
     received: bool,
 ) -> TransmutedMsgDesc<'buffer> {
@@ -145,7 +637,17 @@ pub(crate) fn next_desc_impl<'buffer>(
             TransmutedMsgDesc::OolVolatile(unsafe { &*ptr })
         }
         MACH_MSG_OOL_PORTS_DESCRIPTOR => {
-            TransmutedMsgDesc::OolPorts(unsafe { anything_from_bytes(desc_bytes) })
+            let ptr = desc_bytes.as_ptr() as *const mach_msg_ool_ports_descriptor_t;
+
+            assert!(ptr.is_aligned_to(mem::align_of::<mach_msg_size_t>()));
+
+            // SAFETY: See above.
+            TransmutedMsgDesc::OolPorts(unsafe { &*ptr })
+        }
+        MACH_MSG_GUARDED_PORT_DESCRIPTOR => {
+            // SAFETY: The structure is packed, so the alignment requirement is the same as for the
+            // other descriptors.
+            TransmutedMsgDesc::GuardedPort(unsafe { anything_from_bytes(desc_bytes) })
         }
         _ => unreachable!("invalid descriptor type"),
     };
@@ -160,53 +662,52 @@ pub(crate) fn next_desc_impl<'buffer>(
 pub struct DescParser<'buffer> {
     buffer: Option<&'buffer mut Buffer>,
     count: mach_msg_size_t,
+    total: mach_msg_size_t,
     offset: mach_msg_size_t,
+    ool_vec: bool,
 }
 
 impl<'buffer> DescParser<'buffer> {
+    /// Returns the number of descriptors that are yet to be parsed.
+    ///
+    /// This is never zero since a body parser is returned after the last descriptor is parsed.
+    #[inline]
+    pub fn remaining(&self) -> mach_msg_size_t {
+        self.count
+    }
+
+    /// Returns the total number of descriptors in the message.
+    #[inline]
+    pub fn total(&self) -> mach_msg_size_t {
+        self.total
+    }
+
+    /// Returns the ID of the parsed message.
+    #[inline]
+    pub fn id(&self) -> MsgId {
+        self.buffer.as_ref().unwrap().header().msgh_id
+    }
+
     /// Parses the next descriptor from the message.
     pub fn next(mut self) -> (ParsedMsgDesc, DescOrBodyParser<'buffer>) {
         assert!(self.count > 0);
 
         let parsed_desc =
             match next_desc_impl(self.buffer.as_mut().unwrap(), &mut self.offset, true) {
-                TransmutedMsgDesc::Port(port_desc) => {
-                    match port_desc.disposition as mach_msg_copy_options_t {
-                        MACH_MSG_TYPE_MOVE_SEND => {
-                            ParsedMsgDesc::PortSend(SendRight::from_raw_name(port_desc.name))
-                        }
-                        MACH_MSG_TYPE_MOVE_SEND_ONCE => ParsedMsgDesc::PortSendOnce(
-                            SendOnceRight::from_raw_name(port_desc.name),
-                        ),
-                        MACH_MSG_TYPE_MOVE_RECEIVE => {
-                            ParsedMsgDesc::PortRecv(RecvRight::from_raw_name(port_desc.name))
-                        }
-                        //MACH_MSG_TYPE_COPY_SEND | MACH_MSG_TYPE_MAKE_SEND | MACH_MSG_TYPE_MAKE_SEND_ONCE =>
-                        _ => unreachable!("invalid disposition value in a port descriptor"),
-                    }
-                }
-                TransmutedMsgDesc::Ool(ool_desc) => {
-                    let length: usize = ool_desc.size.try_into().unwrap();
-                    let ptr = match length {
-                        0 => NonNull::dangling(),
-                        _ => {
-                            // SAFETY: This is obviously safe, but required since the alignment may
-                            // be invalid here.
-                            let address =
-                                unsafe { ptr::read_unaligned(ptr::addr_of!(ool_desc.address)) };
-                            NonNull::new(address as *mut u8).unwrap()
-                        }
-                    };
-
+                TransmutedMsgDesc::Port(port_desc) => port_desc_right(port_desc),
+                // Volatile data is received the same way as regular out-of-line data.
+                TransmutedMsgDesc::Ool(ool_desc) | TransmutedMsgDesc::OolVolatile(ool_desc) => {
                     // SAFETY: The kernel is trusted to provide a valid memory region here.
-                    ParsedMsgDesc::OolData(unsafe { OolBuf::from_raw_parts(ptr, length) })
-                }
-                TransmutedMsgDesc::OolVolatile(_) => {
-                    unimplemented!("OOL and volatile OOL descriptors are not yet supported")
-                }
-                TransmutedMsgDesc::OolPorts(_) => {
-                    unimplemented!("OOL ports descriptors are not supported")
+                    let buf = unsafe { ool_desc_buf(ool_desc) };
+
+                    if self.ool_vec {
+                        ParsedMsgDesc::OolDataVec(buf.into())
+                    } else {
+                        ParsedMsgDesc::OolData(buf)
+                    }
                 }
+                TransmutedMsgDesc::OolPorts(ports_desc) => ool_ports_rights(ports_desc),
+                TransmutedMsgDesc::GuardedPort(guarded_desc) => guarded_desc_right(guarded_desc),
             };
         self.count -= 1;
 
@@ -221,6 +722,53 @@ impl<'buffer> DescParser<'buffer> {
 
         (parsed_desc, parser)
     }
+
+    /// Parses the remaining descriptors passing out-of-line data buffers to the callback and
+    /// returns the body parser.
+    ///
+    /// Port rights from the skipped port descriptors are released as they are parsed.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::msg::{builder::CopyKind, Buffer, Builder, DescOrBodyParser};
+    /// # use mach_ports::rights::RecvRight;
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// # let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_copied_send_right(&send_right);
+    /// builder.append_ool_data(b"data", CopyKind::Virtual);
+    /// send_right.send(builder).unwrap();
+    ///
+    /// let (_, parser) = recv_right.recv(&mut buffer).unwrap().parse_header();
+    /// let DescOrBodyParser::Descriptor(parser) = parser else { unreachable!() };
+    ///
+    /// let mut buffers = vec![];
+    /// parser.for_each_ool(|buf| buffers.push(buf));
+    ///
+    /// assert_eq!(buffers, [b"data"]);
+    /// ```
+    pub fn for_each_ool<F: FnMut(OolBuf)>(self, mut f: F) -> BodyParser<'buffer> {
+        let mut parser = DescOrBodyParser::Descriptor(self);
+
+        loop {
+            match parser {
+                DescOrBodyParser::Descriptor(desc_parser) => {
+                    let (desc, next) = desc_parser.next();
+
+                    match desc {
+                        ParsedMsgDesc::OolData(buf) => f(buf),
+                        // The length matches the capacity, so the conversion doesn't reallocate.
+                        ParsedMsgDesc::OolDataVec(buf) => f(buf.into()),
+                        _ => (),
+                    }
+
+                    parser = next;
+                }
+                DescOrBodyParser::Body(body_parser) => return body_parser,
+            }
+        }
+    }
 }
 
 impl Drop for DescParser<'_> {
@@ -228,23 +776,14 @@ impl Drop for DescParser<'_> {
         // Iterate through all remaining descriptors and free resources.
         while self.count > 0 {
             match next_desc_impl(self.buffer.as_mut().unwrap(), &mut self.offset, true) {
-                TransmutedMsgDesc::Port(port_desc) => {
-                    match port_desc.disposition as mach_msg_copy_options_t {
-                        MACH_MSG_TYPE_MOVE_SEND => drop(SendRight::from_raw_name(port_desc.name)),
-                        MACH_MSG_TYPE_MOVE_SEND_ONCE => {
-                            drop(SendOnceRight::from_raw_name(port_desc.name))
-                        }
-                        MACH_MSG_TYPE_MOVE_RECEIVE => {
-                            drop(RecvRight::from_raw_name(port_desc.name))
-                        }
-                        _ => unreachable!("invalid disposition value in a port descriptor"),
-                    }
-                }
-                TransmutedMsgDesc::Ool(_) | TransmutedMsgDesc::OolVolatile(_) => {
-                    unimplemented!("OOL and volatile OOL descriptors are not yet supported")
+                TransmutedMsgDesc::Port(port_desc) => drop(port_desc_right(port_desc)),
+                TransmutedMsgDesc::Ool(ool_desc) | TransmutedMsgDesc::OolVolatile(ool_desc) => {
+                    // SAFETY: The kernel is trusted to provide a valid memory region here.
+                    drop(unsafe { ool_desc_buf(ool_desc) })
                 }
-                TransmutedMsgDesc::OolPorts(_) => {
-                    unimplemented!("OOL ports descriptors are not supported")
+                TransmutedMsgDesc::OolPorts(ports_desc) => drop(ool_ports_rights(ports_desc)),
+                TransmutedMsgDesc::GuardedPort(guarded_desc) => {
+                    drop(guarded_desc_right(guarded_desc))
                 }
             }
 
@@ -256,17 +795,29 @@ impl Drop for DescParser<'_> {
     }
 }
 
-fn parse_header_impl(buffer: &mut Buffer) -> (ParsedMsgHdr, DescOrBodyParser) {
+fn parse_header_impl(
+    buffer: &mut Buffer,
+    trailer: MsgTrailer,
+    ool_vec: bool,
+) -> (ParsedMsgHdr, DescOrBodyParser) {
     let header = buffer.header_mut();
     let bits = MachMsgBits(header.msgh_bits);
     let id = header.msgh_id;
 
     let raw_voucher_name = header.msgh_voucher_port;
     let voucher = if raw_voucher_name != MACH_PORT_NULL {
-        assert!(matches!(
-            bits.voucher(),
-            MACH_MSG_TYPE_COPY_SEND | MACH_MSG_TYPE_MOVE_SEND
-        ));
+        match bits.voucher() {
+            // The kernel always passes the voucher reference to the receiver as moved, no matter
+            // which disposition the sender used.
+            MACH_MSG_TYPE_MOVE_SEND => {}
+            // Only possible for messages that weren't received, which are parsed as if the
+            // reference was passed with the message (see `MsgParser::from_bytes_with_rights`).
+            MACH_MSG_TYPE_COPY_SEND => {}
+            _ => unreachable!("unexpected voucher rights"),
+        }
+
+        // Either way the parser owns a single reference that is released when the wrapper is
+        // dropped.
         Some(SendRight::from_raw_name(raw_voucher_name))
     } else {
         None
@@ -290,7 +841,9 @@ fn parse_header_impl(buffer: &mut Buffer) -> (ParsedMsgHdr, DescOrBodyParser) {
         DescOrBodyParser::Descriptor(DescParser {
             buffer: Some(buffer),
             count,
+            total: count,
             offset: mem::size_of::<mach_msg_size_t>() as mach_msg_size_t,
+            ool_vec,
         })
     } else {
         DescOrBodyParser::Body(BodyParser { buffer, offset: 0 })
@@ -300,38 +853,562 @@ fn parse_header_impl(buffer: &mut Buffer) -> (ParsedMsgHdr, DescOrBodyParser) {
         id,
         reply_right,
         voucher,
+        importance_raised: bits.raise_imp(),
+        importance_held: bits.imp_hold_assert(),
+        trailer,
     };
 
     (parsed_hdr, desc_parser)
 }
 
+/// Validates a message that wasn't received from the kernel, so that parsing it can't panic or
+/// produce invalid out-of-line buffers.
+///
+/// Port names are only accepted in case `with_rights` is `true`.
+fn validate(buffer: &Buffer, with_rights: bool) -> Result<(), ParseError> {
+    const HEADER_SIZE: usize = mem::size_of::<mach_msg_header_t>();
+    const SIZE_SIZE: usize = mem::size_of::<mach_msg_size_t>();
+    const TYPE_DESC_SIZE: usize = mem::size_of::<mach_msg_type_descriptor_t>();
+
+    let header = buffer.header();
+    let size = header.msgh_size as usize;
+
+    if size < HEADER_SIZE || size > buffer.as_slice().len() || !size.is_multiple_of(SIZE_SIZE) {
+        return Err(ParseError::InvalidSize);
+    }
+
+    if header.msgh_bits != header.msgh_bits & MACH_MSGH_BITS_USED {
+        return Err(ParseError::InvalidHeaderBits);
+    }
+
+    let bits = MachMsgBits(header.msgh_bits);
+
+    let names = [
+        header.msgh_remote_port,
+        header.msgh_local_port,
+        header.msgh_voucher_port,
+    ];
+    if !with_rights && names.iter().any(|&name| name != MACH_PORT_NULL) {
+        return Err(ParseError::UntrustedRight);
+    }
+
+    if header.msgh_remote_port != MACH_PORT_NULL
+        && !matches!(
+            bits.remote(),
+            MACH_MSG_TYPE_MOVE_SEND | MACH_MSG_TYPE_MOVE_SEND_ONCE
+        )
+    {
+        return Err(ParseError::InvalidHeaderBits);
+    }
+
+    if header.msgh_voucher_port != MACH_PORT_NULL
+        && !matches!(
+            bits.voucher(),
+            MACH_MSG_TYPE_COPY_SEND | MACH_MSG_TYPE_MOVE_SEND
+        )
+    {
+        return Err(ParseError::InvalidHeaderBits);
+    }
+
+    if !bits.complex() {
+        return Ok(());
+    }
+
+    let body = &buffer.body()[..size - HEADER_SIZE];
+    let Some((count, _)) = body.split_first_chunk::<SIZE_SIZE>() else {
+        return Err(ParseError::InvalidDescriptorCount);
+    };
+
+    let mut offset = SIZE_SIZE;
+    for _ in 0..mach_msg_size_t::from_ne_bytes(*count) {
+        // Every descriptor starts with the fields of mach_msg_type_descriptor_t.
+        let Some(desc) = body[offset..].first_chunk::<TYPE_DESC_SIZE>() else {
+            return Err(ParseError::InvalidDescriptorCount);
+        };
+
+        let type_ = desc[mem::offset_of!(mach_msg_type_descriptor_t, type_)];
+        let desc_size = match type_ as mach_msg_descriptor_type_t {
+            MACH_MSG_PORT_DESCRIPTOR if !with_rights => return Err(ParseError::UntrustedRight),
+            MACH_MSG_PORT_DESCRIPTOR => {
+                let disposition = desc[mem::offset_of!(mach_msg_port_descriptor_t, disposition)];
+
+                if !matches!(
+                    disposition as mach_msg_type_name_t,
+                    MACH_MSG_TYPE_MOVE_RECEIVE
+                        | MACH_MSG_TYPE_MOVE_SEND
+                        | MACH_MSG_TYPE_MOVE_SEND_ONCE
+                ) {
+                    return Err(ParseError::InvalidDescriptor);
+                }
+
+                mem::size_of::<mach_msg_port_descriptor_t>()
+            }
+            MACH_MSG_OOL_DESCRIPTOR
+            | MACH_MSG_OOL_VOLATILE_DESCRIPTOR
+            | MACH_MSG_OOL_PORTS_DESCRIPTOR => return Err(ParseError::UntrustedOolDescriptor),
+            MACH_MSG_GUARDED_PORT_DESCRIPTOR if !with_rights => {
+                return Err(ParseError::UntrustedRight)
+            }
+            _ => return Err(ParseError::InvalidDescriptor),
+        };
+
+        if body.len() - offset < desc_size {
+            return Err(ParseError::InvalidDescriptorCount);
+        }
+
+        offset += desc_size;
+    }
+
+    Ok(())
+}
+
+/// Sets the `deallocate` flag of every out-of-line data and ports descriptor in a received message.
+///
+/// The kernel clears the flag in descriptors received as physical copies, so it's set to make the
+/// kernel unmap the memory from the task when the message is forwarded.
+fn set_ool_deallocate(buffer: &mut Buffer) {
+    let count = buffer.descriptors_count();
+    let body = buffer.body_mut();
+    let mut offset = mem::size_of::<mach_msg_size_t>();
+    for _ in 0..count {
+        let type_ = body[offset + mem::offset_of!(mach_msg_type_descriptor_t, type_)];
+        let type_ = type_ as mach_msg_descriptor_type_t;
+        match type_ {
+            MACH_MSG_OOL_DESCRIPTOR | MACH_MSG_OOL_VOLATILE_DESCRIPTOR => {
+                body[offset + mem::offset_of!(mach_msg_ool_descriptor_t, deallocate)] = 1;
+            }
+            MACH_MSG_OOL_PORTS_DESCRIPTOR => {
+                body[offset + mem::offset_of!(mach_msg_ool_ports_descriptor_t, deallocate)] = 1;
+            }
+            _ => {}
+        }
+
+        offset += size_for_desc_type(type_);
+    }
+}
+
 /// A Mach message parser that can parse Mach message headers and construct subsequent parsers.
-#[repr(transparent)]
 #[derive(Debug)]
-pub struct MsgParser<'buffer>(Option<&'buffer mut Buffer>);
+pub struct MsgParser<'buffer> {
+    buffer: Option<&'buffer mut Buffer>,
+    trailer: MsgTrailer,
+    ool_vec: bool,
+}
 
 impl<'buffer> MsgParser<'buffer> {
+    /// Creates a parser for a message received into the buffer by the kernel.
+    ///
+    /// The kernel is trusted to produce a valid message, only the size in the header is checked to
+    /// fit into the buffer. [`ParseError::InvalidSize`] is returned otherwise.
     #[inline(always)]
-    pub(crate) fn new(buffer: &'buffer mut Buffer) -> Self {
+    pub(crate) fn new(
+        buffer: &'buffer mut Buffer,
+        options: RecvOptions,
+    ) -> Result<Self, ParseError> {
+        let body_size = (buffer.header().msgh_size as usize)
+            .checked_sub(mem::size_of::<mach_msg_header_t>())
+            .filter(|&body_size| body_size <= buffer.capacity())
+            .ok_or(ParseError::InvalidSize)?;
+
+        // SAFETY: The message has just been received so the trailer is initialized.
+        let trailer = MsgTrailer::from_bytes(unsafe { buffer.trailer_bytes() });
+
+        // SAFETY: The kernel has just written the message body.
         unsafe {
-            buffer.set_len(buffer.header().msgh_size);
+            buffer.set_len(body_size.try_into().unwrap());
         }
 
-        MsgParser(Some(buffer))
+        #[cfg(debug_assertions)]
+        buffer.set_debug_state(crate::msg::BufferState::Received);
+
+        Ok(MsgParser {
+            buffer: Some(buffer),
+            trailer,
+            ool_vec: options.ool_vec_enabled(),
+        })
+    }
+
+    /// Moves the data of the out-of-line data descriptors into the regions in order, see
+    /// [`RecvRight::recv_scatter`].
+    ///
+    /// Each moved descriptor is emptied, so the parser yields an empty buffer for it and doesn't
+    /// deallocate the memory. Descriptors without a matching region are left untouched. Returns
+    /// `false` in case the data doesn't fit into its region, the descriptor isn't emptied then.
+    ///
+    /// [`RecvRight::recv_scatter`]: crate::rights::RecvRight::recv_scatter
+    pub(crate) fn scatter_ool(&mut self, regions: &mut [OolVec]) -> bool {
+        let buffer = self.buffer.as_mut().unwrap();
+        let count = buffer.descriptors_count();
+        let body = buffer.body_mut();
+        let mut regions = regions.iter_mut();
+        let mut offset = mem::size_of::<mach_msg_size_t>();
+        for _ in 0..count {
+            let type_ = body[offset + mem::offset_of!(mach_msg_type_descriptor_t, type_)];
+            let type_ = type_ as mach_msg_descriptor_type_t;
+            let desc_size = size_for_desc_type(type_);
+
+            if matches!(
+                type_,
+                MACH_MSG_OOL_DESCRIPTOR | MACH_MSG_OOL_VOLATILE_DESCRIPTOR
+            ) {
+                let Some(region) = regions.next() else {
+                    break;
+                };
+
+                let desc_bytes = &mut body[offset..offset + desc_size];
+                // SAFETY: The kernel has written a complete descriptor, it's read unaligned.
+                let desc = unsafe {
+                    ptr::read_unaligned(desc_bytes.as_ptr() as *const mach_msg_ool_descriptor_t)
+                };
+                let size = desc.size as usize;
+
+                if size != 0 && desc.address as *mut u8 == region.as_ptr().as_ptr() {
+                    // SAFETY: The kernel has written the data into the region in place.
+                    unsafe { region.set_len(size) };
+                } else if size <= region.capacity() {
+                    // SAFETY: The kernel has mapped the data for the task, the buffer deallocates
+                    // it after the copy.
+                    let data = unsafe { ool_desc_buf(&desc) };
+
+                    // SAFETY: The length is reset before the region is filled.
+                    unsafe { region.set_len(0) };
+                    region.extend_from_slice(&data);
+                } else {
+                    return false;
+                }
+
+                let address = mem::offset_of!(mach_msg_ool_descriptor_t, address);
+                desc_bytes[address..address + mem::size_of::<usize>()].fill(0);
+                let size = mem::offset_of!(mach_msg_ool_descriptor_t, size);
+                desc_bytes[size..size + mem::size_of::<mach_msg_size_t>()].fill(0);
+            }
+
+            offset += desc_size;
+        }
+
+        true
+    }
+
+    /// Creates a parser for a message that wasn't received from the kernel, e.g. one copied into a
+    /// buffer using [`Buffer::from_bytes`].
+    ///
+    /// Unlike received messages, the message is validated before parsing. Messages containing
+    /// out-of-line descriptors are rejected since the validity of the memory they reference can't
+    /// be verified. Messages naming port rights in the header or in descriptors are rejected as
+    /// well, since the parser would take ownership of whatever rights the task holds under these
+    /// names. See [`MsgParser::from_bytes_with_rights`] for parsing such messages. The parsed
+    /// message has an empty trailer.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::msg::{Buffer, MsgParser, ParseError};
+    /// // A header claiming the message is larger than the data.
+    /// let mut bytes = [0u8; 24];
+    /// bytes[4..8].copy_from_slice(&32u32.to_ne_bytes());
+    ///
+    /// let mut buffer = Buffer::from_bytes(&bytes);
+    /// let error = MsgParser::from_bytes(&mut buffer).unwrap_err();
+    ///
+    /// assert_eq!(error, ParseError::InvalidSize);
+    /// ```
+    pub fn from_bytes(buffer: &'buffer mut Buffer) -> Result<Self, ParseError> {
+        validate(buffer, false)?;
+
+        Ok(MsgParser {
+            buffer: Some(buffer),
+            trailer: MsgTrailer::default(),
+            ool_vec: false,
+        })
+    }
+
+    /// Creates a parser for a message that wasn't received from the kernel and may name port
+    /// rights, e.g. one copied using [`MsgParser::into_raw_bytes`].
+    ///
+    /// The message is validated like by [`MsgParser::from_bytes`], except that port names in the
+    /// header and port descriptors are accepted.
+    ///
+    /// # Safety
+    /// The parser takes ownership of the port right references named in the message as if the
+    /// message was received, and releases them once they are dropped. Every name must denote a
+    /// right of the type matching its disposition in the IPC space of the task, and the caller
+    /// must own a reference on the right for each time it's named which isn't owned by anything
+    /// else.
+    pub unsafe fn from_bytes_with_rights(buffer: &'buffer mut Buffer) -> Result<Self, ParseError> {
+        validate(buffer, true)?;
+
+        Ok(MsgParser {
+            buffer: Some(buffer),
+            trailer: MsgTrailer::default(),
+            ool_vec: false,
+        })
+    }
+
+    #[cfg(feature = "trace")]
+    #[inline(always)]
+    pub(crate) fn buffer(&self) -> &Buffer {
+        self.buffer.as_ref().unwrap()
+    }
+
+    /// Returns the size of the received message from the `msgh_size` field of the header.
+    ///
+    /// The size includes the header, the descriptors and the inline data, but neither the trailer
+    /// nor the out-of-line data.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::Buffer, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// send_right.send_inline(&mut buffer, 1337, b"test").unwrap();
+    ///
+    /// let parser = recv_right.recv(&mut buffer).unwrap();
+    /// assert_eq!(parser.wire_size(), 28);
+    /// ```
+    #[inline]
+    pub fn wire_size(&self) -> usize {
+        self.buffer.as_ref().unwrap().header().msgh_size as usize
+    }
+
+    /// Returns the trailer appended to the message by the kernel.
+    #[inline]
+    pub fn trailer(&self) -> &MsgTrailer {
+        &self.trailer
+    }
+
+    /// Returns the kinds of the descriptors in the message in the order they appear in it without
+    /// consuming the message.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{builder::CopyKind, Buffer, Builder, DescKind}, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_copied_send_right(&send_right);
+    /// builder.append_ool_data(b"data", CopyKind::Virtual);
+    /// send_right.send(builder).unwrap();
+    ///
+    /// let parser = recv_right.recv(&mut buffer).unwrap();
+    ///
+    /// assert_eq!(parser.descriptor_kinds(), [DescKind::PortSend, DescKind::Ool]);
+    /// ```
+    pub fn descriptor_kinds(&self) -> Vec<DescKind> {
+        let buffer = self.buffer.as_ref().unwrap();
+        let count = buffer.descriptors_count();
+        let mut offset = mem::size_of::<mach_msg_size_t>() as mach_msg_size_t;
+
+        (0..count)
+            .map(|_| DescKind::of(&next_desc_impl(buffer, &mut offset, true)))
+            .collect()
     }
 
     /// Parses the header of the message and returns the parsed header and either a descriptor or
     /// a body parser depending on whether there are descriptors in the message.
     pub fn parse_header(mut self) -> (ParsedMsgHdr, DescOrBodyParser<'buffer>) {
-        let buffer = self.0.take().unwrap();
-        parse_header_impl(buffer)
+        let buffer = self.buffer.take().unwrap();
+        parse_header_impl(buffer, self.trailer, self.ool_vec)
+    }
+
+    /// Parses the whole message at once collecting the descriptors and copying the body.
+    ///
+    /// This is a non-streaming alternative to [`MsgParser::parse_header`] that is convenient when
+    /// the message is small or all of its contents are needed anyway.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder, ParsedMsgDesc}, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_copied_send_right(&send_right);
+    /// builder.append_inline_data(b"test");
+    /// send_right.send(builder).unwrap();
+    ///
+    /// let msg = recv_right.recv(&mut buffer).unwrap().parse_all();
+    ///
+    /// assert!(matches!(msg.descriptors[..], [ParsedMsgDesc::PortSend(_)]));
+    /// assert_eq!(msg.body, b"test");
+    /// ```
+    pub fn parse_all(self) -> ParsedMsg {
+        let (header, mut parser) = self.parse_header();
+        let mut descriptors = Vec::new();
+
+        let body = loop {
+            match parser {
+                DescOrBodyParser::Descriptor(desc_parser) => {
+                    let (desc, next) = desc_parser.next();
+                    descriptors.push(desc);
+                    parser = next;
+                }
+                DescOrBodyParser::Body(body_parser) => break body_parser.body().to_vec(),
+            }
+        };
+
+        ParsedMsg {
+            header,
+            descriptors,
+            body,
+        }
+    }
+
+    /// Copies the raw bytes of the message, including the header and the descriptors, without
+    /// parsing it.
+    ///
+    /// The trailer isn't copied, see [`MsgParser::trailer`].
+    ///
+    /// # Right references
+    /// The ownership of the port right references and the out-of-line memory referenced by the
+    /// message is passed to the caller, the parser doesn't release them. This is primarily useful
+    /// for messages carrying no rights, otherwise the caller must handle the referenced resources,
+    /// e.g. by parsing the copy using [`MsgParser::from_bytes_with_rights`] or by passing it to
+    /// `mach_msg_destroy`.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder}, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_inline_data(b"test");
+    /// send_right.send(builder).unwrap();
+    ///
+    /// let bytes = recv_right.recv(&mut buffer).unwrap().into_raw_bytes();
+    ///
+    /// assert_eq!(bytes.len(), 28);
+    /// assert_eq!(&bytes[24..], b"test");
+    /// ```
+    pub fn into_raw_bytes(mut self) -> Vec<u8> {
+        let buffer = self.buffer.take().unwrap();
+        let size = buffer.header().msgh_size as usize;
+
+        buffer.as_slice()[..size].to_vec()
+    }
+
+    /// Releases the resources passed with the message using `mach_msg_destroy` without parsing it.
+    ///
+    /// Must only be called for messages received from the kernel.
+    pub(crate) fn destroy(mut self) {
+        let buffer = self.buffer.take().unwrap();
+
+        // SAFETY: The kernel has written a valid message into the buffer.
+        unsafe { mach_msg_destroy(buffer.header_mut()) };
+    }
+
+    /// Parses a bulk data message consisting of a single out-of-line data descriptor returning the
+    /// out-of-line buffer.
+    ///
+    /// Returns `None` in case the message has any other layout, the resources passed with the
+    /// message are released in this case. The rights passed in the header are released as well.
+    pub fn into_bulk_ool(self) -> Option<OolBuf> {
+        let (_, parser) = self.parse_header();
+        let DescOrBodyParser::Descriptor(parser) = parser else {
+            return None;
+        };
+
+        if parser.total() != 1 {
+            return None;
+        }
+
+        match parser.next() {
+            (ParsedMsgDesc::OolData(buf), DescOrBodyParser::Body(body))
+                if body.body().is_empty() =>
+            {
+                Some(buf)
+            }
+            (ParsedMsgDesc::OolDataVec(vec), DescOrBodyParser::Body(body))
+                if body.body().is_empty() =>
+            {
+                Some(vec.into_buf())
+            }
+            _ => None,
+        }
+    }
+
+    /// Forwards the message to another port without parsing it.
+    ///
+    /// Only the header is rewritten in place: the destination is replaced with a copy of the
+    /// specified send right and the reply right, if any, is forwarded as the reply right of the new
+    /// message. The descriptors and the voucher are sent as is since the dispositions of received
+    /// rights are valid move dispositions, so the rights and the out-of-line memory are transferred
+    /// by the kernel without being wrapped. The out-of-line memory is unmapped from the task once
+    /// the message is sent.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder}, rights::RecvRight};
+    /// # let gateway = RecvRight::alloc();
+    /// # let target = RecvRight::alloc();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_inline_data(b"test");
+    /// gateway.make_send().send(builder).unwrap();
+    ///
+    /// let parser = gateway.recv(&mut buffer).unwrap();
+    /// parser.forward(&target.make_send()).unwrap();
+    ///
+    /// let msg = target.recv(&mut buffer).unwrap().parse_all();
+    /// assert_eq!(msg.body, b"test");
+    /// ```
+    ///
+    /// # Port right references
+    /// Like sending a message built by a [`Builder`](crate::msg::Builder), this method consumes
+    /// all the port right references that the message holds no matter if the message transfer is
+    /// successful or not.
+    pub fn forward(mut self, dest: &SendRight) -> Result<(), SendError> {
+        let buffer = self.buffer.take().unwrap();
+        let header = buffer.header_mut();
+        let bits = MachMsgBits::from_bits(header.msgh_bits);
+
+        // The local port names the receive right the message was received on and holds no
+        // reference, so it's replaced by the reply right.
+        header.msgh_local_port = header.msgh_remote_port;
+        header.msgh_remote_port = dest.as_raw_name();
+        header.msgh_bits = bits
+            .set_local(bits.remote())
+            .set_remote(MACH_MSG_TYPE_COPY_SEND)
+            .0
+            & MACH_MSGH_BITS_USER;
+
+        let size = header.msgh_size;
+        set_ool_deallocate(buffer);
+
+        let header = buffer.header_mut();
+        let result = unsafe {
+            mach_msg(
+                header,
+                MACH_SEND_MSG,
+                size,
+                0,
+                MACH_PORT_NULL,
+                0,
+                MACH_PORT_NULL,
+            )
+        };
+
+        if result == KERN_SUCCESS {
+            return Ok(());
+        }
+
+        let error = SendError::from_bits(result);
+        // The message is left untouched in this case, so its resources are released here.
+        if error.rights_not_consumed() {
+            // SAFETY: The header only names rights owned by the message, the destination is
+            // copied and isn't released.
+            unsafe { mach_msg_destroy(header) };
+        }
+
+        Err(error)
     }
 }
 
 impl Drop for MsgParser<'_> {
     fn drop(&mut self) {
-        if let Some(buffer) = &mut self.0 {
-            drop(parse_header_impl(buffer))
+        if let Some(buffer) = &mut self.buffer {
+            drop(parse_header_impl(buffer, self.trailer, self.ool_vec))
         }
     }
 }
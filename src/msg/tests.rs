@@ -1,4 +1,21 @@
-use crate::{msg::*, rights::*};
+use crate::{
+    msg::{ndr, trailer::MacTrailer, *},
+    rights::*,
+    test_util::{recv_refs, send_refs},
+    traits::{AsRawName, IntoRawName},
+};
+use mach2::{
+    kern_return::{kern_return_t, KERN_INVALID_ARGUMENT, KERN_SUCCESS},
+    message::{
+        mach_msg_bits_t, mach_msg_ool_descriptor_t, mach_msg_port_descriptor_t, mach_msg_size_t,
+        MACH_MSGH_BITS_COMPLEX, MACH_MSG_GUARD_FLAGS_NONE, MACH_MSG_GUARD_FLAGS_UNGUARDED_ON_SEND,
+        MACH_MSG_IPC_SPACE, MACH_MSG_PORT_DESCRIPTOR, MACH_MSG_TYPE_COPY_SEND,
+        MACH_MSG_TYPE_MAKE_SEND_ONCE, MACH_MSG_TYPE_MOVE_RECEIVE, MACH_MSG_TYPE_MOVE_SEND,
+        MACH_RCV_TIMED_OUT, MACH_RCV_TRAILER_LABELS, MACH_SEND_INVALID_DEST,
+    },
+    port::{mach_port_name_t, mach_port_t, MACH_PORT_NULL},
+};
+use std::mem;
 
 macro_rules! check_msg {
     ($parser:ident $(desc $desc:ident,)* body $body:expr) => {
@@ -18,7 +35,7 @@ macro_rules! check_msg {
                         panic!("expected one more descriptor");
                     }
                 };
-            )+
+            )*
 
             match parser {
                 $crate::msg::DescOrBodyParser::Descriptor(_) => {
@@ -60,3 +77,1465 @@ fn test_send_consumed_recv() {
     builder.append_moved_right(right);
     send_right.send(builder).unwrap();
 }
+
+#[test]
+fn test_recv_labels_trailer() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_inline_data(b"test");
+    send_right.send(builder).unwrap();
+
+    let options = RecvOptions::new().trailer(MACH_RCV_TRAILER_LABELS);
+    let parser = right.recv_with_options(&mut buffer, options).unwrap();
+    let trailer = *parser.trailer();
+
+    assert_eq!(trailer.size(), mem::size_of::<MacTrailer>());
+    assert!(trailer.seqno().is_some());
+    assert!(trailer.sender().is_some());
+    assert!(trailer.labels_sender().is_some());
+
+    let (header, parser) = parser.parse_header();
+    assert_eq!(header.trailer.size(), trailer.size());
+
+    check_msg!(parser body b"test");
+}
+
+#[test]
+fn test_recv_default_trailer() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    send_right.send(Builder::new(&mut buffer)).unwrap();
+
+    let parser = right.recv(&mut buffer).unwrap();
+    let trailer = parser.trailer();
+
+    assert_eq!(
+        trailer.size(),
+        mem::size_of::<mach2::message::mach_msg_trailer_t>()
+    );
+    assert!(trailer.seqno().is_none());
+    assert!(trailer.sender().is_none());
+}
+
+#[test]
+fn test_recv_trailer_request() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let mut buffer = Buffer::with_capacity(4096);
+
+    send_right.send(Builder::new(&mut buffer)).unwrap();
+    let options = RecvOptions::new().request_trailer(TrailerRequest::Null);
+    let parser = right.recv_with_options(&mut buffer, options).unwrap();
+    let trailer = *parser.trailer();
+    drop(parser);
+
+    assert_eq!(trailer.size(), TrailerRequest::Null.size());
+    assert_eq!(trailer.bytes(TrailerRequest::Null).unwrap().len(), 8);
+    assert!(trailer.bytes(TrailerRequest::Seqno).is_none());
+
+    send_right.send(Builder::new(&mut buffer)).unwrap();
+    let options = RecvOptions::new().request_trailer(TrailerRequest::Seqno);
+    let parser = right.recv_with_options(&mut buffer, options).unwrap();
+    let trailer = *parser.trailer();
+    drop(parser);
+
+    assert_eq!(trailer.size(), TrailerRequest::Seqno.size());
+    assert!(trailer.bytes(TrailerRequest::Null).is_some());
+    assert!(trailer.bytes(TrailerRequest::Sender).is_none());
+
+    // mach_msg_seqno_trailer_t
+    let bytes = trailer.bytes(TrailerRequest::Seqno).unwrap();
+    assert_eq!(bytes.len(), 12);
+    let seqno = u32::from_ne_bytes(bytes[8..].try_into().unwrap());
+    assert_eq!(Some(seqno), trailer.seqno());
+}
+
+#[test]
+fn test_recv_sender_pid() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let mut buffer = Buffer::with_capacity(4096);
+
+    send_right.send(Builder::new(&mut buffer)).unwrap();
+    let options = RecvOptions::new().request_trailer(TrailerRequest::Audit);
+    let (header, _) = right
+        .recv_with_options(&mut buffer, options)
+        .unwrap()
+        .parse_header();
+
+    assert_eq!(header.sender_pid(), Some(std::process::id() as libc::pid_t));
+    assert!(header.trailer.audit_token().is_some());
+
+    // The audit token isn't included unless requested.
+    send_right.send(Builder::new(&mut buffer)).unwrap();
+    let options = RecvOptions::new().request_trailer(TrailerRequest::Sender);
+    let (header, _) = right
+        .recv_with_options(&mut buffer, options)
+        .unwrap()
+        .parse_header();
+
+    assert_eq!(header.sender_pid(), None);
+}
+
+#[test]
+fn test_desc_parser_counts() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_copied_send_right(&send_right);
+    builder.append_made_send_right(&right, true);
+    builder.append_made_send_right(&right, false);
+    send_right.send(builder).unwrap();
+
+    let parser = right.recv(&mut buffer).unwrap();
+    let (_header, mut parser) = parser.parse_header();
+
+    let mut remaining = 3;
+    while let DescOrBodyParser::Descriptor(desc_parser) = parser {
+        assert_eq!(desc_parser.total(), 3);
+        assert_eq!(desc_parser.remaining(), remaining);
+
+        remaining -= 1;
+        parser = desc_parser.next().1;
+    }
+
+    assert_eq!(remaining, 0);
+}
+
+/// Serializes a message with the specified header fields and no port names in the header.
+fn msg_bytes(bits: mach_msg_bits_t, size: mach_msg_size_t, id: MsgId, body: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&bits.to_ne_bytes());
+    bytes.extend_from_slice(&size.to_ne_bytes());
+    // the remote, local and voucher port names
+    bytes.extend_from_slice(&[0; 12]);
+    bytes.extend_from_slice(&id.to_ne_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+#[test]
+fn test_parse_from_bytes() {
+    let mut buffer = Buffer::from_bytes(&msg_bytes(0, 28, 1337, b"test"));
+    let parser = MsgParser::from_bytes(&mut buffer).unwrap();
+
+    assert_eq!(parser.trailer().size(), 0);
+
+    let (header, parser) = parser.parse_header();
+    assert_eq!(header.id, 1337);
+    assert!(header.reply_right.is_none());
+
+    check_msg!(parser body b"test");
+}
+
+#[test]
+fn test_parse_from_bytes_invalid() {
+    // msgh_size exceeds the size of the data
+    let mut buffer = Buffer::from_bytes(&msg_bytes(0, 32, 0, b"test"));
+    let error = MsgParser::from_bytes(&mut buffer).unwrap_err();
+    assert_eq!(error, ParseError::InvalidSize);
+
+    // the descriptor count doesn't fit into the message
+    let mut buffer = Buffer::from_bytes(&msg_bytes(MACH_MSGH_BITS_COMPLEX, 24, 0, &[]));
+    let error = MsgParser::from_bytes(&mut buffer).unwrap_err();
+    assert_eq!(error, ParseError::InvalidDescriptorCount);
+
+    // the descriptor count claims a descriptor not present in the message
+    let body = 1u32.to_ne_bytes();
+    let mut buffer = Buffer::from_bytes(&msg_bytes(MACH_MSGH_BITS_COMPLEX, 28, 0, &body));
+    let error = MsgParser::from_bytes(&mut buffer).unwrap_err();
+    assert_eq!(error, ParseError::InvalidDescriptorCount);
+}
+
+#[test]
+fn test_parse_from_bytes_rights() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let raw_name = right.as_raw_name();
+    let refs = send_refs(raw_name);
+
+    // the remote port names a live send right
+    let mut bytes = msg_bytes(MACH_MSG_TYPE_MOVE_SEND, 24, 0, &[]);
+    bytes[8..12].copy_from_slice(&raw_name.to_ne_bytes());
+    let mut buffer = Buffer::from_bytes(&bytes);
+    let error = MsgParser::from_bytes(&mut buffer).unwrap_err();
+    assert_eq!(error, ParseError::UntrustedRight);
+    assert_eq!(send_refs(raw_name), refs);
+
+    // a port descriptor moves a live receive right
+    let mut body = 1u32.to_ne_bytes().to_vec();
+    body.extend_from_slice(&raw_name.to_ne_bytes());
+    body.extend_from_slice(&[0; 6]);
+    body.push(MACH_MSG_TYPE_MOVE_RECEIVE as u8);
+    body.push(MACH_MSG_PORT_DESCRIPTOR as u8);
+    let bytes = msg_bytes(MACH_MSGH_BITS_COMPLEX, 40, 0, &body);
+    let mut buffer = Buffer::from_bytes(&bytes);
+    let error = MsgParser::from_bytes(&mut buffer).unwrap_err();
+    assert_eq!(error, ParseError::UntrustedRight);
+
+    assert_eq!(recv_refs(raw_name), Some(1));
+    assert_eq!(send_refs(raw_name), refs);
+
+    drop(send_right);
+}
+
+#[test]
+fn test_parse_all() {
+    let mut buffer = Buffer::from_bytes(&msg_bytes(0, 32, 1337, b"testdata"));
+    let msg = MsgParser::from_bytes(&mut buffer).unwrap().parse_all();
+
+    assert_eq!(msg.header.id, 1337);
+    assert!(msg.descriptors.is_empty());
+    assert_eq!(msg.body, b"testdata");
+
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(1024);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_copied_send_right(&send_right);
+    builder.append_made_send_right(&right, true);
+    builder.append_inline_data(b"test");
+    send_right.send(builder).unwrap();
+
+    let msg = right.recv(&mut buffer).unwrap().parse_all();
+
+    assert!(matches!(
+        msg.descriptors[..],
+        [ParsedMsgDesc::PortSend(_), ParsedMsgDesc::PortSendOnce(_)]
+    ));
+    assert_eq!(msg.body, b"test");
+}
+
+#[test]
+fn test_parse_importance_bits() {
+    // MACH_MSGH_BITS_RAISEIMP
+    let mut buffer = Buffer::from_bytes(&msg_bytes(0x2000_0000, 28, 1337, b"test"));
+    assert_eq!(buffer.inline_data_offset(), 0);
+
+    let parser = MsgParser::from_bytes(&mut buffer).unwrap();
+    let (header, parser) = parser.parse_header();
+    assert_eq!(header.id, 1337);
+    assert!(header.importance_raised);
+    assert!(!header.importance_held);
+
+    check_msg!(parser body b"test");
+}
+
+extern "C" {
+    fn mach_host_self() -> mach_port_t;
+    fn host_create_mach_voucher(
+        host: mach_port_t,
+        recipes: *const u8,
+        recipes_size: u32,
+        voucher: *mut mach_port_t,
+    ) -> kern_return_t;
+}
+
+/// Creates a voucher with a bank attribute, the way libdispatch does.
+fn create_voucher() -> SendRight {
+    // mach_voucher_attr_recipe_data_t { MACH_VOUCHER_ATTR_KEY_BANK,
+    // MACH_VOUCHER_ATTR_BANK_CREATE, MACH_VOUCHER_NULL, 0 }
+    let recipe: [u32; 4] = [3, 610, 0, 0];
+    let mut raw_name = MACH_PORT_NULL;
+    let result = unsafe {
+        host_create_mach_voucher(
+            mach_host_self(),
+            recipe.as_ptr() as *const u8,
+            mem::size_of_val(&recipe) as u32,
+            &mut raw_name,
+        )
+    };
+
+    assert_eq!(result, KERN_SUCCESS);
+    assert_ne!(raw_name, MACH_PORT_NULL);
+
+    SendRight::from_raw_name(raw_name)
+}
+
+/// Returns the reference count expected after releasing one reference.
+fn released(refs: Option<u32>) -> Option<u32> {
+    refs.and_then(|refs| refs.checked_sub(1))
+        .filter(|&refs| refs > 0)
+}
+
+#[test]
+fn test_send_recv_voucher() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    // Vouchers are deduplicated by the kernel, so only relative reference counts are checked.
+    let voucher = create_voucher();
+    let raw_name = voucher.as_raw_name();
+    let refs = send_refs(raw_name);
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_copied_voucher(&voucher);
+    builder.append_inline_data(b"test");
+    send_right.send(builder).unwrap();
+
+    // The sender's reference must be left intact after sending a copied voucher.
+    assert_eq!(send_refs(raw_name), refs);
+
+    let options = RecvOptions::new().voucher(true);
+    let parser = right.recv_with_options(&mut buffer, options).unwrap();
+    let (header, parser) = parser.parse_header();
+
+    check_msg!(parser body b"test");
+
+    let received = header.voucher.expect("expected a voucher");
+    let received_refs = send_refs(received.as_raw_name());
+    let received_name = received.as_raw_name();
+
+    // Dropping the received voucher releases exactly the reference passed with the message.
+    drop(received);
+    assert_eq!(send_refs(received_name), released(received_refs));
+
+    // A moved voucher that isn't requested by the receiver is released by the kernel.
+    let refs = send_refs(raw_name);
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_moved_voucher(voucher);
+    send_right.send(builder).unwrap();
+
+    let parser = right.recv(&mut buffer).unwrap();
+    let (header, _) = parser.parse_header();
+
+    assert!(header.voucher.is_none());
+    assert_eq!(send_refs(raw_name), released(refs));
+}
+
+#[test]
+fn test_copied_voucher_drop() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let voucher = create_voucher();
+    let raw_name = voucher.as_raw_name();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_copied_voucher(&voucher);
+    send_right.send(builder).unwrap();
+
+    let options = RecvOptions::new().voucher(true);
+    let bytes = right
+        .recv_with_options(&mut buffer, options)
+        .unwrap()
+        .into_raw_bytes();
+    let bits = mach_msg_bits_t::from_ne_bytes(bytes[..4].try_into().unwrap());
+    let received_name = mach_port_name_t::from_ne_bytes(bytes[16..20].try_into().unwrap());
+
+    // The copied voucher is received as moved.
+    assert_eq!((bits >> 16) & 0x1f, MACH_MSG_TYPE_MOVE_SEND);
+    let refs = send_refs(received_name);
+    assert!(refs.is_some());
+
+    // The parser releases the received reference when it's dropped.
+    let mut buffer = Buffer::from_bytes(&bytes);
+    drop(unsafe { MsgParser::from_bytes_with_rights(&mut buffer) }.unwrap());
+    assert_eq!(send_refs(received_name), released(refs));
+
+    // A copied voucher in a message that wasn't received is owned by the parser as well.
+    let refs = send_refs(raw_name);
+    let mut bytes = msg_bytes(MACH_MSG_TYPE_COPY_SEND << 16, 24, 0, &[]);
+    bytes[16..20].copy_from_slice(&voucher.clone().into_raw_name().to_ne_bytes());
+
+    let mut buffer = Buffer::from_bytes(&bytes);
+    let parser = unsafe { MsgParser::from_bytes_with_rights(&mut buffer) }.unwrap();
+    let (header, _) = parser.parse_header();
+    assert_eq!(send_refs(raw_name), refs.map(|refs| refs + 1));
+
+    drop(header);
+    assert_eq!(send_refs(raw_name), refs);
+}
+
+#[test]
+fn test_set_reply_and_voucher() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let reply_right = RecvRight::alloc();
+    let voucher = create_voucher();
+    let old_voucher = create_voucher();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    // Both values are overwritten.
+    builder.set_made_reply_port(&right, false);
+    builder.set_moved_voucher(old_voucher);
+    builder.set_reply_and_voucher(&reply_right, true, &voucher);
+    send_right.send(builder).unwrap();
+
+    let options = RecvOptions::new().voucher(true);
+    let parser = right.recv_with_options(&mut buffer, options).unwrap();
+    let (header, _) = parser.parse_header();
+
+    assert!(matches!(
+        header.reply_right,
+        Some(AnySendRight::SendOnce(_))
+    ));
+    assert!(header.voucher.is_some());
+}
+
+#[test]
+fn test_parse_mig_reply() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_ndr();
+    builder.append_inline_data(&KERN_INVALID_ARGUMENT.to_ne_bytes());
+    builder.append_inline_data(&1337u32.to_ne_bytes());
+    send_right.send(builder).unwrap();
+
+    let parser = right.recv(&mut buffer).unwrap();
+    let (_, parser) = parser.parse_header();
+    let DescOrBodyParser::Body(mut parser) = parser else {
+        panic!("expected a body parser");
+    };
+
+    assert_eq!(parser.parse_mig_reply(), Ok(KERN_INVALID_ARGUMENT));
+    assert_eq!(parser.body(), 1337u32.to_ne_bytes());
+
+    // Only the argument is left, so parsing another reply must fail.
+    assert_eq!(parser.parse_mig_reply(), Err(ParseError::Truncated));
+}
+
+#[test]
+fn test_parse_mig_reply_invalid_ndr() {
+    let mut ndr = ndr::local_bytes();
+    // NDR_record_t.int_rep
+    ndr[4] ^= 1;
+
+    let mut body = ndr.to_vec();
+    body.extend_from_slice(&KERN_SUCCESS.to_ne_bytes());
+
+    let size = (24 + body.len()) as mach_msg_size_t;
+    let mut buffer = Buffer::from_bytes(&msg_bytes(0, size, 0, &body));
+    let (_, parser) = MsgParser::from_bytes(&mut buffer).unwrap().parse_header();
+    let DescOrBodyParser::Body(mut parser) = parser else {
+        panic!("expected a body parser");
+    };
+
+    assert_eq!(parser.parse_mig_reply(), Err(ParseError::InvalidNdr));
+    assert_eq!(parser.body().len(), body.len());
+}
+
+#[test]
+fn test_ndr_round_trip() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_ndr();
+    builder.append_inline_data(b"test");
+    assert_eq!(
+        builder.as_slice()[24..24 + ndr::NDR_SIZE],
+        ndr::local_bytes()
+    );
+    send_right.send(builder).unwrap();
+
+    let (_, parser) = right.recv(&mut buffer).unwrap().parse_header();
+    let DescOrBodyParser::Body(mut parser) = parser else {
+        panic!("expected a body parser");
+    };
+
+    assert_eq!(parser.read_ndr(), Ok(()));
+    assert_eq!(parser.body(), b"test");
+
+    // The remaining data isn't an NDR record.
+    assert_eq!(parser.read_ndr(), Err(ParseError::Truncated));
+
+    let mut record = ndr::local_bytes();
+    // NDR_record_t.int_rep
+    record[4] ^= 1;
+
+    let size = (24 + record.len()) as mach_msg_size_t;
+    let mut buffer = Buffer::from_bytes(&msg_bytes(0, size, 0, &record));
+    let (_, parser) = MsgParser::from_bytes(&mut buffer).unwrap().parse_header();
+    let DescOrBodyParser::Body(mut parser) = parser else {
+        panic!("expected a body parser");
+    };
+
+    assert_eq!(parser.read_ndr(), Err(ParseError::InvalidNdr));
+    assert_eq!(parser.body(), record);
+}
+
+#[test]
+fn test_cstr_round_trip() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    assert_eq!(builder.append_cstr("hello"), Ok(()));
+    assert_eq!(builder.append_cstr("w\u{f6}rld"), Ok(()));
+    assert_eq!(builder.append_cstr(""), Ok(()));
+    assert_eq!(
+        builder.append_cstr("inter\0ior"),
+        Err(BuildError::InteriorNul)
+    );
+    // Pad the message to a multiple of 4 bytes.
+    builder.append_inline_data(b"\xff\xff");
+    assert_eq!(builder.as_slice().len(), 24 + 16);
+    send_right.send(builder).unwrap();
+
+    let (_, parser) = right.recv(&mut buffer).unwrap().parse_header();
+    let DescOrBodyParser::Body(mut parser) = parser else {
+        panic!("expected a body parser");
+    };
+
+    assert_eq!(parser.read_cstr(), Ok("hello"));
+    assert_eq!(parser.read_cstr(), Ok("w\u{f6}rld"));
+    assert_eq!(parser.read_cstr(), Ok(""));
+    assert_eq!(parser.read_cstr(), Err(ParseError::Truncated));
+    assert_eq!(parser.body(), b"\xff\xff");
+
+    let body = b"\xfftest\0\0\0";
+    let size = (24 + body.len()) as mach_msg_size_t;
+    let mut buffer = Buffer::from_bytes(&msg_bytes(0, size, 0, body));
+    let (_, parser) = MsgParser::from_bytes(&mut buffer).unwrap().parse_header();
+    let DescOrBodyParser::Body(mut parser) = parser else {
+        panic!("expected a body parser");
+    };
+
+    assert_eq!(parser.read_cstr(), Err(ParseError::InvalidUtf8));
+    assert_eq!(parser.body(), body);
+}
+
+/// Returns the major version of the running Darwin kernel.
+fn darwin_major() -> u32 {
+    extern "C" {
+        fn sysctlbyname(
+            name: *const std::ffi::c_char,
+            oldp: *mut std::ffi::c_void,
+            oldlenp: *mut usize,
+            newp: *mut std::ffi::c_void,
+            newlen: usize,
+        ) -> std::ffi::c_int;
+    }
+
+    let mut release = [0u8; 64];
+    let mut len = release.len();
+    let result = unsafe {
+        sysctlbyname(
+            c"kern.osrelease".as_ptr(),
+            release.as_mut_ptr().cast(),
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    assert_eq!(result, 0);
+
+    let release = std::ffi::CStr::from_bytes_until_nul(&release).unwrap();
+    let major = release.to_str().unwrap().split('.').next().unwrap();
+    major.parse().unwrap()
+}
+
+#[test]
+fn test_send_guarded_recv_right() {
+    const GUARD: u64 = 0x1337;
+
+    // Guarded port descriptors are supported since macOS 10.15 (Darwin 19).
+    if darwin_major() < 19 {
+        return;
+    }
+
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let guarded = GuardedRecvRight::alloc(GUARD);
+    let raw_guarded_name = guarded.as_raw_name();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_moved_guarded_recv_right(guarded, MACH_MSG_GUARD_FLAGS_NONE);
+    builder.append_inline_data(b"test");
+    send_right.send(builder).unwrap();
+
+    let options = RecvOptions::new().guarded_desc(true);
+    let guarded = {
+        let (_, parser) = right
+            .recv_with_options(&mut buffer, options)
+            .unwrap()
+            .parse_header();
+        let DescOrBodyParser::Descriptor(parser) = parser else {
+            panic!("expected a descriptor parser");
+        };
+
+        let (desc, parser) = parser.next();
+        check_msg!(parser body b"test");
+
+        let ParsedMsgDesc::PortGuardedRecv(guarded) = desc else {
+            panic!("expected a guarded receive right");
+        };
+        guarded
+    };
+
+    // The guard must survive the transfer.
+    assert_eq!(guarded.as_raw_name(), raw_guarded_name);
+    assert_eq!(guarded.guard(), GUARD);
+
+    // Send the right back asking the kernel to remove the guard.
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_moved_guarded_recv_right(guarded, MACH_MSG_GUARD_FLAGS_UNGUARDED_ON_SEND);
+    send_right.send(builder).unwrap();
+
+    let (_, parser) = right
+        .recv_with_options(&mut buffer, options)
+        .unwrap()
+        .parse_header();
+    check_msg!(parser desc PortRecv, body b"");
+}
+
+#[test]
+fn test_splice_resend() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_id(1337);
+    builder.append_inline_data(b"test");
+    send_right.send(builder).unwrap();
+
+    drop(right.recv(&mut buffer).unwrap());
+
+    // Prepend data to the received inline data.
+    let at = buffer.inline_data_offset();
+    assert_eq!(at, 0);
+    buffer.splice(at, b"abcd");
+
+    // The received header names the destination port, reset it before forwarding the message.
+    *buffer.header_mut() = mach2::message::mach_msg_header_t {
+        msgh_id: buffer.header().msgh_id,
+        ..Default::default()
+    };
+    // SAFETY: The message carries no rights.
+    send_right
+        .send(unsafe { Builder::from_contents(&mut buffer) })
+        .unwrap();
+
+    let parser = right.recv(&mut buffer).unwrap();
+    let (header, parser) = parser.parse_header();
+
+    assert_eq!(header.id, 1337);
+    check_msg!(parser body b"abcdtest");
+}
+
+#[test]
+fn test_rewrite_destination() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let other_right = RecvRight::alloc();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_id(1337);
+    builder.append_made_send_right(&other_right, false);
+    builder.append_inline_data(b"test");
+    builder.rewrite_destination(&send_right);
+
+    let bits = mach_msg_bits_t::from_ne_bytes(builder.as_slice()[..4].try_into().unwrap());
+    assert_ne!(bits & MACH_MSGH_BITS_COMPLEX, 0);
+    builder.send().unwrap();
+
+    let parser = right.recv(&mut buffer).unwrap();
+    let (header, parser) = parser.parse_header();
+
+    assert_eq!(header.id, 1337);
+    check_msg!(parser desc PortSend, body b"test");
+
+    // The destination is required.
+    let error = Builder::new(&mut buffer).send().unwrap_err();
+    assert!(matches!(error.kind(), SendErrorKind::InvalidDest));
+}
+
+#[test]
+fn test_forward() {
+    let gateway = RecvRight::alloc();
+    let target = RecvRight::alloc();
+    let carried = RecvRight::alloc();
+    let reply = RecvRight::alloc();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_id(1337);
+    builder.set_made_reply_port(&reply, true);
+    builder.append_made_send_right(&carried, false);
+    builder.append_inline_data(b"test");
+    gateway.make_send().send(builder).unwrap();
+
+    let parser = gateway.recv(&mut buffer).unwrap();
+    parser.forward(&target.make_send()).unwrap();
+
+    let msg = target.recv(&mut buffer).unwrap().parse_all();
+    assert_eq!(msg.header.id, 1337);
+    assert!(matches!(
+        msg.header.reply_right,
+        Some(AnySendRight::SendOnce(_))
+    ));
+    assert_eq!(msg.body, b"test");
+
+    // The forwarded send right still refers to the carried port.
+    let [ParsedMsgDesc::PortSend(send_right)] = &msg.descriptors[..] else {
+        panic!("expected a send right descriptor");
+    };
+    send_right.ping(&mut buffer, 1).unwrap();
+    let (header, _) = carried.recv(&mut buffer).unwrap().parse_header();
+    assert_eq!(header.id, 1);
+
+    // The gateway doesn't hold the forwarded message anymore.
+    assert!(gateway.peek_size(&mut buffer).is_err());
+}
+
+#[test]
+fn test_parser_id() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_id(1337);
+    builder.append_made_send_right(&right, false);
+    builder.append_inline_data(b"test");
+    send_right.send(builder).unwrap();
+
+    let (_, parser) = right.recv(&mut buffer).unwrap().parse_header();
+    let DescOrBodyParser::Descriptor(parser) = parser else {
+        panic!("expected a descriptor parser");
+    };
+    assert_eq!(parser.id(), 1337);
+
+    let (_, parser) = parser.next();
+    let DescOrBodyParser::Body(parser) = parser else {
+        panic!("expected a body parser");
+    };
+    assert_eq!(parser.id(), 1337);
+    assert_eq!(parser.body(), b"test");
+}
+
+#[test]
+fn test_into_raw_bytes() {
+    const HEADER_SIZE: usize = 24;
+
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_id(1337);
+    builder.append_inline_data(&[0xAA; 12]);
+    builder.append_inline_data(b"test");
+    let built = builder.as_slice().to_vec();
+    send_right.send(builder).unwrap();
+
+    let bytes = right.recv(&mut buffer).unwrap().into_raw_bytes();
+    assert_eq!(bytes.len(), built.len());
+
+    // The ports in the header are rewritten by the kernel.
+    let field = |offset: usize| u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    assert_eq!(field(4) as usize, bytes.len());
+    assert_eq!(field(12), right.as_raw_name());
+    assert_eq!(field(20) as i32, 1337);
+    assert_eq!(bytes[HEADER_SIZE..], built[HEADER_SIZE..]);
+}
+
+#[test]
+fn test_reply_with() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let reply_right = RecvRight::alloc();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_made_reply_port(&reply_right, true);
+    builder.append_inline_data(b"ping");
+    send_right.send(builder).unwrap();
+
+    let (mut header, parser) = right.recv(&mut buffer).unwrap().parse_header();
+    drop(parser);
+
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_id(1337);
+    builder.append_inline_data(b"pong");
+    header.reply_with(builder).unwrap();
+
+    let msg = reply_right.recv(&mut buffer).unwrap().parse_all();
+    assert_eq!(msg.header.id, 1337);
+    assert_eq!(msg.body, b"pong");
+
+    // The send once right is consumed by the first reply.
+    let error = header.reply_with(Builder::new(&mut buffer)).unwrap_err();
+    assert!(matches!(error.kind(), SendErrorKind::InvalidDest));
+}
+
+#[test]
+fn test_headroom() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    assert_eq!(buffer.headroom(), 4096);
+
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_inline_data(b"test");
+    send_right.send(builder).unwrap();
+
+    drop(right.recv(&mut buffer).unwrap());
+    assert_eq!(buffer.capacity(), 4096);
+    assert_eq!(buffer.headroom(), 4096 - 4);
+}
+
+#[test]
+fn test_descriptor_kinds() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let moved = RecvRight::alloc();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_made_send_right(&right, true);
+    builder.append_ool_data(b"test", builder::CopyKind::Virtual);
+    builder.append_copied_send_right(&send_right);
+    builder.append_moved_right(moved);
+    builder.append_inline_data(b"test");
+    send_right.send(builder).unwrap();
+
+    let parser = right.recv(&mut buffer).unwrap();
+    assert_eq!(
+        parser.descriptor_kinds(),
+        [
+            DescKind::PortSendOnce,
+            DescKind::Ool,
+            DescKind::PortSend,
+            DescKind::PortRecv
+        ]
+    );
+    // The walk doesn't consume anything.
+    assert_eq!(parser.descriptor_kinds().len(), 4);
+
+    let parsed = parser.parse_all();
+    assert!(matches!(
+        parsed.descriptors[..],
+        [
+            ParsedMsgDesc::PortSendOnce(_),
+            ParsedMsgDesc::OolData(_),
+            ParsedMsgDesc::PortSend(_),
+            ParsedMsgDesc::PortRecv(_)
+        ]
+    ));
+    assert_eq!(parsed.body, b"test");
+
+    send_right.send(Builder::new(&mut buffer)).unwrap();
+    assert!(right
+        .recv(&mut buffer)
+        .unwrap()
+        .descriptor_kinds()
+        .is_empty());
+}
+
+#[test]
+fn test_new_invalid_size() {
+    let mut buffer = Buffer::with_capacity(64);
+
+    // A header claiming the message is larger than the buffer.
+    buffer.header_mut().msgh_size = (mem::size_of::<mach2::message::mach_msg_header_t>() + 128)
+        .try_into()
+        .unwrap();
+    let error = MsgParser::new(&mut buffer, RecvOptions::new()).unwrap_err();
+    assert_eq!(error, ParseError::InvalidSize);
+
+    // A header claiming the message is smaller than the header itself.
+    buffer.header_mut().msgh_size = 16;
+    let error = MsgParser::new(&mut buffer, RecvOptions::new()).unwrap_err();
+    assert_eq!(error, ParseError::InvalidSize);
+}
+
+#[test]
+fn test_mixed_ool_descriptors() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let other = RecvRight::alloc();
+    let other_send = other.make_send();
+    let raw_other_name = other_send.as_raw_name();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_inline_data(b"test");
+    builder.append_ool_data(b"data", builder::CopyKind::Virtual);
+    builder.append_copied_ool_ports(std::slice::from_ref(&other_send));
+    builder.append_moved_ool_ports([other.make_send(), other.make_send()]);
+    builder.append_made_send_right(&right, true);
+    assert_eq!(builder.descriptor_count(), 4);
+    assert_eq!(builder.validate(), Ok(()));
+    send_right.send(builder).unwrap();
+
+    let parser = right.recv(&mut buffer).unwrap();
+    assert_eq!(
+        parser.descriptor_kinds(),
+        [
+            DescKind::Ool,
+            DescKind::OolPorts,
+            DescKind::OolPorts,
+            DescKind::PortSendOnce
+        ]
+    );
+
+    let msg = parser.parse_all();
+    let [ParsedMsgDesc::OolData(data), ParsedMsgDesc::OolPortsSend(copied), ParsedMsgDesc::OolPortsSend(moved), ParsedMsgDesc::PortSendOnce(_)] =
+        &msg.descriptors[..]
+    else {
+        panic!("unexpected descriptors {:?}", msg.descriptors);
+    };
+    assert_eq!(data, b"data");
+    assert_eq!(msg.body, b"test");
+
+    // The rights are merged into the name of the existing send right.
+    assert_eq!(copied.len(), 1);
+    assert_eq!(moved.len(), 2);
+    for right in copied.iter().chain(moved) {
+        assert_eq!(right.as_ref().unwrap().as_raw_name(), raw_other_name);
+    }
+    assert_eq!(send_refs(raw_other_name), Some(4));
+
+    drop(msg);
+    assert_eq!(send_refs(raw_other_name), Some(1));
+
+    // Empty arrays and dropping the unparsed descriptors.
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_moved_ool_ports(Vec::<SendRight>::new());
+    builder.append_moved_ool_ports([other.make_send()]);
+    send_right.send(builder).unwrap();
+
+    let (_, parser) = right.recv(&mut buffer).unwrap().parse_header();
+    let DescOrBodyParser::Descriptor(parser) = parser else {
+        panic!("expected a descriptor parser");
+    };
+    let (desc, parser) = parser.next();
+    assert!(matches!(desc, ParsedMsgDesc::OolPortsSend(rights) if rights.is_empty()));
+    assert_eq!(send_refs(raw_other_name), Some(2));
+
+    drop(parser);
+    assert_eq!(send_refs(raw_other_name), Some(1));
+}
+
+#[test]
+fn test_build_after_parse() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let other_right = RecvRight::alloc();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_id(1337);
+    builder.append_made_send_right(&other_right, false);
+    builder.append_inline_data(b"test");
+    send_right.send(builder).unwrap();
+
+    let msg = right.recv(&mut buffer).unwrap().parse_all();
+    assert_eq!(msg.descriptors.len(), 1);
+    drop(msg);
+
+    // The stale message is discarded, the new one doesn't inherit its descriptors.
+    let mut builder = Builder::new(&mut buffer);
+    assert!(!builder.is_complex());
+    assert_eq!(
+        builder.as_slice().len(),
+        mem::size_of::<mach_msg_header_t>()
+    );
+    builder.append_inline_data(b"next");
+    send_right.send(builder).unwrap();
+
+    let msg = right.recv(&mut buffer).unwrap().parse_all();
+    assert_eq!(msg.header.id, 0);
+    assert!(msg.descriptors.is_empty());
+    assert_eq!(msg.body, b"next");
+}
+
+#[test]
+fn test_forward_ool_data() {
+    let proxy = RecvRight::alloc();
+    let proxy_send = proxy.make_send();
+    let target = RecvRight::alloc();
+    let data: Vec<u8> = (0..0x8000).map(|i| i as u8).collect();
+
+    let mut buffer = Buffer::with_capacity(1024);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_copied_send_right(&proxy_send);
+    builder.append_ool_data(&data, builder::CopyKind::Virtual);
+    proxy_send.send(builder).unwrap();
+
+    let mut descriptors = proxy.recv(&mut buffer).unwrap().parse_all().descriptors;
+    let forwarded = descriptors.pop().unwrap().into_forwardable().unwrap();
+    let port = descriptors.pop().unwrap().into_forwardable().unwrap_err();
+    assert!(matches!(port, ParsedMsgDesc::PortSend(_)));
+
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_forwarded_ool(forwarded, builder::CopyKind::Virtual);
+    target.make_send().send(builder).unwrap();
+
+    let options = RecvOptions::new().ool_vec(true);
+    let msg = target
+        .recv_with_options(&mut buffer, options)
+        .unwrap()
+        .parse_all();
+    let [ParsedMsgDesc::OolDataVec(received)] = &msg.descriptors[..] else {
+        panic!("unexpected descriptors {:?}", msg.descriptors);
+    };
+    assert_eq!(received.as_slice(), data);
+}
+
+#[test]
+fn test_clone_reply_send() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let reply_right = RecvRight::alloc();
+    let mut buffer = Buffer::with_capacity(1024);
+
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_made_reply_port(&reply_right, false);
+    send_right.send(builder).unwrap();
+
+    let (mut header, _) = right.recv(&mut buffer).unwrap().parse_header();
+    let cloned = header.clone_reply_send().unwrap();
+    let raw_name = cloned.as_raw_name();
+    assert_eq!(send_refs(raw_name), Some(2));
+
+    // Both the clone and the original reach the reply port.
+    cloned.ping(&mut buffer, 1).unwrap();
+    header.reply_with(Builder::new(&mut buffer)).unwrap();
+    assert_eq!(send_refs(raw_name), Some(1));
+
+    let (first, _) = reply_right.recv(&mut buffer).unwrap().parse_header();
+    assert_eq!(first.id, 1);
+    let (second, _) = reply_right.recv(&mut buffer).unwrap().parse_header();
+    assert_eq!(second.id, 0);
+
+    // Send once rights can't be cloned.
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_made_reply_port(&reply_right, true);
+    send_right.send(builder).unwrap();
+
+    let (header, _) = right.recv(&mut buffer).unwrap().parse_header();
+    assert!(matches!(
+        header.reply_right,
+        Some(AnySendRight::SendOnce(_))
+    ));
+    assert!(header.clone_reply_send().is_none());
+}
+
+#[test]
+fn test_ool_volatile() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let data = vec![0xa5; 0x4000];
+
+    let mut buffer = Buffer::with_capacity(1024);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_ool_volatile(&data);
+    builder.append_inline_data(b"test");
+    assert_eq!(builder.validate(), Ok(()));
+    send_right.send(builder).unwrap();
+
+    let parser = right.recv(&mut buffer).unwrap();
+    assert_eq!(parser.descriptor_kinds(), [DescKind::Ool]);
+
+    let msg = parser.parse_all();
+    let [ParsedMsgDesc::OolData(received)] = &msg.descriptors[..] else {
+        panic!("unexpected descriptors {:?}", msg.descriptors);
+    };
+    assert_eq!(received, &data[..]);
+    assert_eq!(msg.body, b"test");
+
+    // Dropping a builder with a volatile descriptor leaves the data intact.
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_ool_volatile(&data);
+    builder.discard();
+    assert!(data.iter().all(|&byte| byte == 0xa5));
+}
+
+#[test]
+fn test_wire_size() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let mut buffer = Buffer::with_capacity(1024);
+
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_copied_send_right(&send_right);
+    builder.append_ool_data(b"data", builder::CopyKind::Virtual);
+    builder.append_inline_data(b"testdata");
+    send_right.send(builder).unwrap();
+
+    // The header, the descriptor count, a port and an OOL descriptor and the inline data.
+    let expected_size = mem::size_of::<mach_msg_header_t>() + 4 + 12 + 16 + 8;
+    let parser = right.recv(&mut buffer).unwrap();
+    assert_eq!(parser.wire_size(), expected_size);
+
+    let (_, mut parser) = parser.parse_header();
+    let parser = loop {
+        match parser {
+            DescOrBodyParser::Descriptor(desc_parser) => parser = desc_parser.next().1,
+            DescOrBodyParser::Body(body_parser) => break body_parser,
+        }
+    };
+    assert_eq!(parser.total_wire_size(), expected_size);
+    assert_eq!(parser.body(), b"testdata");
+}
+
+#[test]
+fn test_clear_descriptors() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let raw_name = send_right.as_raw_name();
+    let mut buffer = Buffer::with_capacity(1024);
+
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_id(1337);
+    builder.append_inline_data(b"te");
+    builder.append_moved_right(right.make_send());
+    builder.append_ool_data(b"data", builder::CopyKind::Virtual);
+    builder.append_inline_data(b"st");
+    assert_eq!(send_refs(raw_name), Some(2));
+
+    builder.clear_descriptors();
+    assert!(!builder.is_complex());
+    assert_eq!(builder.descriptor_count(), 0);
+    assert_eq!(
+        builder.as_slice().len(),
+        mem::size_of::<mach_msg_header_t>() + 4
+    );
+    assert_eq!(send_refs(raw_name), Some(1));
+
+    // Clearing a simple message does nothing and descriptors may be appended again.
+    builder.clear_descriptors();
+    builder.append_made_send_right(&right, true);
+    builder.clear_descriptors();
+    builder.append_inline_data(b"data");
+    send_right.send(builder).unwrap();
+
+    let msg = right.recv(&mut buffer).unwrap().parse_all();
+    assert_eq!(msg.header.id, 1337);
+    assert!(msg.descriptors.is_empty());
+    assert_eq!(msg.body, b"testdata");
+}
+
+#[test]
+fn test_raw_port_desc() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let raw_name = send_right.as_raw_name();
+
+    let made = mach_msg_port_descriptor_t::new(raw_name, MACH_MSG_TYPE_MAKE_SEND_ONCE);
+    assert_eq!(DescKind::from(&made), DescKind::PortSendOnce);
+
+    let desc = mach_msg_port_descriptor_t::new(send_right.into_raw_name(), MACH_MSG_TYPE_MOVE_SEND);
+    assert_eq!(DescKind::from(&desc), DescKind::PortSend);
+
+    // SAFETY: The reference of the send right was moved into the descriptor.
+    let ParsedMsgDesc::PortSend(send_right) = (unsafe { ParsedMsgDesc::from_raw_port_desc(&desc) })
+    else {
+        panic!("expected a send right");
+    };
+    assert_eq!(send_right.as_raw_name(), raw_name);
+    assert_eq!(send_refs(raw_name), Some(1));
+
+    drop(send_right);
+    assert_eq!(send_refs(raw_name), None);
+}
+
+#[test]
+fn test_inline_data_offset() {
+    let right = RecvRight::alloc();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_made_send_right(&right, false);
+    builder.append_ool_data(b"data", builder::CopyKind::Virtual);
+    builder.append_inline_data(b"test");
+    // Discarding the builder leaves the descriptors in the buffer.
+    builder.discard();
+
+    // the descriptor count, a port and an OOL descriptor
+    let at = buffer.inline_data_offset();
+    assert_eq!(at, 4 + 12 + 16);
+    assert_eq!(&buffer.as_slice()[24 + at..], b"test");
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn test_debug_state() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(1024);
+    assert_eq!(buffer.debug_state(), BufferState::Empty);
+
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_inline_data(b"test");
+    send_right.send(builder).unwrap();
+    assert_eq!(buffer.debug_state(), BufferState::Building);
+
+    drop(right.recv(&mut buffer).unwrap());
+    assert_eq!(buffer.debug_state(), BufferState::Received);
+
+    buffer.clear();
+    assert_eq!(buffer.debug_state(), BufferState::Empty);
+    assert!(buffer.as_slice().iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn test_error_raw() {
+    let raw = MACH_SEND_INVALID_DEST | MACH_MSG_IPC_SPACE;
+    assert_eq!(SendError::from_bits(raw).raw(), raw);
+
+    let raw = MACH_RCV_TIMED_OUT;
+    assert_eq!(RecvError::from_bits(raw).raw(), raw);
+
+    // Codes without a corresponding error kind are preserved.
+    let error = RecvError::from_bits(0x1000_4fff);
+    assert!(matches!(error.kind(), RecvErrorKind::Other));
+    assert_eq!(error.raw(), 0x1000_4fff);
+}
+
+#[test]
+fn test_error_unknown_code() {
+    let error = SendError::from_bits(0x1000_00ff | MACH_MSG_IPC_SPACE);
+    assert!(matches!(error.kind(), SendErrorKind::Other));
+    assert_eq!(error.to_string(), "0x100000ff|MACH_MSG_IPC_SPACE");
+
+    let error = RecvError::from_bits(-1);
+    assert!(matches!(error.kind(), RecvErrorKind::Other));
+    error.to_string();
+}
+
+#[test]
+fn test_for_each_ool() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let other_right = RecvRight::alloc();
+    let other_send_right = other_right.make_send();
+    let raw_name = other_send_right.as_raw_name();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_copied_send_right(&other_send_right);
+    builder.append_ool_data(b"first", builder::CopyKind::Virtual);
+    builder.append_copied_send_right(&other_send_right);
+    builder.append_ool_data(b"second", builder::CopyKind::Physical);
+    builder.append_inline_data(b"test");
+    send_right.send(builder).unwrap();
+
+    let (_, parser) = right.recv(&mut buffer).unwrap().parse_header();
+    assert_eq!(send_refs(raw_name), Some(3));
+
+    let DescOrBodyParser::Descriptor(parser) = parser else {
+        panic!("expected a descriptor parser");
+    };
+    let mut buffers = vec![];
+    let parser = parser.for_each_ool(|buf| buffers.push(buf));
+
+    assert_eq!(buffers, [&b"first"[..], b"second"]);
+    assert_eq!(parser.body(), b"test");
+    // The references from the skipped port descriptors are released.
+    assert_eq!(send_refs(raw_name), Some(1));
+}
+
+#[test]
+fn test_bulk_ool() {
+    const SIZE: usize = 2 * 1024 * 1024;
+
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut data = ool::OolVec::with_capacity(SIZE);
+    data.extend((0..=255).cycle().take(SIZE));
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.set_bulk_ool(data.into_buf(), builder::CopyKind::Virtual);
+    send_right.send(builder).unwrap();
+
+    let data = right.recv_bulk_ool(&mut buffer).unwrap().unwrap();
+    assert_eq!(data.len(), SIZE);
+    assert!(data.iter().copied().eq((0..=255).cycle().take(SIZE)));
+
+    // Messages with any other layout are rejected.
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_ool_data(b"data", builder::CopyKind::Virtual);
+    builder.append_inline_data(b"test");
+    send_right.send(builder).unwrap();
+    assert!(right.recv_bulk_ool(&mut buffer).unwrap().is_none());
+
+    // The descriptors of rejected messages are released.
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_ool_data(b"data", builder::CopyKind::Virtual);
+    builder.append_ool_data(b"more", builder::CopyKind::Physical);
+    send_right.send(builder).unwrap();
+    assert!(right.recv_bulk_ool(&mut buffer).unwrap().is_none());
+
+    send_right.ping(&mut buffer, 1).unwrap();
+    assert!(right.recv_bulk_ool(&mut buffer).unwrap().is_none());
+}
+
+#[test]
+fn test_forward_ool() {
+    let page_size = page_size::get_granularity();
+    let proxy = RecvRight::alloc();
+    let target = RecvRight::alloc();
+
+    let data = ool::OolVec::with_capacity_filled(page_size * 3, 0x5A);
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_consumed_ool_data(data.into_buf(), builder::CopyKind::Virtual);
+    builder.append_inline_data(b"test");
+    proxy.make_send().send(builder).unwrap();
+
+    let msg = proxy.recv(&mut buffer).unwrap().parse_all();
+    let mut descriptors = msg.descriptors.into_iter();
+    let (Some(ParsedMsgDesc::OolData(data)), None) = (descriptors.next(), descriptors.next())
+    else {
+        panic!("expected an out-of-line data descriptor");
+    };
+
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_forwarded_ool(data, builder::CopyKind::Virtual);
+    builder.append_inline_data(&msg.body);
+    target.make_send().send(builder).unwrap();
+
+    let msg = target.recv(&mut buffer).unwrap().parse_all();
+    let [ParsedMsgDesc::OolData(data)] = &msg.descriptors[..] else {
+        panic!("expected an out-of-line data descriptor");
+    };
+    assert_eq!(data.len(), page_size * 3);
+    assert!(data.iter().all(|&byte| byte == 0x5A));
+    assert_eq!(msg.body, b"test");
+}
+
+#[test]
+fn test_forward_dead_dest() {
+    let proxy = RecvRight::alloc();
+    let carried = RecvRight::alloc();
+    let dead_name = {
+        let port = RecvRight::alloc();
+        let send_right = port.make_send();
+        drop(port);
+        send_right
+    };
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_ool_data(&[0x5A; 64], builder::CopyKind::Physical);
+    builder.append_made_send_right(&carried, false);
+    proxy.make_send().send(builder).unwrap();
+
+    let parser = proxy.recv(&mut buffer).unwrap();
+    let error = parser.forward(&dead_name).unwrap_err();
+    assert!(matches!(error.kind(), SendErrorKind::InvalidDest));
+
+    // The out-of-line memory is marked to be deallocated and the received send right is released.
+    let at = 24 + 4 + mem::offset_of!(mach_msg_ool_descriptor_t, deallocate);
+    assert_eq!(buffer.as_slice()[at], 1);
+    let rights = crate::task::rights_for(carried.as_raw_name()).unwrap();
+    assert!(rights.receive() && !rights.send());
+}
+
+#[test]
+fn test_recv_ool_vec() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_ool_data(b"data", builder::CopyKind::Physical);
+    send_right.send(builder).unwrap();
+
+    let options = RecvOptions::new().ool_vec(true);
+    let (_, parser) = right
+        .recv_with_options(&mut buffer, options)
+        .unwrap()
+        .parse_header();
+    let DescOrBodyParser::Descriptor(parser) = parser else {
+        panic!("expected a descriptor parser");
+    };
+    let (ParsedMsgDesc::OolDataVec(mut data), _) = parser.next() else {
+        panic!("expected an out-of-line data vector");
+    };
+
+    assert_eq!(data, b"data");
+    assert_eq!(data.len(), data.capacity());
+
+    data.reserve(1);
+    data.push(b'!');
+    assert_eq!(data, b"data!");
+}
+
+#[test]
+fn test_recv_scatter() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_ool_data(b"first", builder::CopyKind::Physical);
+    builder.append_ool_data(b"second", builder::CopyKind::Virtual);
+    builder.append_ool_data(b"third", builder::CopyKind::Physical);
+    send_right.send(builder).unwrap();
+
+    let mut regions = [
+        ool::OolVec::with_capacity(4096),
+        ool::OolVec::with_capacity_filled(4096, 0xAA),
+    ];
+    let msg = right
+        .recv_scatter(&mut buffer, &mut regions)
+        .unwrap()
+        .parse_all();
+
+    assert_eq!(regions[0], *b"first");
+    assert_eq!(regions[1], *b"second");
+    assert_eq!(regions[1].capacity(), 4096);
+
+    // The moved descriptors are empty, the one without a region is received as usual.
+    let [ParsedMsgDesc::OolData(first), ParsedMsgDesc::OolData(second), ParsedMsgDesc::OolData(third)] =
+        &msg.descriptors[..]
+    else {
+        panic!("unexpected descriptors {:?}", msg.descriptors);
+    };
+    assert!(first.is_empty());
+    assert!(second.is_empty());
+    assert_eq!(third, b"third");
+}
+
+#[test]
+fn test_recv_scatter_small() {
+    let right = RecvRight::alloc();
+    let send_right = right.make_send();
+    let port_right = RecvRight::alloc().make_send();
+    let name = port_right.as_raw_name();
+
+    let mut buffer = Buffer::with_capacity(4096);
+    let mut builder = Builder::new(&mut buffer);
+    builder.append_ool_data(b"data", builder::CopyKind::Physical);
+    builder.append_ool_data(&[0; 8192], builder::CopyKind::Virtual);
+    builder.append_moved_right(port_right.clone());
+    send_right.send(builder).unwrap();
+
+    let mut regions = [
+        ool::OolVec::with_capacity(4096),
+        ool::OolVec::with_capacity(4096),
+    ];
+    let error = right.recv_scatter(&mut buffer, &mut regions).unwrap_err();
+
+    assert!(matches!(error.kind(), RecvErrorKind::ScatterSmall));
+    assert_eq!(regions[0], *b"data");
+    assert!(regions[1].is_empty());
+    // The message has been destroyed releasing the right it carried.
+    assert_eq!(send_refs(name), Some(1));
+}
@@ -0,0 +1,189 @@
+//! Provides the [`MsgTrailer`] structure representing the trailer of a received Mach message.
+//!
+//! The kernel appends a trailer to every received message. By default the trailer is minimal and
+//! only contains its type and size, but additional elements may be requested using
+//! [`RecvOptions::trailer`](super::RecvOptions::trailer) or
+//! [`RecvOptions::request_trailer`](super::RecvOptions::request_trailer).
+
+use mach2::{
+    message::{
+        audit_token_t, mach_msg_trailer_size_t, mach_msg_trailer_t, mach_msg_trailer_type_t,
+        security_token_t, MACH_RCV_TRAILER_AUDIT, MACH_RCV_TRAILER_AV, MACH_RCV_TRAILER_CTX,
+        MACH_RCV_TRAILER_LABELS, MACH_RCV_TRAILER_NULL, MACH_RCV_TRAILER_SENDER,
+        MACH_RCV_TRAILER_SEQNO,
+    },
+    port::{mach_port_name_t, mach_port_seqno_t},
+};
+use std::{cmp, mem, ptr, slice};
+
+#[link(name = "bsm", kind = "dylib")]
+extern "C" {
+    fn audit_token_to_pid(atoken: audit_token_t) -> libc::pid_t;
+}
+
+/// The largest trailer the kernel may append to a message (`mach_msg_mac_trailer_t`).
+///
+/// This isn't provided by mach2. Trailers are packed to 4 bytes in the system headers, so the
+/// context field is unaligned.
+#[repr(C, packed(4))]
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct MacTrailer {
+    pub msgh_trailer_type: mach_msg_trailer_type_t,
+    pub msgh_trailer_size: mach_msg_trailer_size_t,
+    pub msgh_seqno: mach_port_seqno_t,
+    pub msgh_sender: security_token_t,
+    pub msgh_audit: audit_token_t,
+    pub msgh_context: u64,
+    pub msgh_ad: i32,
+    pub msgh_labels_sender: mach_port_name_t,
+}
+
+/// The trailer elements that may be requested when receiving a message.
+///
+/// Each request includes all the elements of the preceding ones, e.g. the audit trailer also
+/// contains the sequence number and the security token of the sender.
+#[repr(u32)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[non_exhaustive]
+pub enum TrailerRequest {
+    /// The minimal trailer containing only its type and size (`MACH_RCV_TRAILER_NULL`).
+    Null = MACH_RCV_TRAILER_NULL,
+    /// The sequence number of the message (`MACH_RCV_TRAILER_SEQNO`).
+    Seqno = MACH_RCV_TRAILER_SEQNO,
+    /// The security token of the sender (`MACH_RCV_TRAILER_SENDER`).
+    Sender = MACH_RCV_TRAILER_SENDER,
+    /// The audit token of the sender (`MACH_RCV_TRAILER_AUDIT`).
+    Audit = MACH_RCV_TRAILER_AUDIT,
+    /// The context of the receive right (`MACH_RCV_TRAILER_CTX`).
+    Context = MACH_RCV_TRAILER_CTX,
+    /// The access vector element (`MACH_RCV_TRAILER_AV`).
+    Av = MACH_RCV_TRAILER_AV,
+    /// The MAC label of the sender (`MACH_RCV_TRAILER_LABELS`).
+    Labels = MACH_RCV_TRAILER_LABELS,
+}
+
+impl TrailerRequest {
+    /// Returns the `MACH_RCV_TRAILER_*` elements value corresponding to the request.
+    #[inline]
+    pub const fn elements(self) -> mach_msg_trailer_type_t {
+        self as mach_msg_trailer_type_t
+    }
+
+    /// Returns the size of the trailer containing the requested elements.
+    pub const fn size(self) -> usize {
+        match self {
+            Self::Null => mem::size_of::<mach_msg_trailer_t>(),
+            Self::Seqno => mem::offset_of!(MacTrailer, msgh_sender),
+            Self::Sender => mem::offset_of!(MacTrailer, msgh_audit),
+            Self::Audit => mem::offset_of!(MacTrailer, msgh_context),
+            Self::Context => mem::offset_of!(MacTrailer, msgh_ad),
+            Self::Av => mem::offset_of!(MacTrailer, msgh_labels_sender),
+            Self::Labels => mem::size_of::<MacTrailer>(),
+        }
+    }
+}
+
+/// A copy of the trailer of a received Mach message.
+///
+/// Accessors for trailer elements return `None` in case the kernel didn't include the element in
+/// the trailer, either because it wasn't requested or because the kernel doesn't support it.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct MsgTrailer(MacTrailer);
+
+macro_rules! trailer_has {
+    ($trailer:expr, $field:ident) => {{
+        let end = mem::offset_of!(MacTrailer, $field) + mem::size_of_val(&{ $trailer.$field });
+        ($trailer.msgh_trailer_size as usize) >= end
+    }};
+}
+
+impl MsgTrailer {
+    /// Copies a trailer from the bytes following a received message.
+    ///
+    /// Only the bytes covered by the trailer size are copied, elements the trailer doesn't contain
+    /// are left zeroed.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let mut raw = MacTrailer::default();
+
+        if bytes.len() >= mem::size_of::<mach_msg_trailer_t>() {
+            // SAFETY: The slice is long enough to contain the trailer header.
+            let header: mach_msg_trailer_t =
+                unsafe { ptr::read_unaligned(bytes.as_ptr() as *const mach_msg_trailer_t) };
+            let len = cmp::min(
+                cmp::min(header.msgh_trailer_size as usize, bytes.len()),
+                mem::size_of::<MacTrailer>(),
+            );
+
+            // SAFETY: len is limited by both the source slice and the destination structure
+            // sizes.
+            unsafe {
+                ptr::copy_nonoverlapping(bytes.as_ptr(), &mut raw as *mut _ as *mut u8, len);
+            }
+
+            raw.msgh_trailer_size = len as mach_msg_trailer_size_t;
+        }
+
+        Self(raw)
+    }
+
+    /// Returns the trailer type (the trailer format) reported by the kernel.
+    #[inline]
+    pub fn trailer_type(&self) -> mach_msg_trailer_type_t {
+        self.0.msgh_trailer_type
+    }
+
+    /// Returns the size of the trailer in bytes.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.0.msgh_trailer_size as usize
+    }
+
+    /// Returns the raw bytes of the trailer elements corresponding to a request if the trailer
+    /// contains them.
+    ///
+    /// The returned slice starts with the trailer header and is exactly [`TrailerRequest::size`]
+    /// bytes long, so it may be reinterpreted as the corresponding `mach_msg_*_trailer_t`
+    /// structure.
+    pub fn bytes(&self, request: TrailerRequest) -> Option<&[u8]> {
+        let len = request.size();
+
+        // SAFETY: The structure only contains integer fields and isn't smaller than the largest
+        // request.
+        (self.size() >= len)
+            .then(|| unsafe { slice::from_raw_parts(&self.0 as *const _ as *const u8, len) })
+    }
+
+    /// Returns the sequence number of the message if the trailer contains it.
+    pub fn seqno(&self) -> Option<mach_port_seqno_t> {
+        trailer_has!(self.0, msgh_seqno).then(|| self.0.msgh_seqno)
+    }
+
+    /// Returns the security token of the sender if the trailer contains it.
+    pub fn sender(&self) -> Option<security_token_t> {
+        trailer_has!(self.0, msgh_sender).then(|| self.0.msgh_sender)
+    }
+
+    /// Returns the audit token of the sender if the trailer contains it.
+    pub fn audit_token(&self) -> Option<audit_token_t> {
+        trailer_has!(self.0, msgh_audit).then(|| self.0.msgh_audit)
+    }
+
+    /// Returns the PID of the sender from the audit token if the trailer contains it.
+    ///
+    /// PIDs are reused, so the audit token should be used to identify the sender in case the
+    /// result is used for security decisions.
+    pub fn sender_pid(&self) -> Option<libc::pid_t> {
+        // SAFETY: The function only reads a field of the token.
+        self.audit_token()
+            .map(|token| unsafe { audit_token_to_pid(token) })
+    }
+
+    /// Returns the sender's MAC label port name from the `mach_msg_mac_trailer_t` if the trailer
+    /// contains it.
+    ///
+    /// Recent kernels don't populate the label and always report `MACH_PORT_NULL` even if the
+    /// labels trailer was requested.
+    pub fn labels_sender(&self) -> Option<mach_port_name_t> {
+        trailer_has!(self.0, msgh_labels_sender).then(|| self.0.msgh_labels_sender)
+    }
+}
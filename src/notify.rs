@@ -0,0 +1,327 @@
+//! Provides a wrapper for receive rights used to receive kernel notifications.
+//!
+//! Notification requests are registered using `mach_port_request_notification` and are one-shot:
+//! once the kernel delivers a notification the request is consumed and has to be registered again
+//! to receive the next one. [`NotificationPort`] registers the requests and parses the received
+//! notification messages into [`Notification`] values.
+
+use crate::{
+    msg::{ndr::NDR_SIZE, Buffer, MsgId, ParsedMsgDesc, RecvError, RecvOptions, TrailerRequest},
+    rights::{mach_port_request_notification, RecvRight, SendOnceRight, SendRight},
+    traits::*,
+};
+use mach2::{
+    kern_return::{kern_return_t, KERN_SUCCESS},
+    message::MACH_MSG_TYPE_MAKE_SEND_ONCE,
+    port::{mach_port_mscount_t, mach_port_name_t, mach_port_t, MACH_PORT_NULL},
+    traps,
+};
+
+/// The ID of a port-deleted notification message, not provided by mach2.
+const MACH_NOTIFY_PORT_DELETED: MsgId = 0o101;
+/// The ID of a send-possible notification message, not provided by mach2.
+pub(crate) const MACH_NOTIFY_SEND_POSSIBLE: MsgId = 0o102;
+/// The ID of a port-destroyed notification message, not provided by mach2.
+const MACH_NOTIFY_PORT_DESTROYED: MsgId = 0o105;
+/// The ID of a no-senders notification message, not provided by mach2.
+const MACH_NOTIFY_NO_SENDERS: MsgId = 0o106;
+/// The ID of a send-once notification message, not provided by mach2.
+const MACH_NOTIFY_SEND_ONCE: MsgId = 0o107;
+/// The ID of a dead-name notification message, not provided by mach2.
+const MACH_NOTIFY_DEAD_NAME: MsgId = 0o110;
+
+/// A notification received by a [`NotificationPort`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Notification {
+    /// The name the request was registered for was deallocated (`MACH_NOTIFY_PORT_DELETED`).
+    ///
+    /// The name is no longer valid and may already denote a different right.
+    PortDeleted(mach_port_name_t),
+    /// A message may be sent using the send right the request was registered for
+    /// (`MACH_NOTIFY_SEND_POSSIBLE`).
+    SendPossible(mach_port_name_t),
+    /// The receive right the request was registered for was destroyed and is returned to the
+    /// notify port (`MACH_NOTIFY_PORT_DESTROYED`).
+    PortDestroyed(RecvRight),
+    /// The last send right to the port the request was registered for was released
+    /// (`MACH_NOTIFY_NO_SENDERS`). Contains the make-send count of the port.
+    NoSenders(mach_port_mscount_t),
+    /// A send-once right to the notify port was released without being used
+    /// (`MACH_NOTIFY_SEND_ONCE`), e.g. because a request was replaced.
+    SendOnce,
+    /// The port the request was registered for died (`MACH_NOTIFY_DEAD_NAME`).
+    ///
+    /// The notification carries a dead name reference on the name which is released when the
+    /// wrapper is dropped.
+    DeadName(SendRight),
+    /// A message that is not a notification sent by the kernel or a malformed notification.
+    ///
+    /// The rights carried by the message are released.
+    Unknown(MsgId),
+}
+
+/// A receive right wrapper used as the notify port of notification requests.
+///
+/// # Example
+/// ```
+/// # use mach_ports::{msg::Buffer, notify::{Notification, NotificationPort}, rights::RecvRight};
+/// # use mach_ports::traits::AsRawName;
+/// let port = RecvRight::alloc();
+/// let send_right = port.make_send();
+/// let notify = NotificationPort::alloc();
+/// notify.request_dead_name(&send_right).unwrap();
+/// drop(port);
+///
+/// let mut buffer = Buffer::with_capacity(1024);
+/// let Notification::DeadName(name) = notify.next_notification(&mut buffer).unwrap() else {
+///     unreachable!()
+/// };
+/// assert_eq!(name.as_raw_name(), send_right.as_raw_name());
+/// ```
+#[derive(Debug)]
+pub struct NotificationPort(RecvRight);
+
+impl NotificationPort {
+    /// Allocates a new receive right to be used as the notify port.
+    pub fn alloc() -> Self {
+        Self(RecvRight::alloc())
+    }
+
+    /// Wraps an existing receive right.
+    pub fn from_recv_right(right: RecvRight) -> Self {
+        Self(right)
+    }
+
+    /// Returns the wrapped receive right.
+    pub fn recv_right(&self) -> &RecvRight {
+        &self.0
+    }
+
+    /// Unwraps the receive right.
+    pub fn into_recv_right(self) -> RecvRight {
+        self.0
+    }
+
+    fn request(&self, name: mach_port_t, id: MsgId, sync: u32) -> Result<(), kern_return_t> {
+        let mut previous = MACH_PORT_NULL;
+        let result = unsafe {
+            mach_port_request_notification(
+                traps::mach_task_self(),
+                name,
+                id,
+                sync,
+                self.0.as_raw_name(),
+                MACH_MSG_TYPE_MAKE_SEND_ONCE,
+                &mut previous,
+            )
+        };
+
+        if result != KERN_SUCCESS {
+            return Err(result);
+        }
+
+        // The kernel sends a send-once notification to the notify port of the replaced request.
+        if previous != MACH_PORT_NULL {
+            drop(SendOnceRight::from_raw_name(previous));
+        }
+
+        Ok(())
+    }
+
+    /// Requests a dead-name notification for the right.
+    ///
+    /// In case the port is already dead the notification is sent immediately. A request
+    /// previously registered for the name is replaced.
+    ///
+    /// # Errors
+    /// Returns the error code returned by `mach_port_request_notification`.
+    pub fn request_dead_name<T: AsRawName>(&self, right: &T) -> Result<(), kern_return_t> {
+        self.request(right.as_raw_name(), MACH_NOTIFY_DEAD_NAME, 1)
+    }
+
+    /// Requests a no-senders notification for the port.
+    ///
+    /// The notification is sent once no send rights to the port exist and the make-send count of
+    /// the port is at least `sync`, immediately in case that is already true.
+    ///
+    /// # Errors
+    /// Returns the error code returned by `mach_port_request_notification`.
+    pub fn request_no_senders<T: AsRawName<Base = RecvRight>>(
+        &self,
+        right: &T,
+        sync: mach_port_mscount_t,
+    ) -> Result<(), kern_return_t> {
+        self.request(right.as_raw_name(), MACH_NOTIFY_NO_SENDERS, sync)
+    }
+
+    /// Requests a port-destroyed notification for the receive right.
+    ///
+    /// Instead of destroying the port when the receive right is released, the kernel moves the
+    /// right to the notify port.
+    ///
+    /// # Errors
+    /// Returns the error code returned by `mach_port_request_notification`.
+    pub fn request_port_destroyed<T: AsRawName<Base = RecvRight>>(
+        &self,
+        right: &T,
+    ) -> Result<(), kern_return_t> {
+        self.request(right.as_raw_name(), MACH_NOTIFY_PORT_DESTROYED, 0)
+    }
+
+    /// Requests a send-possible notification for the send right.
+    ///
+    /// The request is armed immediately, so the notification is sent once the message queue of
+    /// the port has room for a message, see also [`SendRight::send_or_arm`].
+    ///
+    /// # Errors
+    /// Returns the error code returned by `mach_port_request_notification`.
+    pub fn request_send_possible(&self, right: &SendRight) -> Result<(), kern_return_t> {
+        self.request(right.as_raw_name(), MACH_NOTIFY_SEND_POSSIBLE, 1)
+    }
+
+    /// Receives the next notification.
+    ///
+    /// Only messages sent by the kernel are parsed, as identified by the audit token of the
+    /// sender, since any task holding a send right to the port could forge them. Other messages
+    /// are returned as [`Notification::Unknown`].
+    pub fn next_notification(&self, buffer: &mut Buffer) -> Result<Notification, RecvError> {
+        let options = RecvOptions::new().request_trailer(TrailerRequest::Audit);
+        let msg = self.0.recv_with_options(buffer, options)?.parse_all();
+        let id = msg.header.id;
+
+        let from_kernel = msg
+            .header
+            .trailer
+            .audit_token()
+            .is_some_and(|token| token.val == [0; 8]);
+        if !from_kernel {
+            return Ok(Notification::Unknown(id));
+        }
+
+        // All the notifications carrying a value contain an NDR record followed by it.
+        let value = msg
+            .body
+            .get(NDR_SIZE..NDR_SIZE + 4)
+            .map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()));
+
+        let notification = match (id, value, msg.descriptors) {
+            (MACH_NOTIFY_PORT_DELETED, Some(name), _) => Notification::PortDeleted(name),
+            (MACH_NOTIFY_SEND_POSSIBLE, Some(name), _) => Notification::SendPossible(name),
+            (MACH_NOTIFY_NO_SENDERS, Some(count), _) => Notification::NoSenders(count),
+            (MACH_NOTIFY_DEAD_NAME, Some(name), _) if name != MACH_PORT_NULL => {
+                Notification::DeadName(SendRight::from_raw_name(name))
+            }
+            (MACH_NOTIFY_SEND_ONCE, _, _) => Notification::SendOnce,
+            (MACH_NOTIFY_PORT_DESTROYED, _, mut descriptors) => match descriptors.pop() {
+                Some(ParsedMsgDesc::PortRecv(right)) if descriptors.is_empty() => {
+                    Notification::PortDestroyed(right)
+                }
+                _ => Notification::Unknown(id),
+            },
+            _ => Notification::Unknown(id),
+        };
+
+        Ok(notification)
+    }
+}
+
+impl AsRawName for NotificationPort {
+    type Base = RecvRight;
+
+    #[inline(always)]
+    fn as_raw_name(&self) -> mach_port_t {
+        self.0.as_raw_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dead_name() {
+        let port = RecvRight::alloc();
+        let send_right = port.make_send();
+        let notify = NotificationPort::alloc();
+        let mut buffer = Buffer::with_capacity(1024);
+
+        notify.request_dead_name(&send_right).unwrap();
+        drop(port);
+
+        let Notification::DeadName(name) = notify.next_notification(&mut buffer).unwrap() else {
+            panic!("expected a dead-name notification");
+        };
+        assert_eq!(name.as_raw_name(), send_right.as_raw_name());
+        assert!(!name.is_alive());
+
+        // Dropping both dead name references releases the name.
+        drop(name);
+        drop(send_right);
+
+        // Requests for dead names are sent immediately.
+        let port = RecvRight::alloc();
+        let send_right = port.make_send();
+        drop(port);
+
+        notify.request_dead_name(&send_right).unwrap();
+        assert!(matches!(
+            notify.next_notification(&mut buffer).unwrap(),
+            Notification::DeadName(_)
+        ));
+    }
+
+    #[test]
+    fn test_no_senders_rearm() {
+        let port = RecvRight::alloc();
+        let notify = NotificationPort::alloc();
+        let mut buffer = Buffer::with_capacity(1024);
+
+        for count in 1..=2 {
+            let send_right = port.make_send();
+            notify.request_no_senders(&port, count).unwrap();
+            drop(send_right);
+
+            assert!(matches!(
+                notify.next_notification(&mut buffer).unwrap(),
+                Notification::NoSenders(c) if c == count
+            ));
+        }
+    }
+
+    #[test]
+    fn test_port_destroyed() {
+        let port = RecvRight::alloc();
+        let raw_name = port.as_raw_name();
+        let notify = NotificationPort::alloc();
+        let mut buffer = Buffer::with_capacity(1024);
+
+        notify.request_port_destroyed(&port).unwrap();
+        drop(port);
+
+        let Notification::PortDestroyed(right) = notify.next_notification(&mut buffer).unwrap()
+        else {
+            panic!("expected a port-destroyed notification");
+        };
+        assert_eq!(right.as_raw_name(), raw_name);
+    }
+
+    #[test]
+    fn test_forged_notification() {
+        let notify = NotificationPort::alloc();
+        let send_right = notify.recv_right().make_send();
+        let mut buffer = Buffer::with_capacity(1024);
+
+        let mut body = [0; NDR_SIZE + 4];
+        body[NDR_SIZE..].copy_from_slice(&send_right.as_raw_name().to_ne_bytes());
+        send_right
+            .send_inline(&mut buffer, MACH_NOTIFY_DEAD_NAME, &body)
+            .unwrap();
+
+        assert!(matches!(
+            notify.next_notification(&mut buffer).unwrap(),
+            Notification::Unknown(MACH_NOTIFY_DEAD_NAME)
+        ));
+        assert!(send_right.is_alive());
+    }
+}
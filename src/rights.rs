@@ -1,7 +1,8 @@
 //! Provides wrappers for Mach port right names.
 //!
 //! The module provides 3 types [`SendRight`], [`SendOnceRight`] and [`RecvRight`] that are wrappers
-//! for raw `mach_port_t` values (aka Mach port names).
+//! for raw `mach_port_t` values (aka Mach port names). Additionally, [`GuardedRecvRight`] wraps a
+//! receive right protected by a guard value and [`PortSet`] wraps a port set right.
 //!
 //! # Ownership
 //!
@@ -9,10 +10,40 @@
 //! port. That means when a value is dropped, the task loses a reference to the Mach port right
 //! represented by the wrapped name (through a call to `mach_port_mod_refs`). Additionally,
 //! [`SendRight`] wrappers can be cloned which increases the number of references to the port's
-//! send right.
+//! send right. [`SendRight::clone_n`] creates multiple clones at once adding all of the references
+//! in a single call.
+//!
+//! # Thread safety
+//! All wrappers are [`Send`] and [`Sync`]. Port names are shared by all threads of a task and the
+//! kernel serializes operations on the task's IPC space, so modifying reference counts (cloning
+//! and dropping a [`SendRight`]) from multiple threads is safe. Each wrapper owns its own
+//! reference, which means that a reference may not be released by one thread while being used by
+//! another.
+//!
+//! Concurrent calls to [`RecvRight::recv`] on the same right are also allowed: each message is
+//! dequeued by exactly one of the receiving threads. Every thread must use its own [`Buffer`],
+//! which is enforced by the receive functions taking a mutable reference to it.
+//!
+//! # Debug output
+//! The [`Debug`](std::fmt::Debug) implementations of the wrappers don't print the numeric port
+//! names to avoid leaking them into logs. Enable the `debug-port-names` feature to include the
+//! names.
+//!
+//! # Tracing
+//! Enable the `trace` feature to emit [`tracing`](https://docs.rs/tracing) events with the ID,
+//! the size and the descriptor count of every message sent and received through the wrappers, or
+//! the kind of the error in case the operation fails. Successful operations are reported at the
+//! `TRACE` level and failures at the `DEBUG` level.
 
 use crate::{
-    msg::{Buffer, Builder, MsgParser, RecvError, SendError},
+    msg::{
+        builder,
+        ool::{OolBuf, OolVec},
+        Buffer, Builder, CallError, FinishedMsg, MsgId, MsgParser, RecvBatchError, RecvError,
+        RecvErrorKind, RecvOptions, SendError, SendErrorKind,
+    },
+    notify::MACH_NOTIFY_SEND_POSSIBLE,
+    task::MACH_PORT_TYPE_DEAD_NAME,
     traits::*,
 };
 use mach2::{
@@ -20,13 +51,52 @@ use mach2::{
     mach_port,
     message::*,
     port::{
-        mach_port_delta_t, mach_port_right_t, mach_port_t, MACH_PORT_NULL,
-        MACH_PORT_RIGHT_DEAD_NAME, MACH_PORT_RIGHT_RECEIVE, MACH_PORT_RIGHT_SEND,
-        MACH_PORT_RIGHT_SEND_ONCE,
+        mach_port_delta_t, mach_port_options_t, mach_port_right_t, mach_port_t, MACH_PORT_NULL,
+        MACH_PORT_RIGHT_DEAD_NAME, MACH_PORT_RIGHT_PORT_SET, MACH_PORT_RIGHT_RECEIVE,
+        MACH_PORT_RIGHT_SEND, MACH_PORT_RIGHT_SEND_ONCE, MPO_CONTEXT_AS_GUARD,
     },
     traps,
+    vm_types::mach_port_context_t,
+};
+use std::{
+    fmt,
+    marker::PhantomData,
+    mem::{self, ManuallyDrop},
+    ops::{ControlFlow, Deref},
+    time::{Duration, Instant},
 };
-use std::mem::ManuallyDrop;
+
+extern "C" {
+    fn mach_port_move_member(
+        task: mach_port_t,
+        member: mach_port_t,
+        after: mach_port_t,
+    ) -> kern_return_t;
+    fn mach_port_insert_member(
+        task: mach_port_t,
+        name: mach_port_t,
+        pset: mach_port_t,
+    ) -> kern_return_t;
+    fn mach_port_extract_member(
+        task: mach_port_t,
+        name: mach_port_t,
+        pset: mach_port_t,
+    ) -> kern_return_t;
+    pub(crate) fn mach_port_request_notification(
+        task: mach_port_t,
+        name: mach_port_t,
+        msgid: MsgId,
+        sync: u32,
+        notify: mach_port_t,
+        notify_poly: mach_msg_type_name_t,
+        previous: *mut mach_port_t,
+    ) -> kern_return_t;
+    pub(crate) fn mach_port_type(
+        task: mach_port_t,
+        name: mach_port_t,
+        ptype: *mut u32,
+    ) -> kern_return_t;
+}
 
 fn mod_refs_wrapper(
     name: mach_port_t,
@@ -52,7 +122,173 @@ fn mod_refs_wrapper(
     result
 }
 
-fn send_impl(name: mach_port_t, msg: Builder, bits: mach_msg_bits_t) -> Result<(), SendError> {
+/// Returns `true` in case the name denotes a right other than a dead name.
+fn is_alive_name(name: mach_port_t) -> bool {
+    let mut ptype = 0;
+    let result = unsafe { mach_port_type(traps::mach_task_self(), name, &mut ptype) };
+
+    result == KERN_SUCCESS && ptype & MACH_PORT_TYPE_DEAD_NAME == 0
+}
+
+/// Formats a port name wrapper for the `Debug` implementations.
+fn fmt_name(f: &mut fmt::Formatter, type_name: &str, name: mach_port_t) -> fmt::Result {
+    if cfg!(feature = "debug-port-names") {
+        f.debug_tuple(type_name).field(&name).finish()
+    } else {
+        write!(f, "{}(<port>)", type_name)
+    }
+}
+
+#[cfg(feature = "trace")]
+fn trace_send(buffer: &Buffer, result: &Result<(), SendError>) {
+    let id = buffer.header().msgh_id;
+    let size = buffer.as_slice().len();
+    let descriptors = buffer.descriptors_count();
+
+    match result {
+        Ok(()) => tracing::trace!(id, size, descriptors, "sent a message"),
+        Err(error) => {
+            tracing::debug!(id, size, descriptors, error = ?error.kind(), "failed to send a message")
+        }
+    }
+}
+
+#[cfg(feature = "trace")]
+fn trace_recv(result: &Result<MsgParser<'_>, RecvError>) {
+    match result {
+        Ok(parser) => {
+            let buffer = parser.buffer();
+            let id = buffer.header().msgh_id;
+            let size = buffer.as_slice().len();
+            let descriptors = buffer.descriptors_count();
+
+            tracing::trace!(id, size, descriptors, "received a message")
+        }
+        Err(error) => tracing::debug!(error = ?error.kind(), "failed to receive a message"),
+    }
+}
+
+/// Returns the `mach_msg` options and the timeout value for receiving a message with an optional
+/// timeout.
+fn recv_timeout(
+    option: mach_msg_option_t,
+    timeout: Option<Duration>,
+) -> (mach_msg_option_t, mach_msg_timeout_t) {
+    match timeout {
+        // Timeouts that don't fit are rounded down to the maximal one.
+        Some(timeout) => (
+            option | MACH_RCV_TIMEOUT,
+            timeout
+                .as_millis()
+                .try_into()
+                .unwrap_or(mach_msg_timeout_t::MAX),
+        ),
+        None => (option, MACH_MSG_TIMEOUT_NONE),
+    }
+}
+
+/// Converts a `mach_msg` receive error into a [`RecvError`] destroying a partially received
+/// message.
+///
+/// On `MACH_RCV_BODY_ERROR` the header and a part of the body are received, so the rights and the
+/// memory the kernel has already placed into the task must be released. On `MACH_RCV_HEADER_ERROR`
+/// the kernel destroys the message itself.
+fn recv_error(buffer: &mut Buffer, result: mach_msg_return_t) -> RecvError {
+    let error = RecvError::from_bits(result);
+
+    if matches!(error.kind(), RecvErrorKind::BodyError) {
+        // SAFETY: The kernel has written a valid message header and body.
+        unsafe { mach_msg_destroy(buffer.header_mut()) };
+    }
+
+    error
+}
+
+/// Creates a parser for a message that has just been received into the buffer.
+///
+/// A message size in the header that doesn't fit into the buffer is reported as
+/// `MACH_RCV_TOO_LARGE`. The message can't be destroyed in this case since its extent is unknown.
+fn received(buffer: &mut Buffer, options: RecvOptions) -> Result<MsgParser<'_>, RecvError> {
+    MsgParser::new(buffer, options).map_err(|_| RecvError::from_kind(RecvErrorKind::TooLarge))
+}
+
+/// Receives a message on a port or a port set.
+fn recv_impl<'buffer>(
+    name: mach_port_t,
+    buffer: &'buffer mut Buffer,
+    options: RecvOptions,
+    timeout: Option<Duration>,
+) -> Result<MsgParser<'buffer>, RecvError> {
+    let (option, timeout) = recv_timeout(options.bits(), timeout);
+
+    let result = unsafe {
+        mach_msg(
+            buffer.as_recv_ptr(),
+            option,
+            0,
+            buffer.recv_size(),
+            name,
+            timeout,
+            MACH_PORT_NULL,
+        )
+    };
+
+    let result = if result == KERN_SUCCESS {
+        received(buffer, options)
+    } else {
+        Err(recv_error(buffer, result))
+    };
+
+    #[cfg(feature = "trace")]
+    trace_recv(&result);
+
+    result
+}
+
+/// Returns the size of the next message queued on a port or a port set without dequeuing it.
+fn peek_size_impl(name: mach_port_t, scratch: &mut Buffer) -> Result<usize, RecvError> {
+    // The receive limit is too small for any message so that the kernel always reports the
+    // size without dequeuing the message.
+    let result = unsafe {
+        mach_msg(
+            scratch.header_mut(),
+            MACH_RCV_MSG | MACH_RCV_LARGE | MACH_RCV_TIMEOUT,
+            0,
+            mem::size_of::<mach_msg_header_t>() as mach_msg_size_t,
+            name,
+            0,
+            MACH_PORT_NULL,
+        )
+    };
+
+    match result {
+        MACH_RCV_TOO_LARGE => Ok(scratch.header().msgh_size as usize),
+        KERN_SUCCESS => unreachable!("a message was received into a header-sized buffer"),
+        _ => Err(RecvError::from_bits(result)),
+    }
+}
+
+pub(crate) fn send_impl(
+    name: mach_port_t,
+    msg: Builder,
+    bits: mach_msg_bits_t,
+) -> Result<(), SendError> {
+    send_with_option(name, msg, bits, MACH_SEND_MSG)
+}
+
+fn send_with_option(
+    name: mach_port_t,
+    msg: Builder,
+    bits: mach_msg_bits_t,
+    option: mach_msg_option_t,
+) -> Result<(), SendError> {
+    // A wrapper may be created from any raw name, so reject the null name early instead of making
+    // a syscall the kernel would fail. Unlike a failed send, dropping the builder here releases the
+    // rights moved into the message.
+    if name == MACH_PORT_NULL {
+        return Err(SendError::from_kind(SendErrorKind::InvalidDest));
+    }
+
     let mut msg = ManuallyDrop::new(msg);
 
     msg.set_raw_remote_port(name, bits);
@@ -61,7 +297,7 @@ fn send_impl(name: mach_port_t, msg: Builder, bits: mach_msg_bits_t) -> Result<(
     let result = unsafe {
         mach_msg(
             data.as_ptr() as *mut mach_msg_header_t,
-            MACH_SEND_MSG,
+            option,
             data.len() as mach_msg_size_t,
             0,
             MACH_PORT_NULL,
@@ -70,16 +306,78 @@ fn send_impl(name: mach_port_t, msg: Builder, bits: mach_msg_bits_t) -> Result<(
         )
     };
 
-    if result == KERN_SUCCESS {
+    let result = if result == KERN_SUCCESS {
         Ok(())
     } else {
         Err(SendError::from_bits(result))
+    };
+
+    handle_send_result(msg, name, bits, result)
+}
+
+/// Handles the result of sending a message built by a builder, which is dropped in case the
+/// kernel didn't consume the message.
+fn handle_send_result(
+    mut msg: ManuallyDrop<Builder>,
+    name: mach_port_t,
+    bits: mach_msg_bits_t,
+    result: Result<(), SendError>,
+) -> Result<(), SendError> {
+    #[cfg(feature = "trace")]
+    trace_send(msg.buffer(), &result);
+
+    match result {
+        // The header is validated before copying in any rights, so none of the references moved
+        // into the message are consumed.
+        Err(error) if error.rights_not_consumed() => {
+            if bits == MACH_MSG_TYPE_MOVE_SEND_ONCE {
+                drop(SendOnceRight::from_raw_name(name));
+            }
+
+            msg.set_raw_remote_port(MACH_PORT_NULL, 0);
+            drop(ManuallyDrop::into_inner(msg));
+        }
+        // A message that timed out is pseudo-received back into the buffer, so the rights and the
+        // memory it carried are returned to the task and must be released.
+        Err(error) if matches!(error.kind(), SendErrorKind::TimedOut) => {
+            // SAFETY: The kernel has written a valid message into the buffer.
+            unsafe { mach_msg_destroy(msg.buffer_mut().header_mut()) };
+        }
+        _ => {}
     }
+
+    result
+}
+
+/// Allocates a new port returning the receive right and a send right to it.
+///
+/// This is a shorthand for [`RecvRight::alloc`] followed by [`RecvRight::make_send`]. Both rights
+/// share the same name. The wrappers may be dropped in any order: in case the receive right is
+/// dropped first, the send right becomes a dead name which is still released by the wrapper.
+///
+/// # Panics
+/// This function will panic in the same cases as [`RecvRight::alloc`].
+///
+/// # Example
+/// ```
+/// # use mach_ports::{msg::Buffer, rights};
+/// let (recv_right, send_right) = rights::port_pair();
+/// let mut buffer = Buffer::with_capacity(1024);
+/// send_right.send_inline(&mut buffer, 1337, b"ping").unwrap();
+///
+/// let msg = recv_right.recv(&mut buffer).unwrap().parse_all();
+/// assert_eq!(msg.header.id, 1337);
+/// assert_eq!(msg.body, b"ping");
+/// ```
+pub fn port_pair() -> (RecvRight, SendRight) {
+    let recv_right = RecvRight::alloc();
+    let send_right = recv_right.make_send();
+
+    (recv_right, send_right)
 }
 
 /// A wrapper for a Mach port name that holds a send right to a port.
 #[repr(transparent)]
-#[derive(Debug)]
 pub struct SendRight(mach_port_t);
 
 impl SendRight {
@@ -94,16 +392,285 @@ impl SendRight {
         mod_refs_wrapper(self.0, MACH_PORT_RIGHT_SEND, delta)
     }
 
+    /// Borrows the right without taking a reference.
+    #[inline(always)]
+    pub fn as_borrowed(&self) -> BorrowedRight<'_, SendRight> {
+        BorrowedRight::new(self.0)
+    }
+
     /// Sends a message built by a [`Builder`].
     ///
     /// This function is a safe wrapper around the `mach_msg` API.
     ///
+    /// # Errors
+    /// Returns the error returned by `mach_msg`. An error of the [`InvalidReply`] kind usually
+    /// means the reply port was made from a receive right released before sending, see
+    /// [`SendError::is_invalid_reply`].
+    ///
     /// # Port right references
     /// This method consumes all moved port right references that the message holds no matter if the
     /// message transfer is successful or not.
+    ///
+    /// [`InvalidReply`]: SendErrorKind::InvalidReply
     pub fn send(&self, msg: Builder) -> Result<(), SendError> {
         send_impl(self.0, msg, MACH_MSG_TYPE_COPY_SEND)
     }
+
+    /// Sends a finished message returning the number of bytes sent.
+    ///
+    /// The size includes the message header, the descriptors and the inline data, but not the
+    /// out-of-line data. See [`SendRight::send`] for details.
+    pub fn send_finished(&self, msg: FinishedMsg) -> Result<usize, SendError> {
+        let size = msg.as_slice().len();
+
+        msg.send(self).map(|()| size)
+    }
+
+    /// Sends a message built by a [`Builder`] without blocking and arms a send-possible
+    /// notification in case the message queue of the port is full.
+    ///
+    /// A send-possible request for the notify port is registered for the name before sending, the
+    /// request is then armed by the kernel during the send using `MACH_SEND_NOTIFY`. This way no
+    /// notification may be lost between a failed send and arming the request. The notify port
+    /// receives a `MACH_NOTIFY_SEND_POSSIBLE` message once the message may be sent, which should be
+    /// retried then.
+    ///
+    /// A request previously registered for the name is replaced, and the kernel sends a send-once
+    /// notification to its notify port as the send-once right of the request is released.
+    ///
+    /// # Errors
+    /// Returns an error of the [`TimedOut`] kind in case the queue is full and the notification is
+    /// armed. In case the request can't be registered, an error of the [`InvalidDest`] kind is
+    /// returned for invalid and dead names and of the [`InvalidNotify`] kind otherwise.
+    ///
+    /// # Port right references
+    /// This method consumes all moved port right references that the message holds no matter if the
+    /// message transfer is successful or not.
+    ///
+    /// [`TimedOut`]: SendErrorKind::TimedOut
+    /// [`InvalidDest`]: SendErrorKind::InvalidDest
+    /// [`InvalidNotify`]: SendErrorKind::InvalidNotify
+    pub fn send_or_arm(&self, msg: Builder, notify: &RecvRight) -> Result<(), SendError> {
+        let mut previous = MACH_PORT_NULL;
+        let result = unsafe {
+            mach_port_request_notification(
+                traps::mach_task_self(),
+                self.0,
+                MACH_NOTIFY_SEND_POSSIBLE,
+                // The request is only armed by sends using `MACH_SEND_NOTIFY`.
+                0,
+                notify.0,
+                MACH_MSG_TYPE_MAKE_SEND_ONCE,
+                &mut previous,
+            )
+        };
+
+        match result {
+            KERN_SUCCESS => {}
+            KERN_INVALID_NAME | KERN_INVALID_RIGHT => {
+                return Err(SendError::from_kind(SendErrorKind::InvalidDest))
+            }
+            _ => return Err(SendError::from_kind(SendErrorKind::InvalidNotify)),
+        }
+
+        if previous != MACH_PORT_NULL {
+            drop(SendOnceRight::from_raw_name(previous));
+        }
+
+        send_with_option(
+            self.0,
+            msg,
+            MACH_MSG_TYPE_COPY_SEND,
+            MACH_SEND_MSG | MACH_SEND_TIMEOUT | MACH_SEND_NOTIFY,
+        )
+    }
+
+    /// Builds a simple message containing only the specified ID and inline data in the buffer and
+    /// sends it.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::Buffer, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// # let mut buffer = Buffer::with_capacity(1024);
+    /// send_right.send_inline(&mut buffer, 1337, b"ping").unwrap();
+    /// ```
+    pub fn send_inline(
+        &self,
+        buffer: &mut Buffer,
+        id: MsgId,
+        data: &[u8],
+    ) -> Result<(), SendError> {
+        let mut builder = Builder::new(buffer);
+        builder.set_id(id);
+        builder.append_inline_data(data);
+
+        self.send(builder)
+    }
+
+    /// Sends a message consisting only of a header with the specified ID.
+    ///
+    /// This is the smallest possible message and may be used e.g. for liveness checks.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::Buffer, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// # let mut buffer = Buffer::with_capacity(1024);
+    /// send_right.ping(&mut buffer, 1337).unwrap();
+    /// ```
+    pub fn ping(&self, buffer: &mut Buffer, id: MsgId) -> Result<(), SendError> {
+        self.send_inline(buffer, id, &[])
+    }
+
+    /// Sends a request and waits for the reply to be received on the reply port.
+    ///
+    /// The reply port right should already be set in the request by the caller, e.g. using
+    /// [`Builder::set_made_reply_port`]. The reply is received into `reply_buffer` waiting for at
+    /// most `timeout` or indefinitely in case it's `None`.
+    ///
+    /// Both operations are performed by a single `mach_msg` call like MIG clients do. Since the
+    /// call uses a single buffer, the request is copied into the reply buffer first, which is
+    /// grown in case it's smaller than the request. The reply buffer should still be large enough
+    /// to hold the reply.
+    ///
+    /// # Errors
+    /// Returns [`CallError::Send`] in case the request couldn't be sent and [`CallError::Recv`] in
+    /// case the reply couldn't be received. The latter includes the timeout expiring.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder}, rights::RecvRight};
+    /// # use std::{thread, time::Duration};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// # thread::spawn(move || {
+    /// #     let mut buffer = Buffer::with_capacity(1024);
+    /// #     let parser = recv_right.recv(&mut buffer).unwrap();
+    /// #     let reply_right = parser.parse_header().0.reply_right.unwrap();
+    /// #     reply_right.reply(Builder::new(&mut buffer)).unwrap();
+    /// # });
+    /// let reply_right = RecvRight::alloc();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut reply_buffer = Buffer::with_capacity(1024);
+    ///
+    /// let mut request = Builder::new(&mut buffer);
+    /// request.set_made_reply_port(&reply_right, true);
+    ///
+    /// let timeout = Some(Duration::from_secs(1));
+    /// let reply = send_right.call(request, &reply_right, &mut reply_buffer, timeout).unwrap();
+    /// ```
+    pub fn call<'buffer>(
+        &self,
+        request: Builder,
+        reply: &RecvRight,
+        reply_buffer: &'buffer mut Buffer,
+        timeout: Option<Duration>,
+    ) -> Result<MsgParser<'buffer>, CallError> {
+        // See `send_impl`.
+        if self.0 == MACH_PORT_NULL {
+            return Err(SendError::from_kind(SendErrorKind::InvalidDest).into());
+        }
+
+        let mut request = ManuallyDrop::new(request);
+        request.set_raw_remote_port(self.0, MACH_MSG_TYPE_COPY_SEND);
+
+        // The request is sent and the reply is received by the same `mach_msg` call, which uses a
+        // single buffer for both. The request is copied into the reply buffer growing it in case
+        // it's smaller than the request. The references held by the request are transferred with
+        // the copy so the builder must not be dropped.
+        let send_size = request.as_slice().len() as mach_msg_size_t;
+        reply_buffer.set_contents(request.as_slice());
+
+        let (option, timeout) = recv_timeout(MACH_SEND_MSG | MACH_RCV_MSG, timeout);
+        let result = unsafe {
+            mach_msg(
+                reply_buffer.as_recv_ptr(),
+                option,
+                send_size,
+                reply_buffer.recv_size(),
+                reply.0,
+                timeout,
+                MACH_PORT_NULL,
+            )
+        };
+
+        let is_recv_error = result & !MACH_MSG_MASK >= MACH_RCV_IN_PROGRESS;
+        let send_result = if result == KERN_SUCCESS || is_recv_error {
+            Ok(())
+        } else {
+            Err(SendError::from_bits(result))
+        };
+
+        // The request still holds the same names and descriptors as its copy, so it's used for
+        // releasing the references in case the kernel didn't consume the message.
+        if let Err(error) =
+            handle_send_result(request, self.0, MACH_MSG_TYPE_COPY_SEND, send_result)
+        {
+            reply_buffer.clear();
+
+            return Err(error.into());
+        }
+
+        if result == KERN_SUCCESS {
+            Ok(received(reply_buffer, RecvOptions::new())?)
+        } else {
+            Err(recv_error(reply_buffer, result).into())
+        }
+    }
+
+    /// Creates `n` new wrappers for the send right adding all references with a single call to
+    /// `mach_port_mod_refs`.
+    ///
+    /// This is equivalent to cloning the wrapper `n` times, but avoids a syscall per clone. Each
+    /// returned wrapper owns one user reference, so the references are released one by one as the
+    /// wrappers are dropped.
+    ///
+    /// # Panics
+    /// This function will panic in case `n` exceeds `i32::MAX`, the largest delta accepted by
+    /// `mach_port_mod_refs`, or in case `mach_port_mod_refs` returns an error, which happens when
+    /// the user reference count would overflow.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::rights::RecvRight;
+    /// # let recv_right = RecvRight::alloc();
+    /// let send_right = recv_right.make_send();
+    /// let workers_rights = send_right.clone_n(4);
+    ///
+    /// assert_eq!(workers_rights.len(), 4);
+    /// ```
+    pub fn clone_n(&self, n: u32) -> Vec<SendRight> {
+        if n == 0 {
+            return vec![];
+        }
+
+        assert_eq!(self.mod_refs(n.try_into().unwrap()), KERN_SUCCESS);
+
+        (0..n).map(|_| SendRight(self.0)).collect()
+    }
+
+    /// Returns `true` unless the port has been destroyed and the right has become a dead name.
+    ///
+    /// This only queries the IPC space of the current task using `mach_port_type`, no message is
+    /// sent. The port may still die right after the check.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::rights::RecvRight;
+    /// let recv_right = RecvRight::alloc();
+    /// let send_right = recv_right.make_send();
+    /// assert!(send_right.is_alive());
+    ///
+    /// drop(recv_right);
+    /// assert!(!send_right.is_alive());
+    /// ```
+    #[inline]
+    pub fn is_alive(&self) -> bool {
+        is_alive_name(self.0)
+    }
 }
 
 impl Clone for SendRight {
@@ -116,9 +683,14 @@ impl Clone for SendRight {
 
     #[inline(always)]
     fn clone_from(&mut self, source: &Self) {
-        assert_eq!(self.mod_refs(1), KERN_SUCCESS);
+        // The reference held by self is released when the old value is dropped.
+        *self = source.clone();
+    }
+}
 
-        self.0 = source.0;
+impl fmt::Debug for SendRight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_name(f, "SendRight", self.0)
     }
 }
 
@@ -162,7 +734,6 @@ impl BaseSendRight for SendRight {}
 
 /// A wrapper for a Mach port name that holds a send once right to a port.
 #[repr(transparent)]
-#[derive(Debug)]
 pub struct SendOnceRight(mach_port_t);
 
 impl SendOnceRight {
@@ -177,6 +748,12 @@ impl SendOnceRight {
         mod_refs_wrapper(self.0, MACH_PORT_RIGHT_SEND_ONCE, delta)
     }
 
+    /// Borrows the right without taking a reference.
+    #[inline(always)]
+    pub fn as_borrowed(&self) -> BorrowedRight<'_, SendOnceRight> {
+        BorrowedRight::new(self.0)
+    }
+
     /// Sends a message built by a [`Builder`] and consumes the send once right.
     ///
     /// This function is a safe wrapper around the `mach_msg` API.
@@ -188,6 +765,19 @@ impl SendOnceRight {
         let name = ManuallyDrop::new(self);
         send_impl(name.0, msg, MACH_MSG_TYPE_MOVE_SEND_ONCE)
     }
+
+    /// Returns `true` unless the port has been destroyed and the right has become a dead name, see
+    /// [`SendRight::is_alive`].
+    #[inline]
+    pub fn is_alive(&self) -> bool {
+        is_alive_name(self.0)
+    }
+}
+
+impl fmt::Debug for SendOnceRight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_name(f, "SendOnceRight", self.0)
+    }
 }
 
 impl Drop for SendOnceRight {
@@ -230,7 +820,6 @@ impl BaseSendRight for SendOnceRight {}
 
 /// A wrapper for a Mach port name that holds a receive right to a port.
 #[repr(transparent)]
-#[derive(Debug)]
 pub struct RecvRight(mach_port_t);
 
 impl RecvRight {
@@ -241,6 +830,16 @@ impl RecvRight {
     /// either if the IPC space of the current task is exhausted or in case of a kernel resource
     /// shortage.
     pub fn alloc() -> Self {
+        Self::try_alloc().unwrap()
+    }
+
+    /// Allocates a new port and returns a receive right to the newly allocated port.
+    ///
+    /// # Errors
+    /// Returns the error code returned by `mach_port_allocate`. This may only happen either if the
+    /// IPC space of the current task is exhausted (`KERN_NO_SPACE`) or in case of a kernel
+    /// resource shortage (`KERN_RESOURCE_SHORTAGE`).
+    pub fn try_alloc() -> Result<Self, kern_return_t> {
         let mut raw_name = MACH_PORT_NULL;
         let result = unsafe {
             mach_port::mach_port_allocate(
@@ -250,10 +849,13 @@ impl RecvRight {
             )
         };
 
-        assert_eq!(result, KERN_SUCCESS);
-        assert_ne!(raw_name, MACH_PORT_NULL);
+        if result == KERN_SUCCESS {
+            assert_ne!(raw_name, MACH_PORT_NULL);
 
-        RecvRight::from_raw_name(raw_name)
+            Ok(RecvRight::from_raw_name(raw_name))
+        } else {
+            Err(result)
+        }
     }
 
     /// Creates a `RecvRight` wrapper from a raw `mach_port_t`.
@@ -265,10 +867,26 @@ impl RecvRight {
     /// Inserts a send right for the receive right into the current task and wraps the name into a
     /// [`SendRight`].
     ///
+    /// # Make-send count
+    /// The right is created using the `MACH_MSG_TYPE_MAKE_SEND` disposition, so every call
+    /// increments the make-send count of the port, which is used by the kernel to deliver
+    /// no-senders notifications. Use [`RecvRight::insert_send_right`] to add a reference on an
+    /// existing send right without affecting the count.
+    ///
     /// # Panics
     /// This function will panic in case `mach_port_insert_right` returns an error. This should only
     /// be possible on a user reference count overflow or a kernel resource shortage.
     pub fn make_send(&self) -> SendRight {
+        self.try_make_send().unwrap()
+    }
+
+    /// Inserts a send right for the receive right into the current task and wraps the name into a
+    /// [`SendRight`].
+    ///
+    /// # Errors
+    /// Returns the error code returned by `mach_port_insert_right`. This should only be possible
+    /// on a user reference count overflow (`KERN_UREFS_OVERFLOW`) or a kernel resource shortage.
+    pub fn try_make_send(&self) -> Result<SendRight, kern_return_t> {
         let raw_name = self.0;
         let result = unsafe {
             mach_port::mach_port_insert_right(
@@ -279,62 +897,370 @@ impl RecvRight {
             )
         };
 
-        assert_eq!(result, KERN_SUCCESS);
-
-        SendRight::from_raw_name(raw_name)
+        if result == KERN_SUCCESS {
+            Ok(SendRight::from_raw_name(raw_name))
+        } else {
+            Err(result)
+        }
     }
 
-    /// Receives a Mach message into the specified buffer.
-    pub fn recv<'buffer>(
-        &self,
-        buffer: &'buffer mut Buffer,
-    ) -> Result<MsgParser<'buffer>, RecvError> {
-        let data = buffer.as_slice();
+    /// Adds a reference on the send right the current task already holds for the port and wraps
+    /// the name into a [`SendRight`].
+    ///
+    /// Unlike [`RecvRight::make_send`], the right is inserted using the `MACH_MSG_TYPE_COPY_SEND`
+    /// disposition, so the make-send count of the port isn't incremented.
+    ///
+    /// # Errors
+    /// Returns the error code returned by `mach_port_insert_right`, e.g. in case the task doesn't
+    /// hold a send right for the port.
+    pub fn insert_send_right(&self) -> Result<SendRight, kern_return_t> {
+        let raw_name = self.0;
         let result = unsafe {
-            mach_msg(
-                data.as_ptr() as *mut mach_msg_header_t,
-                MACH_RCV_MSG,
-                0,
-                4096,
-                self.0,
-                0,
-                MACH_PORT_NULL,
+            mach_port::mach_port_insert_right(
+                traps::mach_task_self(),
+                raw_name,
+                raw_name,
+                MACH_MSG_TYPE_COPY_SEND,
             )
         };
 
         if result == KERN_SUCCESS {
-            Ok(MsgParser::new(buffer))
+            Ok(SendRight::from_raw_name(raw_name))
         } else {
-            Err(RecvError::from_bits(result))
+            Err(result)
         }
     }
 
-    #[inline(always)]
-    fn mod_refs(&self, delta: mach_port_delta_t) -> kern_return_t {
-        mod_refs_wrapper(self.0, MACH_PORT_RIGHT_RECEIVE, delta)
+    /// Receives a Mach message into the specified buffer.
+    pub fn recv<'buffer>(
+        &self,
+        buffer: &'buffer mut Buffer,
+    ) -> Result<MsgParser<'buffer>, RecvError> {
+        self.recv_with_options(buffer, RecvOptions::new())
     }
-}
 
-impl Drop for RecvRight {
-    #[inline(always)]
-    fn drop(&mut self) {
-        self.mod_refs(-1);
+    /// Receives a Mach message into the specified buffer using the specified options.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder, RecvOptions}, rights::RecvRight};
+    /// # use mach2::message::MACH_RCV_TRAILER_SEQNO;
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// send_right.send(Builder::new(&mut buffer)).unwrap();
+    ///
+    /// let options = RecvOptions::new().trailer(MACH_RCV_TRAILER_SEQNO);
+    /// let parser = recv_right.recv_with_options(&mut buffer, options).unwrap();
+    ///
+    /// assert!(parser.trailer().seqno().is_some());
+    /// ```
+    pub fn recv_with_options<'buffer>(
+        &self,
+        buffer: &'buffer mut Buffer,
+        options: RecvOptions,
+    ) -> Result<MsgParser<'buffer>, RecvError> {
+        recv_impl(self.0, buffer, options, None)
     }
-}
 
-impl AsRawName for RecvRight {
-    type Base = RecvRight;
+    /// Receives a Mach message into the specified buffer waiting until the deadline at most.
+    ///
+    /// The time remaining until the deadline is rounded down to milliseconds. A deadline that has
+    /// already passed makes the call non-blocking, so only a message that is already queued on the
+    /// port is received.
+    ///
+    /// # Errors
+    /// Returns an error of the [`TimedOut`] kind in case no message is received before the
+    /// deadline.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, RecvErrorKind}, rights::RecvRight};
+    /// # use std::time::{Duration, Instant};
+    /// # let recv_right = RecvRight::alloc();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let deadline = Instant::now() + Duration::from_millis(10);
+    ///
+    /// let error = recv_right.recv_deadline(&mut buffer, deadline).unwrap_err();
+    /// assert!(matches!(error.kind(), RecvErrorKind::TimedOut));
+    /// ```
+    ///
+    /// [`TimedOut`]: crate::msg::RecvErrorKind::TimedOut
+    pub fn recv_deadline<'buffer>(
+        &self,
+        buffer: &'buffer mut Buffer,
+        deadline: Instant,
+    ) -> Result<MsgParser<'buffer>, RecvError> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
 
-    #[inline(always)]
-    fn as_raw_name(&self) -> mach_port_t {
-        self.0
+        recv_impl(self.0, buffer, RecvOptions::new(), Some(timeout))
     }
-}
 
-impl<'a> AsRawName for &'a RecvRight {
-    type Base = RecvRight;
-
-    #[inline(always)]
+    /// Receives up to `max` messages that are already queued on the port without blocking, one
+    /// message into each of the buffers.
+    ///
+    /// The returned vector contains a parser for each received message in the order of the
+    /// buffers, so its length is the number of received messages. Receiving stops once the queue
+    /// is empty or `max` messages or as many messages as there are buffers are received.
+    ///
+    /// Parsers are returned instead of the number of received messages since a parser owns the
+    /// rights and the memory of its message and borrows the buffer it was received into. Parsers
+    /// can't be created for received buffers afterwards, so returning a count would leave the
+    /// messages without an owner.
+    ///
+    /// # Errors
+    /// Returns a [`RecvBatchError`] in case a message couldn't be received for reasons other than
+    /// the queue being empty. The error contains the messages received before the failure. Note
+    /// that a message that doesn't fit into its buffer is destroyed in this case as the error is of
+    /// the [`TooLarge`] kind.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::Buffer, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// send_right.ping(&mut buffer, 1).unwrap();
+    /// send_right.ping(&mut buffer, 2).unwrap();
+    ///
+    /// let mut buffers: Vec<_> = (0..4).map(|_| Buffer::with_capacity(1024)).collect();
+    /// let batch = recv_right.recv_batch(&mut buffers, 4).unwrap();
+    ///
+    /// let ids: Vec<_> = batch.into_iter().map(|msg| msg.parse_all().header.id).collect();
+    /// assert_eq!(ids, [1, 2]);
+    /// ```
+    ///
+    /// [`TooLarge`]: crate::msg::RecvErrorKind::TooLarge
+    pub fn recv_batch<'buffer>(
+        &self,
+        buffers: &'buffer mut [Buffer],
+        max: usize,
+    ) -> Result<Vec<MsgParser<'buffer>>, RecvBatchError<'buffer>> {
+        let mut batch = Vec::new();
+
+        for buffer in buffers.iter_mut().take(max) {
+            match recv_impl(self.0, buffer, RecvOptions::new(), Some(Duration::ZERO)) {
+                Ok(parser) => batch.push(parser),
+                // The queue is empty.
+                Err(error) if matches!(error.kind(), RecvErrorKind::TimedOut) => break,
+                Err(error) => return Err(RecvBatchError { batch, error }),
+            }
+        }
+
+        Ok(batch)
+    }
+
+    /// Receives a bulk data message built using [`Builder::set_bulk_ool`] returning its out-of-line
+    /// buffer.
+    ///
+    /// Returns `Ok(None)` in case the received message has a different layout, see
+    /// [`MsgParser::into_bulk_ool`].
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{builder::CopyKind, Buffer, Builder}, ool_vec, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.set_bulk_ool(ool_vec![1, 2, 3].into_buf(), CopyKind::Virtual);
+    /// send_right.send(builder).unwrap();
+    ///
+    /// let data = recv_right.recv_bulk_ool(&mut buffer).unwrap().unwrap();
+    /// assert_eq!(data, [1, 2, 3]);
+    /// ```
+    pub fn recv_bulk_ool(&self, buffer: &mut Buffer) -> Result<Option<OolBuf>, RecvError> {
+        Ok(self.recv(buffer)?.into_bulk_ool())
+    }
+
+    /// Receives a Mach message moving the data of its out-of-line descriptors into preallocated
+    /// regions.
+    ///
+    /// The data of the first out-of-line data descriptor is placed into the first region, the data
+    /// of the second one into the second region and so on, replacing the contents of the regions.
+    /// The returned parser yields empty buffers for these descriptors. Descriptors without a
+    /// matching region are received as usual.
+    ///
+    /// The buffer is filled with a scatter list of `MACH_MSG_OVERWRITE` descriptors describing the
+    /// regions before receiving. Current kernels ignore the scatter list since `MACH_RCV_OVERWRITE`
+    /// is deprecated and defined as zero, so the data mapped by the kernel is copied into the
+    /// regions and deallocated. Data written into a region in place by the kernel isn't copied.
+    ///
+    /// # Errors
+    /// Returns an error of the [`ScatterSmall`] kind in case the data doesn't fit into the capacity
+    /// of its region. The message is destroyed then, the regions preceding the one that is too
+    /// small already contain their data.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::msg::{builder::CopyKind, ool::OolVec, Buffer, Builder};
+    /// # use mach_ports::rights::RecvRight;
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_ool_data(b"data", CopyKind::Physical);
+    /// send_right.send(builder).unwrap();
+    ///
+    /// let mut regions = [OolVec::with_capacity(4096)];
+    /// recv_right.recv_scatter(&mut buffer, &mut regions).unwrap();
+    /// assert_eq!(regions[0], *b"data");
+    /// ```
+    ///
+    /// [`ScatterSmall`]: crate::msg::RecvErrorKind::ScatterSmall
+    pub fn recv_scatter<'buffer>(
+        &self,
+        buffer: &'buffer mut Buffer,
+        regions: &mut [OolVec],
+    ) -> Result<MsgParser<'buffer>, RecvError> {
+        builder::set_scatter_list(buffer, regions);
+
+        let options = RecvOptions::new();
+        let result = unsafe {
+            mach_msg(
+                buffer.as_recv_ptr(),
+                options.bits() | MACH_RCV_OVERWRITE,
+                0,
+                buffer.recv_size(),
+                self.0,
+                MACH_MSG_TIMEOUT_NONE,
+                MACH_PORT_NULL,
+            )
+        };
+
+        let result = if result == KERN_SUCCESS {
+            received(buffer, options).and_then(|mut parser| {
+                // Dropping the parser destroys the rest of the message.
+                if parser.scatter_ool(regions) {
+                    Ok(parser)
+                } else {
+                    Err(RecvError::from_kind(RecvErrorKind::ScatterSmall))
+                }
+            })
+        } else {
+            Err(recv_error(buffer, result))
+        };
+
+        #[cfg(feature = "trace")]
+        trace_recv(&result);
+
+        result
+    }
+
+    /// Receives a Mach message and releases the port rights and the out-of-line memory passed with
+    /// it without parsing it.
+    ///
+    /// This is cheaper than dropping a parser for draining messages nobody is interested in since
+    /// the resources are released by `mach_msg_destroy` without creating any wrappers.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder}, rights::RecvRight};
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// let mut builder = Builder::new(&mut buffer);
+    /// builder.append_copied_send_right(&send_right);
+    /// send_right.send(builder).unwrap();
+    ///
+    /// recv_right.recv_discard(&mut buffer).unwrap();
+    /// ```
+    pub fn recv_discard(&self, buffer: &mut Buffer) -> Result<(), RecvError> {
+        self.recv(buffer)?.destroy();
+        Ok(())
+    }
+
+    /// Receives messages into the buffer in a loop passing each one to the handler.
+    ///
+    /// The loop stops when the handler returns [`ControlFlow::Break`] or in case receiving a
+    /// message fails. The buffer is reused for every message, a message is dropped together with
+    /// its parser before the next one is received.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::{msg::{Buffer, Builder}, rights::RecvRight};
+    /// # use std::ops::ControlFlow;
+    /// # let recv_right = RecvRight::alloc();
+    /// # let send_right = recv_right.make_send();
+    /// let mut buffer = Buffer::with_capacity(1024);
+    /// send_right.send(Builder::new(&mut buffer)).unwrap();
+    ///
+    /// recv_right
+    ///     .recv_loop(&mut buffer, |parser| {
+    ///         let (_header, _parser) = parser.parse_header();
+    ///
+    ///         ControlFlow::Break(())
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn recv_loop<F>(&self, buffer: &mut Buffer, mut handler: F) -> Result<(), RecvError>
+    where
+        F: FnMut(MsgParser) -> ControlFlow<()>,
+    {
+        loop {
+            // The kernel overwrites the header and the parser updates the buffer length on every
+            // reception, so nothing is left from the previous message.
+            let parser = self.recv(buffer)?;
+
+            if handler(parser).is_break() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns the size of the next message queued on the port without dequeuing it.
+    ///
+    /// The size includes the message header and the minimal trailer, so a buffer created using
+    /// [`Buffer::with_capacity`] with the returned size minus the header size can hold the message.
+    /// Only the header of the scratch buffer is overwritten.
+    ///
+    /// # Errors
+    /// This method doesn't block and returns an error of the [`TimedOut`] kind in case there are
+    /// no messages queued on the port.
+    ///
+    /// [`TimedOut`]: crate::msg::RecvErrorKind::TimedOut
+    pub fn peek_size(&self, scratch: &mut Buffer) -> Result<usize, RecvError> {
+        peek_size_impl(self.0, scratch)
+    }
+
+    #[inline(always)]
+    fn mod_refs(&self, delta: mach_port_delta_t) -> kern_return_t {
+        mod_refs_wrapper(self.0, MACH_PORT_RIGHT_RECEIVE, delta)
+    }
+
+    /// Borrows the right without taking a reference.
+    #[inline(always)]
+    pub fn as_borrowed(&self) -> BorrowedRight<'_, RecvRight> {
+        BorrowedRight::new(self.0)
+    }
+}
+
+impl fmt::Debug for RecvRight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_name(f, "RecvRight", self.0)
+    }
+}
+
+impl Drop for RecvRight {
+    #[inline(always)]
+    fn drop(&mut self) {
+        self.mod_refs(-1);
+    }
+}
+
+impl AsRawName for RecvRight {
+    type Base = RecvRight;
+
+    #[inline(always)]
+    fn as_raw_name(&self) -> mach_port_t {
+        self.0
+    }
+}
+
+impl<'a> AsRawName for &'a RecvRight {
+    type Base = RecvRight;
+
+    #[inline(always)]
     fn as_raw_name(&self) -> mach_port_t {
         self.0
     }
@@ -351,45 +1277,1240 @@ impl BaseRight for RecvRight {
     const MSG_TYPE: mach_port_right_t = MACH_MSG_TYPE_MOVE_RECEIVE;
 }
 
-/// An enum for all available send rights.
-#[derive(Debug)]
-pub enum AnySendRight {
-    /// A send right.
-    Send(SendRight),
-    /// A send once right.
-    SendOnce(SendOnceRight),
+/// A wrapper for a Mach port name that holds a receive right to a port guarded by a context value.
+///
+/// A guarded receive right can't be destroyed or moved in a message without specifying the guard,
+/// doing so raises a guard exception in the current task. The wrapper keeps the guard and
+/// destroys the right using `mach_port_destruct`. Guarded rights may be moved in messages using
+/// [`Builder::append_moved_guarded_recv_right`].
+///
+/// The wrapper dereferences to a [`RecvRight`] to allow receiving messages and creating send
+/// rights.
+pub struct GuardedRecvRight {
+    right: ManuallyDrop<RecvRight>,
+    guard: mach_port_context_t,
 }
 
-impl From<SendRight> for AnySendRight {
-    #[inline]
-    fn from(right: SendRight) -> Self {
-        AnySendRight::Send(right)
+impl GuardedRecvRight {
+    /// Allocates a new port and returns a receive right to the port guarded by the specified value.
+    ///
+    /// # Panics
+    /// This function will panic in case `mach_port_construct` returns an error. This may only
+    /// happen either if the IPC space of the current task is exhausted or in case of a kernel
+    /// resource shortage.
+    pub fn alloc(guard: mach_port_context_t) -> Self {
+        let mut options = mach_port_options_t {
+            flags: MPO_CONTEXT_AS_GUARD,
+            ..Default::default()
+        };
+        let mut raw_name = MACH_PORT_NULL;
+        let result = unsafe {
+            mach_port::mach_port_construct(
+                traps::mach_task_self(),
+                &mut options,
+                guard,
+                &mut raw_name,
+            )
+        };
+
+        assert_eq!(result, KERN_SUCCESS);
+        assert_ne!(raw_name, MACH_PORT_NULL);
+
+        // SAFETY: The port has just been constructed with the guard.
+        unsafe { Self::from_raw_parts(raw_name, guard) }
+    }
+
+    /// Creates a `GuardedRecvRight` wrapper from a raw `mach_port_t` and a guard value.
+    ///
+    /// # Safety
+    /// The caller must ensure the name represents a receive right guarded by the specified value.
+    /// Dropping the wrapper with a wrong guard raises a guard exception.
+    #[inline(always)]
+    pub unsafe fn from_raw_parts(name: mach_port_t, guard: mach_port_context_t) -> Self {
+        Self {
+            right: ManuallyDrop::new(RecvRight::from_raw_name(name)),
+            guard,
+        }
+    }
+
+    /// Returns the guard value of the receive right.
+    #[inline(always)]
+    pub fn guard(&self) -> mach_port_context_t {
+        self.guard
+    }
+
+    /// Converts the wrapper into a raw name and a guard value passing the ownership of the right
+    /// to the caller.
+    #[inline(always)]
+    pub fn into_raw_parts(self) -> (mach_port_t, mach_port_context_t) {
+        let this = ManuallyDrop::new(self);
+
+        (this.right.0, this.guard)
     }
 }
 
-impl From<SendOnceRight> for AnySendRight {
-    #[inline]
-    fn from(right: SendOnceRight) -> Self {
-        AnySendRight::SendOnce(right)
+impl Deref for GuardedRecvRight {
+    type Target = RecvRight;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.right
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl fmt::Debug for GuardedRecvRight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_name(f, "GuardedRecvRight", self.right.0)
+    }
+}
 
-    #[test]
-    fn test_drop() {
-        // the right order
-        let recv_right = RecvRight::alloc();
-        let send_right = recv_right.make_send();
-        drop(send_right);
-        drop(recv_right);
+impl Drop for GuardedRecvRight {
+    #[inline(always)]
+    fn drop(&mut self) {
+        unsafe {
+            mach_port::mach_port_destruct(traps::mach_task_self(), self.right.0, 0, self.guard);
+        }
+    }
+}
 
-        // the reverse order, will cause KERN_INVALID_RIGHT to be returned during the drop
-        let recv_right = RecvRight::alloc();
-        let send_right = recv_right.make_send();
-        drop(recv_right);
-        drop(send_right);
+impl AsRawName for GuardedRecvRight {
+    type Base = RecvRight;
+
+    #[inline(always)]
+    fn as_raw_name(&self) -> mach_port_t {
+        self.right.0
+    }
+}
+
+impl AsRawName for &GuardedRecvRight {
+    type Base = RecvRight;
+
+    #[inline(always)]
+    fn as_raw_name(&self) -> mach_port_t {
+        self.right.0
+    }
+}
+
+/// A wrapper for a Mach port name that holds a port set right.
+///
+/// Messages sent to any of the member ports may be received through the set. Port sets can't be
+/// transferred in messages.
+///
+/// # Membership
+/// A receive right may be a member of multiple port sets at the same time. [`PortSet::insert`]
+/// uses `mach_port_move_member` that **replaces** all existing memberships of the port with the
+/// set, while [`PortSet::insert_keeping_existing`] uses `mach_port_insert_member` that adds the set
+/// to the existing ones.
+#[repr(transparent)]
+pub struct PortSet(mach_port_t);
+
+impl PortSet {
+    /// Allocates a new empty port set.
+    ///
+    /// # Panics
+    /// This function will panic in case `mach_port_allocate` returns an error. This may only happen
+    /// either if the IPC space of the current task is exhausted or in case of a kernel resource
+    /// shortage.
+    pub fn alloc() -> Self {
+        Self::try_alloc().unwrap()
+    }
+
+    /// Allocates a new empty port set.
+    ///
+    /// # Errors
+    /// Returns the error code returned by `mach_port_allocate`. This may only happen either if the
+    /// IPC space of the current task is exhausted (`KERN_NO_SPACE`) or in case of a kernel
+    /// resource shortage (`KERN_RESOURCE_SHORTAGE`).
+    pub fn try_alloc() -> Result<Self, kern_return_t> {
+        let mut raw_name = MACH_PORT_NULL;
+        let result = unsafe {
+            mach_port::mach_port_allocate(
+                traps::mach_task_self(),
+                MACH_PORT_RIGHT_PORT_SET,
+                &mut raw_name,
+            )
+        };
+
+        if result == KERN_SUCCESS {
+            assert_ne!(raw_name, MACH_PORT_NULL);
+
+            Ok(PortSet::from_raw_name(raw_name))
+        } else {
+            Err(result)
+        }
+    }
+
+    /// Creates a `PortSet` wrapper from a raw `mach_port_t`.
+    #[inline(always)]
+    pub fn from_raw_name(name: mach_port_t) -> Self {
+        PortSet(name)
+    }
+
+    /// Makes the port a member of the set removing it from all other sets it belongs to.
+    ///
+    /// # Errors
+    /// Returns the error code returned by `mach_port_move_member`.
+    pub fn insert<T: AsRawName<Base = RecvRight>>(&self, right: &T) -> Result<(), kern_return_t> {
+        let result =
+            unsafe { mach_port_move_member(traps::mach_task_self(), right.as_raw_name(), self.0) };
+
+        if result == KERN_SUCCESS {
+            Ok(())
+        } else {
+            Err(result)
+        }
+    }
+
+    /// Makes the port a member of the set keeping its membership in other sets.
+    ///
+    /// # Errors
+    /// Returns the error code returned by `mach_port_insert_member`, `KERN_ALREADY_IN_SET` in case
+    /// the port is already a member of this set.
+    pub fn insert_keeping_existing<T: AsRawName<Base = RecvRight>>(
+        &self,
+        right: &T,
+    ) -> Result<(), kern_return_t> {
+        let result = unsafe {
+            mach_port_insert_member(traps::mach_task_self(), right.as_raw_name(), self.0)
+        };
+
+        if result == KERN_SUCCESS {
+            Ok(())
+        } else {
+            Err(result)
+        }
+    }
+
+    /// Removes the port from the set.
+    ///
+    /// # Errors
+    /// Returns the error code returned by `mach_port_extract_member`, `KERN_NOT_IN_SET` in case the
+    /// port is not a member of this set.
+    pub fn remove<T: AsRawName<Base = RecvRight>>(&self, right: &T) -> Result<(), kern_return_t> {
+        let result = unsafe {
+            mach_port_extract_member(traps::mach_task_self(), right.as_raw_name(), self.0)
+        };
+
+        if result == KERN_SUCCESS {
+            Ok(())
+        } else {
+            Err(result)
+        }
+    }
+
+    /// Temporarily removes the port from the set, e.g. to receive on it exclusively.
+    ///
+    /// The returned guard inserts the port back into the set when dropped, keeping the other
+    /// memberships of the port intact.
+    ///
+    /// # Errors
+    /// Returns the error code returned by `mach_port_extract_member`, `KERN_NOT_IN_SET` in case the
+    /// port is not a member of this set.
+    ///
+    /// # Example
+    /// ```
+    /// # use mach_ports::rights::{PortSet, RecvRight};
+    /// let set = PortSet::alloc();
+    /// let recv_right = RecvRight::alloc();
+    /// set.insert(&recv_right).unwrap();
+    ///
+    /// let taken = set.take(&recv_right).unwrap();
+    /// // Messages sent to the port may only be received using `taken` here.
+    /// drop(taken);
+    /// ```
+    pub fn take<'a, T: AsRawName<Base = RecvRight>>(
+        &'a self,
+        right: &'a T,
+    ) -> Result<PortSetMembership<'a, T>, kern_return_t> {
+        self.remove(right)?;
+
+        Ok(PortSetMembership { set: self, right })
+    }
+
+    /// Receives a Mach message sent to any of the member ports into the specified buffer.
+    pub fn recv<'buffer>(
+        &self,
+        buffer: &'buffer mut Buffer,
+    ) -> Result<MsgParser<'buffer>, RecvError> {
+        self.recv_with_options(buffer, RecvOptions::new())
+    }
+
+    /// Receives a Mach message sent to any of the member ports into the specified buffer using the
+    /// specified options.
+    pub fn recv_with_options<'buffer>(
+        &self,
+        buffer: &'buffer mut Buffer,
+        options: RecvOptions,
+    ) -> Result<MsgParser<'buffer>, RecvError> {
+        recv_impl(self.0, buffer, options, None)
+    }
+
+    /// Returns the size of the next message queued on any of the member ports without dequeuing
+    /// it.
+    ///
+    /// See [`RecvRight::peek_size`] for details.
+    pub fn peek_size(&self, scratch: &mut Buffer) -> Result<usize, RecvError> {
+        peek_size_impl(self.0, scratch)
+    }
+
+    /// Converts the wrapper into a raw name passing the ownership of the right to the caller.
+    #[inline(always)]
+    pub fn into_raw_name(self) -> mach_port_t {
+        ManuallyDrop::new(self).0
+    }
+}
+
+impl fmt::Debug for PortSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_name(f, "PortSet", self.0)
+    }
+}
+
+impl Drop for PortSet {
+    #[inline(always)]
+    fn drop(&mut self) {
+        mod_refs_wrapper(self.0, MACH_PORT_RIGHT_PORT_SET, -1);
+    }
+}
+
+/// A guard returned by [`PortSet::take`] that inserts the port back into the set when dropped.
+///
+/// The guard dereferences to the taken right.
+#[derive(Debug)]
+pub struct PortSetMembership<'a, T: AsRawName<Base = RecvRight>> {
+    set: &'a PortSet,
+    right: &'a T,
+}
+
+impl<T: AsRawName<Base = RecvRight>> Deref for PortSetMembership<'_, T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        self.right
+    }
+}
+
+impl<T: AsRawName<Base = RecvRight>> Drop for PortSetMembership<'_, T> {
+    fn drop(&mut self) {
+        // Fails only with KERN_ALREADY_IN_SET in case the port was inserted into the set again
+        // while taken, so the set has the port as a member either way.
+        let _ = self.set.insert_keeping_existing(self.right);
+    }
+}
+
+/// A non-owning view of a port right borrowed from one of the wrappers.
+///
+/// This is the Mach port name counterpart of [`BorrowedFd`](std::os::fd::BorrowedFd): it holds no
+/// reference and doesn't alter reference counts on drop, while the lifetime ties it to the owning
+/// wrapper, so the name is guaranteed to stay valid. The type parameter specifies the kind of the
+/// borrowed right.
+///
+/// # Example
+/// ```
+/// # use mach_ports::{msg::{Buffer, Builder}, rights::RecvRight};
+/// # let recv_right = RecvRight::alloc();
+/// let send_right = recv_right.make_send();
+/// let borrowed = send_right.as_borrowed();
+///
+/// let mut buffer = Buffer::with_capacity(1024);
+/// let mut builder = Builder::new(&mut buffer);
+/// builder.append_copied_send_right(&borrowed);
+/// ```
+pub struct BorrowedRight<'a, B: BaseRight> {
+    name: mach_port_t,
+    _marker: PhantomData<&'a B>,
+}
+
+impl<'a, B: BaseRight> BorrowedRight<'a, B> {
+    #[inline(always)]
+    fn new(name: mach_port_t) -> Self {
+        Self {
+            name,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<B: BaseRight> Clone for BorrowedRight<'_, B> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<B: BaseRight> Copy for BorrowedRight<'_, B> {}
+
+impl<B: BaseRight> fmt::Debug for BorrowedRight<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_name(f, "BorrowedRight", self.name)
+    }
+}
+
+impl<B: BaseRight> AsRawName for BorrowedRight<'_, B> {
+    type Base = B;
+
+    #[inline(always)]
+    fn as_raw_name(&self) -> mach_port_t {
+        self.name
+    }
+}
+
+/// An enum for all available send rights.
+#[derive(Debug)]
+pub enum AnySendRight {
+    /// A send right.
+    Send(SendRight),
+    /// A send once right.
+    SendOnce(SendOnceRight),
+}
+
+impl AnySendRight {
+    /// Sends a reply message using the right consuming it.
+    ///
+    /// Consuming the right makes it impossible to use a send once right more than once:
+    ///
+    /// ```compile_fail,E0382
+    /// # use mach_ports::{msg::{Buffer, Builder}, rights::{AnySendRight, RecvRight}};
+    /// # fn reply_twice(reply_right: AnySendRight, buffer: &mut Buffer) {
+    /// reply_right.reply(Builder::new(buffer)).unwrap();
+    /// reply_right.reply(Builder::new(buffer)).unwrap();
+    /// # }
+    /// ```
+    ///
+    /// # Port right references
+    /// This method consumes all moved port right references that the message holds no matter if the
+    /// message transfer is successful or not.
+    pub fn reply(self, msg: Builder) -> Result<(), SendError> {
+        match self {
+            AnySendRight::Send(right) => right.send(msg),
+            AnySendRight::SendOnce(right) => right.send(msg),
+        }
+    }
+}
+
+impl From<SendRight> for AnySendRight {
+    #[inline]
+    fn from(right: SendRight) -> Self {
+        AnySendRight::Send(right)
+    }
+}
+
+impl From<SendOnceRight> for AnySendRight {
+    #[inline]
+    fn from(right: SendOnceRight) -> Self {
+        AnySendRight::SendOnce(right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::{builder::CopyKind, DescOrBodyParser, ParsedMsg, ParsedMsgDesc};
+    use crate::test_util::send_refs;
+
+    #[test]
+    fn test_drop() {
+        // the right order
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        drop(send_right);
+        drop(recv_right);
+
+        // the reverse order, will cause KERN_INVALID_RIGHT to be returned during the drop
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        drop(recv_right);
+        drop(send_right);
+    }
+
+    #[test]
+    fn test_clone_n() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        assert_eq!(send_refs(send_right.as_raw_name()), Some(1));
+
+        let clones = send_right.clone_n(8);
+        assert_eq!(clones.len(), 8);
+        assert_eq!(send_refs(send_right.as_raw_name()), Some(9));
+
+        drop(clones);
+        assert_eq!(send_refs(send_right.as_raw_name()), Some(1));
+
+        assert!(send_right.clone_n(0).is_empty());
+        assert_eq!(send_refs(send_right.as_raw_name()), Some(1));
+    }
+
+    #[test]
+    fn test_borrowed_right() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let borrowed = send_right.as_borrowed();
+        let borrowed_recv = recv_right.as_borrowed();
+        assert_eq!(borrowed.as_raw_name(), send_right.as_raw_name());
+
+        let mut buffer = Buffer::with_capacity(1024);
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_copied_send_right(&borrowed);
+        builder.append_made_send_right(&borrowed_recv, true);
+        send_right.send(builder).unwrap();
+
+        let parser = recv_right.recv(&mut buffer).unwrap();
+        let (_, parser) = parser.parse_header();
+        let DescOrBodyParser::Descriptor(parser) = parser else {
+            panic!("expected a descriptor");
+        };
+
+        // The copied right must name the same port as the borrowed one.
+        let (ParsedMsgDesc::PortSend(received), _) = parser.next() else {
+            panic!("expected a send right");
+        };
+        assert_eq!(received.as_raw_name(), send_right.as_raw_name());
+    }
+
+    #[test]
+    fn test_insert_send_right() {
+        extern "C" {
+            fn mach_port_get_attributes(
+                task: mach_port_t,
+                name: mach_port_t,
+                flavor: i32,
+                info: *mut u32,
+                count: *mut u32,
+            ) -> kern_return_t;
+        }
+
+        // Returns the mps_mscount field of the mach_port_status_t structure.
+        let make_send_count = |right: &RecvRight| {
+            const MACH_PORT_RECEIVE_STATUS: i32 = 2;
+
+            let mut status = [0u32; 10];
+            let mut count = status.len() as u32;
+            let result = unsafe {
+                mach_port_get_attributes(
+                    traps::mach_task_self(),
+                    right.as_raw_name(),
+                    MACH_PORT_RECEIVE_STATUS,
+                    status.as_mut_ptr(),
+                    &mut count,
+                )
+            };
+            assert_eq!(result, KERN_SUCCESS);
+
+            status[2]
+        };
+
+        let recv_right = RecvRight::alloc();
+        assert!(recv_right.insert_send_right().is_err());
+        assert_eq!(make_send_count(&recv_right), 0);
+
+        let first = recv_right.make_send();
+        let second = recv_right.make_send();
+        assert_eq!(make_send_count(&recv_right), 2);
+
+        let third = recv_right.insert_send_right().unwrap();
+        assert_eq!(make_send_count(&recv_right), 2);
+        assert_eq!(third.as_raw_name(), first.as_raw_name());
+
+        drop((first, second, third));
+        assert!(recv_right.insert_send_right().is_err());
+    }
+
+    #[test]
+    fn test_try_alloc() {
+        let recv_right = RecvRight::try_alloc().unwrap();
+        let send_right = recv_right.try_make_send().unwrap();
+
+        assert_eq!(recv_right.as_raw_name(), send_right.as_raw_name());
+
+        // A dead name can't be used to make a send right.
+        let dead_name = recv_right.into_raw_name();
+        unsafe {
+            mach_port::mach_port_mod_refs(
+                traps::mach_task_self(),
+                dead_name,
+                MACH_PORT_RIGHT_RECEIVE,
+                -1,
+            );
+        }
+
+        let error = ManuallyDrop::new(RecvRight::from_raw_name(dead_name))
+            .try_make_send()
+            .unwrap_err();
+        assert_ne!(error, KERN_SUCCESS);
+    }
+
+    #[test]
+    fn test_reply() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let reply_recv_right = RecvRight::alloc();
+        let mut buffer = Buffer::with_capacity(1024);
+
+        for once in [true, false] {
+            let mut builder = Builder::new(&mut buffer);
+            builder.set_made_reply_port(&reply_recv_right, once);
+            send_right.send(builder).unwrap();
+
+            let parser = recv_right.recv(&mut buffer).unwrap();
+            let reply_right = parser.parse_header().0.reply_right.unwrap();
+
+            let mut builder = Builder::new(&mut buffer);
+            builder.set_id(1337);
+            reply_right.reply(builder).unwrap();
+
+            let parser = reply_recv_right.recv(&mut buffer).unwrap();
+            assert_eq!(parser.parse_header().0.id, 1337);
+        }
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<SendRight>();
+        assert_send_sync::<SendOnceRight>();
+        assert_send_sync::<RecvRight>();
+        assert_send_sync::<GuardedRecvRight>();
+        assert_send_sync::<AnySendRight>();
+    }
+
+    #[test]
+    fn test_concurrent_recv() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let mut buffer = Buffer::with_capacity(1024);
+
+        for id in 0..2 {
+            let mut builder = Builder::new(&mut buffer);
+            builder.set_id(id);
+            send_right.send(builder).unwrap();
+        }
+
+        // Each message must be dequeued by exactly one thread.
+        let mut ids: Vec<MsgId> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut buffer = Buffer::with_capacity(1024);
+                        let parser = recv_right.recv(&mut buffer).unwrap();
+                        let (header, _) = parser.parse_header();
+
+                        header.id
+                    })
+                })
+                .collect();
+
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+
+        ids.sort();
+        assert_eq!(ids, [0, 1]);
+    }
+
+    #[test]
+    fn test_ping() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let mut buffer = Buffer::with_capacity(1024);
+
+        send_right.ping(&mut buffer, 1337).unwrap();
+
+        let msg = recv_right.recv(&mut buffer).unwrap().parse_all();
+        assert_eq!(msg.header.id, 1337);
+        assert!(msg.descriptors.is_empty());
+        assert!(msg.body.is_empty());
+
+        assert_eq!(buffer.descriptors_count(), 0);
+        assert_eq!(
+            buffer.header().msgh_size as usize,
+            mem::size_of::<mach_msg_header_t>()
+        );
+    }
+
+    #[test]
+    fn test_call() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let reply_recv_right = RecvRight::alloc();
+
+        // An echo server replying with the ID and the body of the request.
+        let server = std::thread::spawn(move || {
+            let mut buffer = Buffer::with_capacity(1024);
+
+            for _ in 0..2 {
+                let parser = recv_right.recv(&mut buffer).unwrap();
+                let ParsedMsg { header, body, .. } = parser.parse_all();
+
+                let mut builder = Builder::new(&mut buffer);
+                builder.set_id(header.id);
+                builder.append_inline_data(&body);
+                header.reply_right.unwrap().reply(builder).unwrap();
+            }
+        });
+
+        let mut buffer = Buffer::with_capacity(1024);
+        let mut reply_buffer = Buffer::with_capacity(1024);
+        let mut request = Builder::new(&mut buffer);
+        request.set_id(1337);
+        request.set_made_reply_port(&reply_recv_right, true);
+        request.append_inline_data(b"ping");
+
+        let reply = send_right
+            .call(request, &reply_recv_right, &mut reply_buffer, None)
+            .unwrap()
+            .parse_all();
+
+        assert_eq!(reply.header.id, 1337);
+        assert_eq!(reply.body, b"ping");
+
+        // The same exchange performed using two separate calls.
+        let mut request = Builder::new(&mut buffer);
+        request.set_id(1337);
+        request.set_made_reply_port(&reply_recv_right, true);
+        request.append_inline_data(b"ping");
+        send_right.send(request).unwrap();
+
+        let expected = reply_recv_right
+            .recv(&mut reply_buffer)
+            .unwrap()
+            .parse_all();
+        assert_eq!(reply.header.id, expected.header.id);
+        assert_eq!(reply.body, expected.body);
+        assert!(reply.descriptors.is_empty() && expected.descriptors.is_empty());
+        server.join().unwrap();
+
+        // Nobody replies to requests sent to this port.
+        let idle_right = RecvRight::alloc();
+        let mut request = Builder::new(&mut buffer);
+        request.set_made_reply_port(&reply_recv_right, true);
+        let timeout = Some(Duration::from_millis(10));
+        let error = idle_right
+            .make_send()
+            .call(request, &reply_recv_right, &mut reply_buffer, timeout)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            CallError::Recv(error) if matches!(error.kind(), RecvErrorKind::TimedOut)
+        ));
+    }
+
+    #[test]
+    fn test_clone_from() {
+        let recv_right = RecvRight::alloc();
+        let other_recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let mut other_send_right = other_recv_right.make_send();
+
+        other_send_right.clone_from(&send_right);
+        assert_eq!(other_send_right.as_raw_name(), send_right.as_raw_name());
+
+        // The only send right to the other port must have been released.
+        let result = unsafe {
+            mach_port::mach_port_mod_refs(
+                traps::mach_task_self(),
+                other_recv_right.as_raw_name(),
+                MACH_PORT_RIGHT_SEND,
+                0,
+            )
+        };
+        assert_ne!(result, KERN_SUCCESS);
+    }
+
+    #[test]
+    fn test_send_null_dest() {
+        let mut buffer = Buffer::with_capacity(1024);
+        let recv_right = RecvRight::alloc();
+        let raw_name = recv_right.as_raw_name();
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_moved_right(recv_right);
+
+        let error = SendRight::from_raw_name(MACH_PORT_NULL)
+            .send(builder)
+            .unwrap_err();
+        assert!(matches!(error.kind(), SendErrorKind::InvalidDest));
+
+        // The moved right must be released together with the rejected message.
+        let result = unsafe {
+            mach_port::mach_port_mod_refs(
+                traps::mach_task_self(),
+                raw_name,
+                MACH_PORT_RIGHT_RECEIVE,
+                0,
+            )
+        };
+        assert_ne!(result, KERN_SUCCESS);
+
+        let error = SendOnceRight::from_raw_name(MACH_PORT_NULL)
+            .send(Builder::new(&mut buffer))
+            .unwrap_err();
+        assert!(matches!(error.kind(), SendErrorKind::InvalidDest));
+    }
+
+    #[test]
+    fn test_recv_loop() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let mut buffer = Buffer::with_capacity(1024);
+
+        for id in 1..=4 {
+            let mut builder = Builder::new(&mut buffer);
+            builder.set_id(id);
+            builder.append_inline_data(&[id as u8; 4]);
+            send_right.send(builder).unwrap();
+        }
+
+        let mut ids = vec![];
+        recv_right
+            .recv_loop(&mut buffer, |parser| {
+                let (header, _) = parser.parse_header();
+                ids.push(header.id);
+
+                if ids.len() == 3 {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })
+            .unwrap();
+
+        assert_eq!(ids, [1, 2, 3]);
+
+        // The fourth message must still be queued.
+        let parser = recv_right.recv(&mut buffer).unwrap();
+        assert_eq!(parser.parse_header().0.id, 4);
+    }
+
+    #[test]
+    fn test_peek_size() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let mut buffer = Buffer::with_capacity(0);
+
+        let error = recv_right.peek_size(&mut buffer).unwrap_err();
+        assert!(matches!(error.kind(), RecvErrorKind::TimedOut));
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_inline_data(&[0xAA; 8192]);
+        send_right.send(builder).unwrap();
+
+        let size = recv_right.peek_size(&mut buffer).unwrap();
+        let msg_size = mem::size_of::<mach_msg_header_t>() + 8192;
+        assert!(size >= msg_size + mem::size_of::<mach_msg_trailer_t>());
+
+        // The message must still be queued.
+        assert_eq!(recv_right.peek_size(&mut buffer).unwrap(), size);
+    }
+
+    #[test]
+    fn test_port_set_membership() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let set_a = PortSet::alloc();
+        let set_b = PortSet::alloc();
+        let mut buffer = Buffer::with_capacity(64);
+
+        send_right.ping(&mut buffer, 1).unwrap();
+        let is_member = |set: &PortSet, buffer: &mut Buffer| set.peek_size(buffer).is_ok();
+
+        set_a.insert(&recv_right).unwrap();
+        assert!(is_member(&set_a, &mut buffer));
+        assert!(!is_member(&set_b, &mut buffer));
+
+        // Inserting moves the port out of the previous set.
+        set_b.insert(&recv_right).unwrap();
+        assert!(!is_member(&set_a, &mut buffer));
+        assert!(is_member(&set_b, &mut buffer));
+
+        set_a.insert_keeping_existing(&recv_right).unwrap();
+        assert!(is_member(&set_a, &mut buffer));
+        assert!(is_member(&set_b, &mut buffer));
+        assert_eq!(
+            set_a.insert_keeping_existing(&recv_right).unwrap_err(),
+            KERN_ALREADY_IN_SET
+        );
+
+        set_b.remove(&recv_right).unwrap();
+        assert!(!is_member(&set_b, &mut buffer));
+        assert_eq!(set_b.remove(&recv_right).unwrap_err(), KERN_NOT_IN_SET);
+
+        let (header, _) = set_a.recv(&mut buffer).unwrap().parse_header();
+        assert_eq!(header.id, 1);
+        assert!(!is_member(&set_a, &mut buffer));
+    }
+
+    #[test]
+    fn test_port_set_take() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let other_set = PortSet::alloc();
+        let set = PortSet::alloc();
+        let mut buffer = Buffer::with_capacity(64);
+
+        set.insert(&recv_right).unwrap();
+        other_set.insert_keeping_existing(&recv_right).unwrap();
+        send_right.ping(&mut buffer, 1).unwrap();
+
+        let taken = set.take(&recv_right).unwrap();
+        assert!(set.peek_size(&mut buffer).is_err());
+        assert!(other_set.peek_size(&mut buffer).is_ok());
+        assert_eq!(set.take(&recv_right).unwrap_err(), KERN_NOT_IN_SET);
+
+        let (header, _) = taken.recv(&mut buffer).unwrap().parse_header();
+        assert_eq!(header.id, 1);
+        drop(taken);
+
+        send_right.ping(&mut buffer, 2).unwrap();
+        assert!(other_set.peek_size(&mut buffer).is_ok());
+        let (header, _) = set.recv(&mut buffer).unwrap().parse_header();
+        assert_eq!(header.id, 2);
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn test_trace_send() {
+        use std::{
+            collections::HashMap,
+            sync::{Arc, Mutex},
+        };
+        use tracing::{
+            field::{Field, Visit},
+            span, Event, Metadata, Subscriber,
+        };
+
+        type Fields = HashMap<&'static str, String>;
+
+        struct FieldsVisitor<'a>(&'a mut Fields);
+
+        impl Visit for FieldsVisitor<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                self.0.insert(field.name(), format!("{:?}", value));
+            }
+        }
+
+        #[derive(Clone, Default)]
+        struct EventsSubscriber(Arc<Mutex<Vec<Fields>>>);
+
+        impl Subscriber for EventsSubscriber {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+                span::Id::from_u64(1)
+            }
+
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = Fields::new();
+                event.record(&mut FieldsVisitor(&mut fields));
+                self.0.lock().unwrap().push(fields);
+            }
+
+            fn enter(&self, _: &span::Id) {}
+
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let mut buffer = Buffer::with_capacity(64);
+
+        let subscriber = EventsSubscriber::default();
+        tracing::subscriber::with_default(subscriber.clone(), || {
+            send_right.send_inline(&mut buffer, 1337, b"test").unwrap();
+        });
+
+        let events = subscriber.0.lock().unwrap();
+        let [fields] = &events[..] else {
+            panic!("expected a single event, got {:?}", events);
+        };
+        assert_eq!(fields["message"], "sent a message");
+        assert_eq!(fields["id"], "1337");
+        assert_eq!(fields["size"], "28");
+        assert_eq!(fields["descriptors"], "0");
+    }
+
+    #[test]
+    fn test_recv_deadline() {
+        let right = RecvRight::alloc();
+        let send_right = right.make_send();
+        let mut buffer = Buffer::with_capacity(1024);
+
+        let start = Instant::now();
+        let deadline = start - Duration::from_millis(1);
+        let error = right.recv_deadline(&mut buffer, deadline).unwrap_err();
+        assert!(matches!(error.kind(), RecvErrorKind::TimedOut));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // Messages that are already queued are still received.
+        send_right.ping(&mut buffer, 1337).unwrap();
+        let parser = right.recv_deadline(&mut buffer, deadline).unwrap();
+        assert_eq!(parser.parse_all().header.id, 1337);
+    }
+
+    #[test]
+    fn test_recv_batch() {
+        let right = RecvRight::alloc();
+        let send_right = right.make_send();
+        let mut buffer = Buffer::with_capacity(1024);
+        let mut buffers: Vec<_> = (0..8).map(|_| Buffer::with_capacity(1024)).collect();
+
+        assert!(right.recv_batch(&mut buffers, 8).unwrap().is_empty());
+
+        for id in 0..5 {
+            send_right.ping(&mut buffer, id).unwrap();
+        }
+
+        let batch = right.recv_batch(&mut buffers, 8).unwrap();
+        let ids: Vec<_> = batch
+            .into_iter()
+            .map(|parser| parser.parse_all().header.id)
+            .collect();
+        assert_eq!(ids, [0, 1, 2, 3, 4]);
+
+        // The batch is limited by both the maximum and the number of buffers.
+        for id in 0..5 {
+            send_right.ping(&mut buffer, id).unwrap();
+        }
+        assert_eq!(right.recv_batch(&mut buffers, 2).unwrap().len(), 2);
+        assert_eq!(right.recv_batch(&mut buffers[..1], 8).unwrap().len(), 1);
+        assert_eq!(right.recv_batch(&mut buffers, 8).unwrap().len(), 2);
+
+        // A failure returns the messages received before it.
+        send_right.ping(&mut buffer, 1).unwrap();
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_inline_data(&[0; 2048]);
+        send_right.send(builder).unwrap();
+        send_right.ping(&mut buffer, 2).unwrap();
+
+        let RecvBatchError { batch, error } = right.recv_batch(&mut buffers, 8).unwrap_err();
+        assert!(matches!(error.kind(), RecvErrorKind::TooLarge));
+        let ids: Vec<_> = batch
+            .into_iter()
+            .map(|parser| parser.parse_all().header.id)
+            .collect();
+        assert_eq!(ids, [1]);
+
+        let batch = right.recv_batch(&mut buffers, 8).unwrap();
+        assert_eq!(batch.len(), 1);
+    }
+
+    #[test]
+    fn test_recv_discard() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let other = RecvRight::alloc();
+        let other_send = other.make_send();
+        let mut buffer = Buffer::with_capacity(1024);
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_copied_send_right(&other_send);
+        builder.append_ool_data(b"test", CopyKind::Physical);
+        builder.append_inline_data(b"test");
+        send_right.send(builder).unwrap();
+
+        // The reference received with the message is released right away.
+        recv_right.recv_discard(&mut buffer).unwrap();
+        assert_eq!(send_refs(other_send.as_raw_name()), Some(1));
+        assert!(recv_right.peek_size(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_is_alive() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let mut buffer = Buffer::with_capacity(1024);
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_made_send_right(&recv_right, true);
+        send_right.send(builder).unwrap();
+
+        let parsed = recv_right.recv(&mut buffer).unwrap().parse_all();
+        let [ParsedMsgDesc::PortSendOnce(send_once_right)] = &parsed.descriptors[..] else {
+            panic!("expected a send once right");
+        };
+        assert!(send_right.is_alive());
+        assert!(send_once_right.is_alive());
+
+        drop(recv_right);
+        assert!(!send_right.is_alive());
+        assert!(!send_once_right.is_alive());
+    }
+
+    #[test]
+    fn test_recv_capacity() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let data = vec![0x5a; 8192];
+
+        let mut buffer = Buffer::with_capacity(16384);
+        send_right.send_inline(&mut buffer, 1337, &data).unwrap();
+
+        let msg = recv_right.recv(&mut buffer).unwrap().parse_all();
+        assert_eq!(msg.header.id, 1337);
+        assert_eq!(msg.body, data);
+    }
+
+    #[test]
+    fn test_recv_after_clear() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let data: Vec<u8> = (0..2048).map(|i| i as u8).collect();
+
+        let mut buffer = Buffer::with_capacity(4096);
+        send_right.send_inline(&mut buffer, 1337, &data).unwrap();
+        buffer.clear();
+        assert_eq!(buffer.as_slice().len(), mem::size_of::<mach_msg_header_t>());
+
+        let msg = recv_right.recv(&mut buffer).unwrap().parse_all();
+        assert_eq!(msg.body, data);
+    }
+
+    #[test]
+    fn test_send_invalid_reply() {
+        let right = RecvRight::alloc();
+        let send_right = right.make_send();
+        let carried = RecvRight::alloc();
+        let carried_send = carried.make_send();
+
+        // The name of the released receive right no longer denotes any right.
+        let reply = RecvRight::alloc();
+        let released = BorrowedRight::<RecvRight>::new(reply.as_raw_name());
+        drop(reply);
+        let mut buffer = Buffer::with_capacity(1024);
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.set_made_reply_port(&released, true);
+        builder.append_moved_right(carried_send.clone());
+        assert_eq!(send_refs(carried_send.as_raw_name()), Some(2));
+
+        let error = send_right.send(builder).unwrap_err();
+        assert!(error.is_invalid_reply());
+        assert_eq!(send_refs(carried_send.as_raw_name()), Some(1));
+    }
+
+    #[test]
+    fn test_send_dead_dest() {
+        let carried = RecvRight::alloc();
+        let carried_send = carried.make_send();
+        let dead_name = {
+            let port = RecvRight::alloc();
+            let send_right = port.make_send();
+            drop(port);
+            send_right
+        };
+        let mut buffer = Buffer::with_capacity(1024);
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_moved_right(carried_send.clone());
+        assert_eq!(send_refs(carried_send.as_raw_name()), Some(2));
+
+        let error = dead_name.send(builder).unwrap_err();
+        assert!(matches!(error.kind(), SendErrorKind::InvalidDest));
+        assert_eq!(send_refs(carried_send.as_raw_name()), Some(1));
+    }
+
+    #[test]
+    fn test_call_invalid_reply() {
+        let right = RecvRight::alloc();
+        let send_right = right.make_send();
+        let carried = RecvRight::alloc();
+        let carried_send = carried.make_send();
+
+        let reply = RecvRight::alloc();
+        let released = BorrowedRight::<RecvRight>::new(reply.as_raw_name());
+        drop(reply);
+        let mut buffer = Buffer::with_capacity(1024);
+        // The request is copied into the reply buffer growing it.
+        let mut reply_buffer = Buffer::with_capacity(16);
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.set_made_reply_port(&released, true);
+        builder.append_moved_right(carried_send.clone());
+        builder.append_inline_data(&[0; 20]);
+        assert_eq!(send_refs(carried_send.as_raw_name()), Some(2));
+
+        let error = send_right
+            .call(builder, &right, &mut reply_buffer, None)
+            .unwrap_err();
+        assert!(matches!(error, CallError::Send(error) if error.is_invalid_reply()));
+        assert_eq!(send_refs(carried_send.as_raw_name()), Some(1));
+    }
+
+    #[test]
+    fn test_send_finished() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let mut buffer = Buffer::with_capacity(1024);
+
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_inline_data(b"testtest");
+        let msg = builder.finish();
+        let size = msg.as_slice().len();
+
+        assert_eq!(send_right.send_finished(msg), Ok(size));
+        assert_eq!(size, mem::size_of::<mach_msg_header_t>() + 8);
+
+        let parser = recv_right.recv(&mut buffer).unwrap();
+        assert_eq!(parser.into_raw_bytes().len(), size);
+    }
+
+    #[test]
+    fn test_send_or_arm() {
+        const MACH_PORT_QLIMIT_DEFAULT: usize = 5;
+
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+        let notify = RecvRight::alloc();
+        let mut buffer = Buffer::with_capacity(64);
+
+        for _ in 0..MACH_PORT_QLIMIT_DEFAULT {
+            send_right.send(Builder::new(&mut buffer)).unwrap();
+        }
+
+        let carried = RecvRight::alloc();
+        let carried_send = carried.make_send();
+        let mut builder = Builder::new(&mut buffer);
+        builder.append_moved_right(carried_send.clone());
+        assert_eq!(send_refs(carried_send.as_raw_name()), Some(2));
+
+        let error = send_right.send_or_arm(builder, &notify).unwrap_err();
+        assert!(matches!(error.kind(), SendErrorKind::TimedOut));
+        assert!(notify.peek_size(&mut buffer).is_err());
+        // The moved reference returned by the kernel is released.
+        assert_eq!(send_refs(carried_send.as_raw_name()), Some(1));
+
+        drop(recv_right.recv(&mut buffer).unwrap());
+
+        let (header, _) = notify.recv(&mut buffer).unwrap().parse_header();
+        assert_eq!(header.id, MACH_NOTIFY_SEND_POSSIBLE);
+    }
+
+    #[test]
+    #[cfg(not(feature = "debug-port-names"))]
+    fn test_debug_redacted() {
+        let recv_right = RecvRight::alloc();
+        let send_right = recv_right.make_send();
+
+        assert_eq!(format!("{:?}", send_right), "SendRight(<port>)");
+        assert_eq!(format!("{:?}", recv_right), "RecvRight(<port>)");
+        assert_eq!(
+            format!("{:?}", AnySendRight::from(send_right)),
+            "Send(SendRight(<port>))"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "debug-port-names")]
+    fn test_debug_port_names() {
+        let recv_right = RecvRight::alloc();
+        let name = recv_right.as_raw_name();
+
+        assert_eq!(format!("{:?}", recv_right), format!("RecvRight({})", name));
     }
 }
@@ -0,0 +1,141 @@
+//! Provides functions querying the IPC space of the current task.
+//!
+//! These are useful to find out whether a raw port name, e.g. received from a foreign API, denotes
+//! a right the task already owns before wrapping or allocating one.
+
+use crate::rights::mach_port_type;
+use mach2::{
+    kern_return::{kern_return_t, KERN_INVALID_NAME, KERN_SUCCESS},
+    port::{
+        mach_port_right_t, mach_port_t, MACH_PORT_RIGHT_DEAD_NAME, MACH_PORT_RIGHT_PORT_SET,
+        MACH_PORT_RIGHT_RECEIVE, MACH_PORT_RIGHT_SEND, MACH_PORT_RIGHT_SEND_ONCE,
+    },
+    traps,
+};
+
+/// The `MACH_PORT_TYPE` macro from `mach/port.h`, not provided by mach2.
+const fn port_type(right: mach_port_right_t) -> u32 {
+    1 << (right + 16)
+}
+
+const MACH_PORT_TYPE_SEND: u32 = port_type(MACH_PORT_RIGHT_SEND);
+const MACH_PORT_TYPE_RECEIVE: u32 = port_type(MACH_PORT_RIGHT_RECEIVE);
+const MACH_PORT_TYPE_SEND_ONCE: u32 = port_type(MACH_PORT_RIGHT_SEND_ONCE);
+const MACH_PORT_TYPE_PORT_SET: u32 = port_type(MACH_PORT_RIGHT_PORT_SET);
+pub(crate) const MACH_PORT_TYPE_DEAD_NAME: u32 = port_type(MACH_PORT_RIGHT_DEAD_NAME);
+
+/// The types of rights the current task holds for a name as reported by `mach_port_type`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PortRights(u32);
+
+impl PortRights {
+    /// Returns the raw `mach_port_type_t` value.
+    #[inline(always)]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns `true` in case the task holds the receive right for the name.
+    #[inline(always)]
+    pub const fn receive(self) -> bool {
+        self.0 & MACH_PORT_TYPE_RECEIVE != 0
+    }
+
+    /// Returns `true` in case the task holds send right references for the name.
+    #[inline(always)]
+    pub const fn send(self) -> bool {
+        self.0 & MACH_PORT_TYPE_SEND != 0
+    }
+
+    /// Returns `true` in case the name denotes a send-once right.
+    #[inline(always)]
+    pub const fn send_once(self) -> bool {
+        self.0 & MACH_PORT_TYPE_SEND_ONCE != 0
+    }
+
+    /// Returns `true` in case the name denotes a port set.
+    #[inline(always)]
+    pub const fn port_set(self) -> bool {
+        self.0 & MACH_PORT_TYPE_PORT_SET != 0
+    }
+
+    /// Returns `true` in case the name is a dead name.
+    #[inline(always)]
+    pub const fn dead_name(self) -> bool {
+        self.0 & MACH_PORT_TYPE_DEAD_NAME != 0
+    }
+}
+
+/// Returns the types of rights the current task holds for the name.
+///
+/// The rights may change right after the call in case the port dies or another thread of the task
+/// releases them.
+///
+/// # Errors
+/// Returns the error code returned by `mach_port_type`, e.g. `KERN_INVALID_NAME` in case the name
+/// doesn't denote any right in the IPC space of the task.
+///
+/// # Example
+/// ```
+/// # use mach_ports::{rights::RecvRight, task, traits::AsRawName};
+/// let recv_right = RecvRight::alloc();
+/// let send_right = recv_right.make_send();
+///
+/// let rights = task::rights_for(send_right.as_raw_name()).unwrap();
+/// assert!(rights.receive() && rights.send());
+/// assert!(!rights.send_once() && !rights.port_set() && !rights.dead_name());
+/// ```
+pub fn rights_for(name: mach_port_t) -> Result<PortRights, kern_return_t> {
+    let mut ptype = 0;
+    let result = unsafe { mach_port_type(traps::mach_task_self(), name, &mut ptype) };
+
+    if result == KERN_SUCCESS {
+        Ok(PortRights(ptype))
+    } else {
+        Err(result)
+    }
+}
+
+/// Returns `true` in case the current task holds the receive right for the name.
+///
+/// Names that don't denote any right in the IPC space of the task are reported as `false`.
+///
+/// # Errors
+/// Returns the error code returned by `mach_port_type` other than `KERN_INVALID_NAME`.
+pub fn holds_receive(name: mach_port_t) -> Result<bool, kern_return_t> {
+    match rights_for(name) {
+        Ok(rights) => Ok(rights.receive()),
+        Err(KERN_INVALID_NAME) => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rights::RecvRight, traits::AsRawName};
+    use mach2::port::MACH_PORT_NULL;
+
+    #[test]
+    fn test_holds_receive() {
+        let recv_right = RecvRight::alloc();
+        assert_eq!(holds_receive(recv_right.as_raw_name()), Ok(true));
+
+        // The send right turns into a dead name once the receive right is released.
+        let dead_name = {
+            let port = RecvRight::alloc();
+            let send_right = port.make_send();
+            drop(port);
+            send_right
+        };
+        assert_eq!(holds_receive(dead_name.as_raw_name()), Ok(false));
+        assert!(rights_for(dead_name.as_raw_name()).unwrap().dead_name());
+
+        assert_eq!(holds_receive(MACH_PORT_NULL), Ok(false));
+
+        let raw_name = recv_right.as_raw_name();
+        drop(recv_right);
+        assert_eq!(holds_receive(raw_name), Ok(false));
+        assert_eq!(rights_for(raw_name), Err(KERN_INVALID_NAME));
+    }
+}
@@ -0,0 +1,35 @@
+//! Helpers shared by the tests of multiple modules.
+
+use mach2::{
+    kern_return::{kern_return_t, KERN_SUCCESS},
+    port::{mach_port_name_t, mach_port_right_t, MACH_PORT_RIGHT_RECEIVE, MACH_PORT_RIGHT_SEND},
+    traps,
+};
+
+extern "C" {
+    fn mach_port_get_refs(
+        task: mach_port_name_t,
+        name: mach_port_name_t,
+        right: mach_port_right_t,
+        refs: *mut u32,
+    ) -> kern_return_t;
+}
+
+/// Returns the user reference count of a right of the specified type or `None` in case the name
+/// doesn't denote such a right.
+pub(crate) fn refs(name: mach_port_name_t, right: mach_port_right_t) -> Option<u32> {
+    let mut refs = 0;
+    let result = unsafe { mach_port_get_refs(traps::mach_task_self(), name, right, &mut refs) };
+
+    (result == KERN_SUCCESS && refs > 0).then_some(refs)
+}
+
+/// Returns the send right user reference count of a name.
+pub(crate) fn send_refs(name: mach_port_name_t) -> Option<u32> {
+    refs(name, MACH_PORT_RIGHT_SEND)
+}
+
+/// Returns the receive right reference count of a name, which is at most one.
+pub(crate) fn recv_refs(name: mach_port_name_t) -> Option<u32> {
+    refs(name, MACH_PORT_RIGHT_RECEIVE)
+}